@@ -592,6 +592,11 @@ rec {
             path = "src/main.rs";
             requiredFeatures = [ ];
           }
+          {
+            name = "fake_create";
+            path = "src/bin/fake_create/main.rs";
+            requiredFeatures = [ ];
+          }
         ];
         src = lib.cleanSourceWith { filter = sourceFilter;  src = ./.; };
         dependencies = [
@@ -603,6 +608,10 @@ rec {
             name = "futures";
             packageId = "futures";
           }
+          {
+            name = "libc";
+            packageId = "libc";
+          }
           {
             name = "log";
             packageId = "log";
@@ -629,6 +638,13 @@ rec {
             packageId = "tokio-serial";
           }
         ];
+        devDependencies = [
+          {
+            name = "tokio";
+            packageId = "tokio";
+            features = [ "full" "test-util" ];
+          }
+        ];
 
       };
       "crossbeam-deque" = rec {
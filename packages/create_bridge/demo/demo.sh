@@ -1,22 +1,20 @@
 #!/usr/bin/env bash
 
-ros2 topic pub --once /create_bridge/drive/straight std_msgs/msg/Int16 'data: -100'
+# The bridge starts the robot in passive mode, and the motions need control of it.
+ros2 topic pub --once /create_bridge/mode create_bridge_interface/msg/OIMode '{mode: 2}'
 
-sleep 1s
+# The motions need the encoder counts, and the bumpers and cliff sensors so they can stop early.
+ros2 topic pub --once /create_bridge/sensor/start_stream create_bridge_interface/msg/SensorQuery \
+  '{bumpers_and_wheel_drops: true, cliff_left: true, cliff_front_left: true, cliff_front_right: true, cliff_right: true, left_encoder_counts: true, right_encoder_counts: true}'
 
-# ros2 topic pub --once /drive/straight std_msgs/msg/Int16 'data: 0'
-ros2 topic pub --once /create_bridge/drive/left std_msgs/msg/Int16 'data: 188'
+ros2 action send_goal /create_bridge/drive_distance create_bridge_interface/action/DriveDistance '{distance: -0.1, speed: 0.1}'
+ros2 action send_goal /create_bridge/rotate_angle create_bridge_interface/action/RotateAngle '{angle: 3.14159}'
 
-sleep 2s
-
-ros2 topic pub --once /create_bridge/drive/straight std_msgs/msg/Int16 'data: 0'
 ros2 topic pub --once /create_bridge/display_text std_msgs/msg/String 'data: "make"'
 
 sleep 2s
 
 ros2 topic pub --once /create_bridge/display_text std_msgs/msg/String 'data: ""'
-ros2 topic pub --once /create_bridge/drive/right std_msgs/msg/Int16 'data: 188'
-
-sleep 2s
+ros2 action send_goal /create_bridge/rotate_angle create_bridge_interface/action/RotateAngle '{angle: -3.14159}'
 
 ros2 topic pub --once /create_bridge/dock std_msgs/msg/Empty
@@ -20,7 +20,11 @@
         };
 	messages = [
           pkgs.rosPackages.humble.ros-core
+          pkgs.rosPackages.humble.diagnostic-msgs
           pkgs.rosPackages.humble.geometry-msgs
+          pkgs.rosPackages.humble.nav-msgs
+          pkgs.rosPackages.humble.sensor-msgs
+          pkgs.rosPackages.humble.tf2-msgs
           (pkgs.callPackage ../create_bridge_interface { })
 	];
         build_dependencies = [
@@ -0,0 +1,337 @@
+//! Collects the battery readings scattered across the sensor packets into one place.
+
+use num_enum::IntoPrimitive;
+
+use crate::roomba_interface::{ChargingState, SensorData};
+
+/// How far the state of charge has to climb back past a threshold before it counts as recovered,
+/// so a reading hovering around it doesn't keep raising the alarm.
+const RECOVERY_MARGIN: f32 = 0.05;
+
+/// Everything the Roomba tells us about its battery, in SI units.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BatteryReading {
+    /// In volts.
+    pub voltage: f32,
+
+    /// In amps. Negative while discharging.
+    pub current: f32,
+
+    /// In amp hours.
+    pub charge: f32,
+
+    /// In amp hours.
+    pub capacity: f32,
+
+    /// In degrees Celsius, if it has ever been read.
+    pub temperature: Option<f32>,
+
+    /// If it has ever been read.
+    pub charging_state: Option<ChargingState>,
+}
+
+impl BatteryReading {
+    /// How full the battery is, from 0 to 1.
+    pub fn percentage(&self) -> f32 {
+        if self.capacity > 0.0 {
+            (self.charge / self.capacity).clamp(0.0, 1.0)
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Feed in sensor data as it arrives, and get a complete reading back once voltage, current,
+/// charge and capacity have all been refreshed. Temperature and charging state are optional, since
+/// the rest of the reading is still useful without them, so the latest known values of those are
+/// attached instead.
+#[derive(Debug, Default)]
+pub struct BatteryMonitor {
+    voltage: Option<u16>,
+    current: Option<i16>,
+    charge: Option<u16>,
+    capacity: Option<u16>,
+    temperature: Option<i8>,
+    charging_state: Option<ChargingState>,
+}
+
+impl BatteryMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, data: &SensorData) -> Option<BatteryReading> {
+        match data {
+            SensorData::Voltage(voltage) => self.voltage = Some(*voltage),
+            SensorData::Current(current) => self.current = Some(*current),
+            SensorData::BatteryCharge(charge) => self.charge = Some(*charge),
+            SensorData::BatteryCapacity(capacity) => self.capacity = Some(*capacity),
+            SensorData::BatteryTemperature(temperature) => {
+                self.temperature = Some(*temperature);
+                return None;
+            }
+            SensorData::ChargingState(charging_state) => {
+                self.charging_state = Some(*charging_state);
+                return None;
+            }
+            _ => return None,
+        }
+
+        let (Some(voltage), Some(current), Some(charge), Some(capacity)) =
+            (self.voltage, self.current, self.charge, self.capacity)
+        else {
+            return None;
+        };
+
+        // Start collecting the next reading from scratch.
+        self.voltage = None;
+        self.current = None;
+        self.charge = None;
+        self.capacity = None;
+
+        Some(BatteryReading {
+            voltage: voltage as f32 / 1000.0,
+            current: current as f32 / 1000.0,
+            charge: charge as f32 / 1000.0,
+            capacity: capacity as f32 / 1000.0,
+            temperature: self.temperature.map(|temperature| temperature as f32),
+            charging_state: self.charging_state,
+        })
+    }
+}
+
+/// How worried to be about the battery running out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, IntoPrimitive)]
+#[repr(u8)]
+pub enum BatteryLevel {
+    #[default]
+    Ok = 0,
+
+    /// Worth a warning.
+    Low,
+
+    /// Time to go back to the dock.
+    Critical,
+}
+
+/// The states of charge, from 0 to 1, at or below which the battery counts as low or critical.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LowBatteryThresholds {
+    pub low: f32,
+    pub critical: f32,
+}
+
+impl Default for LowBatteryThresholds {
+    fn default() -> Self {
+        Self {
+            low: 0.2,
+            critical: 0.1,
+        }
+    }
+}
+
+impl LowBatteryThresholds {
+    fn level(&self, state_of_charge: f32) -> BatteryLevel {
+        if state_of_charge <= self.critical {
+            BatteryLevel::Critical
+        } else if state_of_charge <= self.low {
+            BatteryLevel::Low
+        } else {
+            BatteryLevel::Ok
+        }
+    }
+}
+
+/// Works out the state of charge from the charge and capacity alone, and says when it crosses one
+/// of the thresholds.
+#[derive(Debug)]
+pub struct LowBatteryMonitor {
+    thresholds: LowBatteryThresholds,
+    charge: Option<u16>,
+    capacity: Option<u16>,
+    charging_state: Option<ChargingState>,
+    level: BatteryLevel,
+}
+
+impl LowBatteryMonitor {
+    pub fn new(thresholds: LowBatteryThresholds) -> Self {
+        Self {
+            thresholds,
+            charge: None,
+            capacity: None,
+            charging_state: None,
+            level: BatteryLevel::Ok,
+        }
+    }
+
+    pub fn level(&self) -> BatteryLevel {
+        self.level
+    }
+
+    /// From 0 to 1, once both the charge and capacity have been read.
+    pub fn state_of_charge(&self) -> Option<f32> {
+        let (Some(charge), Some(capacity)) = (self.charge, self.capacity) else {
+            return None;
+        };
+
+        (capacity > 0).then(|| (charge as f32 / capacity as f32).clamp(0.0, 1.0))
+    }
+
+    /// Whether the robot is sitting on a charger. Anything but not charging means it's on one.
+    pub fn on_charger(&self) -> bool {
+        matches!(self.charging_state, Some(state) if state != ChargingState::NotCharging)
+    }
+
+    /// Whether the robot should be on its way to the dock, which it should for as long as the
+    /// battery is critically low and it isn't charging.
+    pub fn needs_dock(&self) -> bool {
+        self.level == BatteryLevel::Critical && !self.on_charger()
+    }
+
+    /// Returns the new level whenever it changes.
+    pub fn update(&mut self, data: &SensorData) -> Option<BatteryLevel> {
+        match data {
+            SensorData::BatteryCharge(charge) => self.charge = Some(*charge),
+            SensorData::BatteryCapacity(capacity) => self.capacity = Some(*capacity),
+            SensorData::ChargingState(charging_state) => {
+                self.charging_state = Some(*charging_state);
+                return None;
+            }
+            _ => return None,
+        }
+
+        let state_of_charge = self.state_of_charge()?;
+        let mut level = self.thresholds.level(state_of_charge);
+        if level < self.level {
+            level = self.thresholds.level(state_of_charge - RECOVERY_MARGIN);
+        }
+
+        if level == self.level {
+            return None;
+        }
+        self.level = level;
+
+        Some(level)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waits_for_complete_reading() {
+        let mut monitor = BatteryMonitor::new();
+
+        assert_eq!(monitor.update(&SensorData::Voltage(15_600)), None);
+        assert_eq!(monitor.update(&SensorData::Current(-180)), None);
+        assert_eq!(monitor.update(&SensorData::BatteryTemperature(25)), None);
+        assert_eq!(
+            monitor.update(&SensorData::ChargingState(ChargingState::NotCharging)),
+            None
+        );
+        assert_eq!(monitor.update(&SensorData::BatteryCharge(1_300)), None);
+
+        let reading = monitor.update(&SensorData::BatteryCapacity(2_600)).unwrap();
+        assert_eq!(
+            reading,
+            BatteryReading {
+                voltage: 15.6,
+                current: -0.18,
+                charge: 1.3,
+                capacity: 2.6,
+                temperature: Some(25.0),
+                charging_state: Some(ChargingState::NotCharging),
+            }
+        );
+        assert_eq!(reading.percentage(), 0.5);
+
+        // A new reading needs everything again.
+        assert_eq!(monitor.update(&SensorData::Voltage(15_500)), None);
+        assert_eq!(monitor.update(&SensorData::BatteryCapacity(2_600)), None);
+    }
+
+    #[test]
+    fn optional_fields() {
+        let mut monitor = BatteryMonitor::new();
+
+        monitor.update(&SensorData::Voltage(15_600));
+        monitor.update(&SensorData::Current(-180));
+        monitor.update(&SensorData::BatteryCharge(3_000));
+        let reading = monitor.update(&SensorData::BatteryCapacity(2_600)).unwrap();
+
+        assert_eq!(reading.temperature, None);
+        assert_eq!(reading.charging_state, None);
+        assert_eq!(reading.percentage(), 1.0);
+    }
+
+    #[test]
+    fn low_battery_levels() {
+        let mut monitor = LowBatteryMonitor::new(LowBatteryThresholds::default());
+
+        // Nothing to go on until both have been read.
+        assert_eq!(monitor.update(&SensorData::BatteryCharge(500)), None);
+        assert_eq!(
+            monitor.update(&SensorData::BatteryCapacity(2_600)),
+            Some(BatteryLevel::Low)
+        );
+        assert_eq!(monitor.update(&SensorData::BatteryCharge(480)), None);
+        assert_eq!(
+            monitor.update(&SensorData::BatteryCharge(260)),
+            Some(BatteryLevel::Critical)
+        );
+        assert_eq!(monitor.level(), BatteryLevel::Critical);
+    }
+
+    #[test]
+    fn low_battery_recovery() {
+        let mut monitor = LowBatteryMonitor::new(LowBatteryThresholds::default());
+        monitor.update(&SensorData::BatteryCapacity(1_000));
+        monitor.update(&SensorData::BatteryCharge(100));
+        assert_eq!(monitor.level(), BatteryLevel::Critical);
+
+        // Only just past the threshold isn't enough.
+        assert_eq!(monitor.update(&SensorData::BatteryCharge(120)), None);
+        assert_eq!(
+            monitor.update(&SensorData::BatteryCharge(160)),
+            Some(BatteryLevel::Low)
+        );
+        assert_eq!(
+            monitor.update(&SensorData::BatteryCharge(900)),
+            Some(BatteryLevel::Ok)
+        );
+    }
+
+    #[test]
+    fn on_charger() {
+        let mut monitor = LowBatteryMonitor::new(LowBatteryThresholds::default());
+        assert!(!monitor.on_charger());
+
+        monitor.update(&SensorData::ChargingState(ChargingState::TrickleCharging));
+        assert!(monitor.on_charger());
+
+        monitor.update(&SensorData::ChargingState(ChargingState::NotCharging));
+        assert!(!monitor.on_charger());
+    }
+
+    #[test]
+    fn needs_dock() {
+        let mut monitor = LowBatteryMonitor::new(LowBatteryThresholds::default());
+        monitor.update(&SensorData::ChargingState(ChargingState::NotCharging));
+        monitor.update(&SensorData::BatteryCapacity(2_000));
+        monitor.update(&SensorData::BatteryCharge(300));
+        assert!(!monitor.needs_dock());
+
+        // Still critical on the next reading, so it still needs the dock.
+        monitor.update(&SensorData::BatteryCharge(100));
+        monitor.update(&SensorData::BatteryCharge(100));
+        assert!(monitor.needs_dock());
+
+        monitor.update(&SensorData::ChargingState(ChargingState::FullCharging));
+        assert!(!monitor.needs_dock());
+
+        // Knocked off the dock before it charged.
+        monitor.update(&SensorData::ChargingState(ChargingState::NotCharging));
+        assert!(monitor.needs_dock());
+    }
+}
@@ -0,0 +1,89 @@
+use anyhow::Result;
+use create_bridge::{
+    battery::{BatteryMonitor, BatteryReading},
+    roomba_interface::{ChargingState, SensorData},
+};
+use r2r::{sensor_msgs::msg::BatteryState, std_msgs::msg::Header, Node, Publisher, QosProfile};
+
+use crate::timestamp::Timestamp;
+
+/// Combines the battery sensors into a `sensor_msgs/BatteryState`, so that tools that already
+/// understand batteries can make sense of ours.
+pub struct BatteryPublisher {
+    monitor: BatteryMonitor,
+    publisher: Publisher<BatteryState>,
+}
+
+impl BatteryPublisher {
+    pub fn new(node: &mut Node) -> Result<Self> {
+        Ok(Self {
+            monitor: BatteryMonitor::new(),
+            publisher: node
+                .create_publisher::<BatteryState>("battery_state", QosProfile::default())?,
+        })
+    }
+
+    pub fn update(&mut self, data: &SensorData, timestamp: &Timestamp) -> Result<()> {
+        if let Some(reading) = self.monitor.update(data) {
+            let header = Header {
+                stamp: timestamp.ros.clone(),
+                frame_id: String::new(),
+            };
+
+            self.publisher.publish(&battery_state(header, &reading))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn battery_state(header: Header, reading: &BatteryReading) -> BatteryState {
+    let (power_supply_status, power_supply_health) = match reading.charging_state {
+        // The Roomba says it's not charging whenever it's off the dock.
+        Some(ChargingState::NotCharging) => (
+            BatteryState::POWER_SUPPLY_STATUS_DISCHARGING,
+            BatteryState::POWER_SUPPLY_HEALTH_GOOD,
+        ),
+        Some(ChargingState::ReconditioningCharging | ChargingState::FullCharging) => (
+            BatteryState::POWER_SUPPLY_STATUS_CHARGING,
+            BatteryState::POWER_SUPPLY_HEALTH_GOOD,
+        ),
+        // Trickle charging is how it keeps an already full battery topped off.
+        Some(ChargingState::TrickleCharging) => (
+            BatteryState::POWER_SUPPLY_STATUS_FULL,
+            BatteryState::POWER_SUPPLY_HEALTH_GOOD,
+        ),
+        Some(ChargingState::Waiting) => (
+            BatteryState::POWER_SUPPLY_STATUS_NOT_CHARGING,
+            BatteryState::POWER_SUPPLY_HEALTH_GOOD,
+        ),
+        Some(ChargingState::ChargingFaultCondition) => (
+            BatteryState::POWER_SUPPLY_STATUS_NOT_CHARGING,
+            BatteryState::POWER_SUPPLY_HEALTH_UNSPEC_FAILURE,
+        ),
+        None => (
+            BatteryState::POWER_SUPPLY_STATUS_UNKNOWN,
+            BatteryState::POWER_SUPPLY_HEALTH_UNKNOWN,
+        ),
+    };
+
+    BatteryState {
+        header,
+        voltage: reading.voltage,
+        temperature: reading.temperature.unwrap_or(f32::NAN),
+        current: reading.current,
+        charge: reading.charge,
+        capacity: reading.capacity,
+        // The Roomba only knows its current capacity, not what it was designed for.
+        design_capacity: f32::NAN,
+        percentage: reading.percentage(),
+        power_supply_status,
+        power_supply_health,
+        power_supply_technology: BatteryState::POWER_SUPPLY_TECHNOLOGY_UNKNOWN,
+        present: true,
+        cell_voltage: Vec::new(),
+        cell_temperature: Vec::new(),
+        location: String::new(),
+        serial_number: String::new(),
+    }
+}
@@ -0,0 +1,134 @@
+//! Pretends to be a Create 2 on a pseudo-terminal, so `create_bridge` can be run without a robot.
+//!
+//! Usage: `fake_create [--link <path>] [scenario file]`
+//!
+//! The path of the pseudo-terminal is printed on startup. Point `create_bridge`'s `serial_device`
+//! at it, or at the symbolic link made with `--link` if you need a path that doesn't change between
+//! runs. The robot keeps its state if the bridge disconnects and comes back.
+//! See `scenario.rs` for how to script sensor changes.
+
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use create_bridge::{roomba_interface::SensorData, virtual_roomba::VirtualRoomba};
+use scenario::Scenario;
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    time::sleep,
+};
+use tokio_serial::{SerialPort, SerialStream};
+
+mod scenario;
+
+/// The error reading the pseudo-terminal gives when nothing has the other side open.
+const EIO: i32 = 5;
+
+/// How often we check if something has opened the pseudo-terminal.
+const RECONNECT_PERIOD: Duration = Duration::from_millis(100);
+
+/// What a Create 2 sitting off the dock with a healthy battery reports.
+/// Scenarios can override any of this.
+const INITIAL_STATE: [SensorData; 5] = [
+    SensorData::Voltage(15_600),
+    SensorData::Current(-180),
+    SensorData::BatteryTemperature(25),
+    SensorData::BatteryCharge(2_600),
+    SensorData::BatteryCapacity(2_696),
+];
+
+struct Arguments {
+    link: Option<PathBuf>,
+    scenario: Option<PathBuf>,
+}
+
+impl Arguments {
+    fn parse() -> Result<Self> {
+        let mut arguments = std::env::args().skip(1);
+
+        let mut link = None;
+        let mut scenario = None;
+
+        while let Some(argument) = arguments.next() {
+            match argument.as_str() {
+                "--link" => {
+                    link = Some(arguments.next().context("--link requires a path")?.into());
+                }
+                _ if scenario.is_none() && !argument.starts_with('-') => {
+                    scenario = Some(argument.into());
+                }
+                _ => bail!("Unexpected argument: {argument}\nUsage: fake_create [--link <path>] [scenario file]"),
+            }
+        }
+
+        Ok(Self { link, scenario })
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let arguments = Arguments::parse()?;
+
+    let mut scenario = match &arguments.scenario {
+        Some(path) => {
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read scenario {}", path.display()))?;
+            Scenario::parse(&text)
+                .with_context(|| format!("Failed to parse scenario {}", path.display()))?
+        }
+        None => Scenario::default(),
+    };
+
+    let roomba = VirtualRoomba::new();
+    for data in INITIAL_STATE {
+        roomba.set_sensor(data);
+    }
+    scenario.apply_initial_state(&roomba);
+
+    let (mut master, slave) = SerialStream::pair().context("Failed to create pseudo-terminal")?;
+    let device = slave.name().context("Pseudo-terminal has no name")?;
+
+    // We have to let go of our end, or `create_bridge` won't be able to open it exclusively.
+    drop(slave);
+
+    if let Some(link) = &arguments.link {
+        // Clean up after a previous run that didn't exit cleanly.
+        if link.is_symlink() {
+            std::fs::remove_file(link).context("Failed to remove old link")?;
+        }
+
+        std::os::unix::fs::symlink(&device, link)
+            .with_context(|| format!("Failed to link {}", link.display()))?;
+    }
+
+    println!("Virtual Create 2 listening on {device}");
+
+    tokio::spawn(scenario.run(roomba.clone()));
+
+    let mut sig_terminate =
+        signal(SignalKind::terminate()).context("Failed to hook into terminate signal.")?;
+    let mut sig_interrupt =
+        signal(SignalKind::interrupt()).context("Failed to hook into interrupt signal.")?;
+
+    let serve = async {
+        loop {
+            match roomba.serve(&mut master).await {
+                // This is what we get when nobody has the device open.
+                // They'll show up eventually, or come back if they restart.
+                Err(error) if error.raw_os_error() == Some(EIO) => sleep(RECONNECT_PERIOD).await,
+                result => break result,
+            }
+        }
+    };
+
+    let result = tokio::select! {
+        result = serve => result.context("Pseudo-terminal failed"),
+        _ = sig_terminate.recv() => Ok(()),
+        _ = sig_interrupt.recv() => Ok(()),
+    };
+
+    if let Some(link) = &arguments.link {
+        std::fs::remove_file(link).ok();
+    }
+
+    result
+}
@@ -0,0 +1,341 @@
+//! Scripted changes to a virtual Roomba's sensors.
+//!
+//! A scenario file has one event per line: the time in seconds, the name of a sensor, and the
+//! value to give it. Times are counted from when the robot is started by the Open Interface.
+//! Sensor names are the same as the fields of `SensorQuery`. Sensors made up of flags take the
+//! names of the flags that are set, so leaving them all out clears them. Anything after a `#` is
+//! a comment.
+//!
+//! ```text
+//! # Bump into something two seconds in, and back off half a second later.
+//! 2.0 bumpers_and_wheel_drops bumper_left bumper_right
+//! 2.5 bumpers_and_wheel_drops
+//!
+//! # Run the battery down.
+//! 30 battery_charge 400
+//! 60 battery_charge 150
+//! ```
+
+use std::{str::FromStr, time::Duration};
+
+use anyhow::{anyhow, bail, Context, Result};
+use create_bridge::{
+    roomba_interface::{ChargingState, OIMode, SensorData},
+    virtual_roomba::VirtualRoomba,
+};
+use tokio::time::{sleep, sleep_until, Instant};
+
+/// How often we check if the robot has been started yet.
+const START_POLL_PERIOD: Duration = Duration::from_millis(10);
+
+#[derive(Debug, PartialEq)]
+pub struct Event {
+    time: Duration,
+    data: SensorData,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Scenario {
+    events: Vec<Event>,
+}
+
+impl Scenario {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut events = Vec::new();
+
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut words = line.split_whitespace();
+
+            let Some(time) = words.next() else {
+                // Blank line.
+                continue;
+            };
+
+            let event = parse_event(time, words)
+                .with_context(|| format!("Invalid event on line {}", line_number + 1))?;
+            events.push(event);
+        }
+
+        // Events can be written in any order, but we play them back in order.
+        events.sort_by_key(|event| event.time);
+
+        Ok(Self { events })
+    }
+
+    /// Apply everything that happens at the very start, without waiting for the robot to start.
+    pub fn apply_initial_state(&mut self, roomba: &VirtualRoomba) {
+        let later = self
+            .events
+            .iter()
+            .position(|event| !event.time.is_zero())
+            .unwrap_or(self.events.len());
+
+        for event in self.events.drain(..later) {
+            roomba.set_sensor(event.data);
+        }
+    }
+
+    /// Play back the events. Waits for the robot to be started before the clock starts.
+    pub async fn run(self, roomba: VirtualRoomba) {
+        while roomba.mode() == OIMode::Off {
+            sleep(START_POLL_PERIOD).await;
+        }
+
+        let start = Instant::now();
+
+        for event in self.events {
+            sleep_until(start + event.time).await;
+
+            println!("{:>8.3}s: {:?}", event.time.as_secs_f64(), event.data);
+            roomba.set_sensor(event.data);
+        }
+    }
+}
+
+fn parse_event<'a>(time: &str, mut words: impl Iterator<Item = &'a str>) -> Result<Event> {
+    let time = time
+        .parse::<f64>()
+        .ok()
+        .and_then(|time| Duration::try_from_secs_f64(time).ok())
+        .ok_or_else(|| anyhow!("Invalid time: {time}"))?;
+
+    let sensor = words.next().context("Missing sensor name")?;
+    let values: Vec<&str> = words.collect();
+
+    let data = parse_sensor_data(sensor, &values)?;
+
+    Ok(Event { time, data })
+}
+
+fn parse_sensor_data(sensor: &str, values: &[&str]) -> Result<SensorData> {
+    /// For sensors with a single value.
+    fn value<T: FromStr>(values: &[&str]) -> Result<T> {
+        match values {
+            [value] => value.parse().map_err(|_| anyhow!("Invalid value: {value}")),
+            _ => bail!("Expected exactly one value"),
+        }
+    }
+
+    /// For sensors made of flags. Returns which of the known flags were listed.
+    fn flags<const N: usize>(values: &[&str], names: [&str; N]) -> Result<[bool; N]> {
+        let mut flags = [false; N];
+
+        for value in values {
+            let index = names
+                .iter()
+                .position(|name| name == value)
+                .ok_or_else(|| anyhow!("Unknown flag {value}, expected one of {names:?}"))?;
+            flags[index] = true;
+        }
+
+        Ok(flags)
+    }
+
+    let data = match sensor {
+        "bumpers_and_wheel_drops" => {
+            let [wheel_drop_left, wheel_drop_right, bumper_left, bumper_right] = flags(
+                values,
+                [
+                    "wheel_drop_left",
+                    "wheel_drop_right",
+                    "bumper_left",
+                    "bumper_right",
+                ],
+            )?;
+
+            SensorData::BumpersAndWheelDrops {
+                wheel_drop_left,
+                wheel_drop_right,
+                bumper_left,
+                bumper_right,
+            }
+        }
+        "wall" => SensorData::Wall(value(values)?),
+        "cliff_left" => SensorData::CliffLeft(value(values)?),
+        "cliff_front_left" => SensorData::CliffFrontLeft(value(values)?),
+        "cliff_front_right" => SensorData::CliffFrontRight(value(values)?),
+        "cliff_right" => SensorData::CliffRight(value(values)?),
+        "virtual_wall" => SensorData::VirtualWall(value(values)?),
+        "wheel_overcurrents" => {
+            let [left_wheel, right_wheel, main_brush, side_brush] = flags(
+                values,
+                ["left_wheel", "right_wheel", "main_brush", "side_brush"],
+            )?;
+
+            SensorData::WheelOvercurrents {
+                left_wheel,
+                right_wheel,
+                main_brush,
+                side_brush,
+            }
+        }
+        "dirt_detect" => SensorData::DirtDetect(value(values)?),
+        "infrared_character_omni" => SensorData::InfraredCharacterOmni(value::<u8>(values)?.into()),
+        "infrared_character_left" => SensorData::InfraredCharacterLeft(value::<u8>(values)?.into()),
+        "infrared_character_right" => {
+            SensorData::InfraredCharacterRight(value::<u8>(values)?.into())
+        }
+        "buttons" => {
+            let [clock, schedule, day, hour, minute, dock, spot, clean] = flags(
+                values,
+                [
+                    "clock", "schedule", "day", "hour", "minute", "dock", "spot", "clean",
+                ],
+            )?;
+
+            SensorData::Buttons {
+                clock,
+                schedule,
+                day,
+                hour,
+                minute,
+                dock,
+                spot,
+                clean,
+            }
+        }
+        "distance" => SensorData::Distance(value(values)?),
+        "angle" => SensorData::Angle(value(values)?),
+        "charging_state" => {
+            let state = match values {
+                ["not_charging"] => ChargingState::NotCharging,
+                ["reconditioning_charging"] => ChargingState::ReconditioningCharging,
+                ["full_charging"] => ChargingState::FullCharging,
+                ["trickle_charging"] => ChargingState::TrickleCharging,
+                ["waiting"] => ChargingState::Waiting,
+                ["charging_fault_condition"] => ChargingState::ChargingFaultCondition,
+                _ => {
+                    let state: u8 = value(values)?;
+                    ChargingState::try_from(state)
+                        .map_err(|_| anyhow!("Invalid charging state: {state}"))?
+                }
+            };
+
+            SensorData::ChargingState(state)
+        }
+        "voltage" => SensorData::Voltage(value(values)?),
+        "current" => SensorData::Current(value(values)?),
+        "battery_temperature" => SensorData::BatteryTemperature(value(values)?),
+        "battery_charge" => SensorData::BatteryCharge(value(values)?),
+        "battery_capacity" => SensorData::BatteryCapacity(value(values)?),
+        "wall_signal" => SensorData::WallSignal(value(values)?),
+        "cliff_left_signal" => SensorData::CliffLeftSignal(value(values)?),
+        "cliff_front_left_signal" => SensorData::CliffFrontLeftSignal(value(values)?),
+        "cliff_front_right_signal" => SensorData::CliffFrontRightSignal(value(values)?),
+        "cliff_right_signal" => SensorData::CliffRightSignal(value(values)?),
+        "charging_sources_available" => {
+            let [home_base, internal_charger] = flags(values, ["home_base", "internal_charger"])?;
+
+            SensorData::ChargingSourcesAvailable {
+                home_base,
+                internal_charger,
+            }
+        }
+        "left_encoder_counts" => SensorData::LeftEncoderCounts(value(values)?),
+        "right_encoder_counts" => SensorData::RightEncoderCounts(value(values)?),
+        "light_bumper" => {
+            let [right, front_right, center_right, center_left, front_left, left] = flags(
+                values,
+                [
+                    "right",
+                    "front_right",
+                    "center_right",
+                    "center_left",
+                    "front_left",
+                    "left",
+                ],
+            )?;
+
+            SensorData::LightBumper {
+                right,
+                front_right,
+                center_right,
+                center_left,
+                front_left,
+                left,
+            }
+        }
+        "light_bump_left_signal" => SensorData::LightBumpLeftSignal(value(values)?),
+        "light_bump_front_left_signal" => SensorData::LightBumpFrontLeftSignal(value(values)?),
+        "light_bump_center_left_signal" => SensorData::LightBumpCenterLeftSignal(value(values)?),
+        "light_bump_center_right_signal" => SensorData::LightBumpCenterRightSignal(value(values)?),
+        "light_bump_front_right_signal" => SensorData::LightBumpFrontRightSignal(value(values)?),
+        "light_bump_right_signal" => SensorData::LightBumpRightSignal(value(values)?),
+        "left_motor_current" => SensorData::LeftMotorCurrent(value(values)?),
+        "right_motor_current" => SensorData::RightMotorCurrent(value(values)?),
+        "main_brush_motor_current" => SensorData::MainBrushMotorCurrent(value(values)?),
+        "side_brush_motor_current" => SensorData::SideBrushMotorCurrent(value(values)?),
+        "is_moving_forward" => SensorData::IsMovingForward(value(values)?),
+        "oi_mode"
+        | "song_number"
+        | "song_playing"
+        | "number_of_stream_packets"
+        | "requested_velocity"
+        | "requested_radius"
+        | "requested_right_velocity"
+        | "requested_left_velocity" => {
+            bail!("{sensor} follows the state of the robot and can't be scripted")
+        }
+        _ => bail!("Unknown sensor: {sensor}"),
+    };
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_events() {
+        let scenario = Scenario::parse(
+            "# A comment.\n\
+             \n\
+             2.5 bumpers_and_wheel_drops bumper_left # Trailing comment.\n\
+             0 charging_state trickle_charging\n\
+             30 battery_charge 400\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            scenario.events,
+            vec![
+                Event {
+                    time: Duration::ZERO,
+                    data: SensorData::ChargingState(ChargingState::TrickleCharging),
+                },
+                Event {
+                    time: Duration::from_millis(2500),
+                    data: SensorData::BumpersAndWheelDrops {
+                        wheel_drop_left: false,
+                        wheel_drop_right: false,
+                        bumper_left: true,
+                        bumper_right: false,
+                    },
+                },
+                Event {
+                    time: Duration::from_secs(30),
+                    data: SensorData::BatteryCharge(400),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reject_bad_events() {
+        for line in [
+            "soon wall true",
+            "-1 wall true",
+            "1",
+            "1 wall",
+            "1 wall maybe",
+            "1 battery_charge 70000",
+            "1 light_bumper middle",
+            "1 oi_mode 3",
+            "1 flux_capacitor 88",
+        ] {
+            assert!(Scenario::parse(line).is_err(), "{line}");
+        }
+    }
+}
@@ -0,0 +1,320 @@
+use anyhow::{bail, Context, Result};
+use create_bridge::{
+    battery::LowBatteryThresholds,
+    health::{Health, HealthLevel, HealthMonitor, HealthThresholds},
+    roomba_interface::{SensorData, StreamStatistics},
+};
+use r2r::{
+    create_bridge_interface::msg::LinkStatus,
+    diagnostic_msgs::msg::{DiagnosticArray, DiagnosticStatus, KeyValue},
+    std_msgs::msg::Header,
+    Clock, ClockType, Node, Publisher, QosProfile,
+};
+use tokio::time::Instant;
+
+/// The robot streams a frame every 15ms.
+const NOMINAL_STREAM_RATE: f64 = 1000.0 / 15.0;
+
+/// Reports on the health of the bridge and the robot through the standard `/diagnostics` topic,
+/// with one status per part of it.
+pub struct DiagnosticsPublisher {
+    hardware_id: String,
+    clock: Clock,
+    publisher: Publisher<DiagnosticArray>,
+
+    link_state: u8,
+    link_attempt: u32,
+    link_error: String,
+
+    /// Every `Roomba` counts from zero, so the counts of past connections are kept here.
+    past_sessions: StreamStatistics,
+    last_published: StreamStatistics,
+
+    /// Frames received since the last time we published, for working out the stream rate.
+    frames: u32,
+    last_publish_time: Instant,
+
+    /// In Hz. A stream slower than these is worth a warning or an error.
+    stream_rate_warn: f64,
+    stream_rate_error: f64,
+
+    health: HealthMonitor,
+}
+
+impl DiagnosticsPublisher {
+    pub fn new(node: &mut Node, hardware_id: String) -> Result<Self> {
+        // Unless they're set here, the battery charge thresholds are the ones the low battery
+        // monitor uses, so /diagnostics and battery/low agree on when the battery is low.
+        let low_battery = LowBatteryThresholds::default();
+        let charge_warn: Option<f64> = node
+            .get_parameter("low_battery.warn_threshold")
+            .context("Failed to get low battery warning threshold.")?;
+        let charge_error: Option<f64> = node
+            .get_parameter("low_battery.dock_threshold")
+            .context("Failed to get low battery dock threshold.")?;
+
+        let defaults = HealthThresholds {
+            battery_charge_warn: charge_warn.map_or(low_battery.low, |warn| warn as f32),
+            battery_charge_error: charge_error.map_or(low_battery.critical, |error| error as f32),
+            ..HealthThresholds::default()
+        };
+        let thresholds = HealthThresholds {
+            battery_temperature_warn: threshold(
+                node,
+                "battery.temperature_warn",
+                defaults.battery_temperature_warn,
+            )?,
+            battery_temperature_error: threshold(
+                node,
+                "battery.temperature_error",
+                defaults.battery_temperature_error,
+            )?,
+            battery_charge_warn: threshold(
+                node,
+                "battery.charge_warn",
+                defaults.battery_charge_warn,
+            )?,
+            battery_charge_error: threshold(
+                node,
+                "battery.charge_error",
+                defaults.battery_charge_error,
+            )?,
+            motor_current_warn: threshold(
+                node,
+                "motors.current_warn_ma",
+                defaults.motor_current_warn,
+            )?,
+            motor_current_error: threshold(
+                node,
+                "motors.current_error_ma",
+                defaults.motor_current_error,
+            )?,
+        };
+
+        // Anything much below the nominal rate of ~66Hz means frames are going missing.
+        let stream_rate_warn: Option<f64> =
+            node.get_parameter("diagnostics.stream.rate_warn_hz")
+                .context("Failed to get stream rate warning threshold.")?;
+        let stream_rate_error: Option<f64> = node
+            .get_parameter("diagnostics.stream.rate_error_hz")
+            .context("Failed to get stream rate error threshold.")?;
+        let stream_rate_warn = stream_rate_warn.unwrap_or(50.0);
+        let stream_rate_error = stream_rate_error.unwrap_or(NOMINAL_STREAM_RATE / 2.0);
+
+        check_pair(
+            "battery temperature",
+            thresholds.battery_temperature_warn.into(),
+            thresholds.battery_temperature_error.into(),
+            true,
+        )?;
+        check_pair(
+            "battery charge",
+            thresholds.battery_charge_warn.into(),
+            thresholds.battery_charge_error.into(),
+            false,
+        )?;
+        if thresholds.battery_charge_warn > 1.0 {
+            bail!("Battery charge thresholds must be between 0 and 1.");
+        }
+        check_pair(
+            "motor current",
+            thresholds.motor_current_warn.into(),
+            thresholds.motor_current_error.into(),
+            true,
+        )?;
+        check_pair("stream rate", stream_rate_warn, stream_rate_error, false)?;
+
+        Ok(Self {
+            hardware_id,
+            clock: Clock::create(ClockType::RosTime)?,
+            publisher: node
+                .create_publisher::<DiagnosticArray>("/diagnostics", QosProfile::default())?,
+            link_state: LinkStatus::CONNECTING,
+            link_attempt: 0,
+            link_error: String::new(),
+            past_sessions: StreamStatistics::default(),
+            last_published: StreamStatistics::default(),
+            frames: 0,
+            last_publish_time: Instant::now(),
+            stream_rate_warn,
+            stream_rate_error,
+            health: HealthMonitor::new(thresholds),
+        })
+    }
+
+    /// Keep track of the link, using the same states as the `link_status` topic.
+    pub fn set_link_status(&mut self, state: u8, attempt: u32, error: &str) {
+        self.link_state = state;
+        self.link_attempt = attempt;
+        self.link_error = error.to_string();
+    }
+
+    /// Takes sensor data from anywhere, streamed or queried.
+    pub fn update(&mut self, data: &[SensorData]) {
+        for data in data {
+            self.health.update(data);
+        }
+    }
+
+    /// Count a frame from the sensor stream towards the stream rate.
+    pub fn count_frame(&mut self) {
+        self.frames += 1;
+    }
+
+    /// Carry over the counts of a connection that has ended. The readings from it are forgotten,
+    /// since they won't be kept up to date until we reconnect.
+    pub fn end_session(&mut self, session: StreamStatistics) {
+        self.past_sessions = add(self.past_sessions, session);
+        self.health.reset();
+    }
+
+    /// Publish the counts of the current connection, on top of the ones before it. `streaming`
+    /// is whether frames are expected to be coming in.
+    pub fn publish(&mut self, session: StreamStatistics, streaming: bool) -> Result<()> {
+        let status = vec![
+            self.link_status(),
+            self.stream_status(session, streaming),
+            self.health_status("Battery", self.health.battery()),
+            self.health_status("Motors", self.health.motors()),
+            self.health_status("Charging", self.health.charging()),
+        ];
+
+        self.publisher.publish(&DiagnosticArray {
+            header: Header {
+                stamp: Clock::to_builtin_time(&self.clock.get_now()?),
+                frame_id: String::new(),
+            },
+            status,
+        })?;
+
+        Ok(())
+    }
+
+    fn link_status(&self) -> DiagnosticStatus {
+        let (level, message) = match self.link_state {
+            LinkStatus::CONNECTED => (DiagnosticStatus::OK, "Connected".to_string()),
+            LinkStatus::CONNECTING => (DiagnosticStatus::WARN, "Connecting".to_string()),
+            _ => (
+                DiagnosticStatus::ERROR,
+                format!("Disconnected: {}", self.link_error),
+            ),
+        };
+
+        self.status(
+            "Serial link",
+            level,
+            message,
+            vec![("Attempt", self.link_attempt.to_string())],
+        )
+    }
+
+    fn stream_status(&mut self, session: StreamStatistics, streaming: bool) -> DiagnosticStatus {
+        let statistics = add(self.past_sessions, session);
+        let dropping = statistics != self.last_published;
+        self.last_published = statistics;
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_publish_time).as_secs_f64();
+        let rate = if elapsed > 0.0 {
+            self.frames as f64 / elapsed
+        } else {
+            0.0
+        };
+        self.frames = 0;
+        self.last_publish_time = now;
+
+        // Losing the odd frame is normal for a serial link. We only complain while it's happening.
+        let (level, message) = if self.link_state != LinkStatus::CONNECTED {
+            (DiagnosticStatus::STALE, "Not connected")
+        } else if !streaming {
+            (DiagnosticStatus::OK, "Not streaming")
+        } else if rate == 0.0 {
+            (DiagnosticStatus::ERROR, "No frames coming in")
+        } else if rate < self.stream_rate_error {
+            (DiagnosticStatus::ERROR, "Stream rate is far too low")
+        } else if rate < self.stream_rate_warn {
+            (DiagnosticStatus::WARN, "Stream rate is low")
+        } else if dropping {
+            (DiagnosticStatus::WARN, "Dropping frames")
+        } else {
+            (DiagnosticStatus::OK, "Receiving frames")
+        };
+
+        let values = vec![
+            ("Rate (Hz)", format!("{rate:.1}")),
+            (
+                "Checksum failures",
+                statistics.checksum_failures.to_string(),
+            ),
+            ("Resyncs", statistics.resyncs.to_string()),
+            ("Dropped packets", statistics.dropped_packets.to_string()),
+        ];
+
+        self.status("Sensor stream", level, message.to_string(), values)
+    }
+
+    fn health_status(&self, name: &str, health: Health) -> DiagnosticStatus {
+        let level = match health.level {
+            HealthLevel::Ok => DiagnosticStatus::OK,
+            HealthLevel::Warn => DiagnosticStatus::WARN,
+            HealthLevel::Error => DiagnosticStatus::ERROR,
+            HealthLevel::Stale => DiagnosticStatus::STALE,
+        };
+
+        self.status(name, level, health.message, health.values)
+    }
+
+    fn status(
+        &self,
+        name: &str,
+        level: u8,
+        message: String,
+        values: Vec<(&str, String)>,
+    ) -> DiagnosticStatus {
+        DiagnosticStatus {
+            level,
+            name: format!("create_bridge: {name}"),
+            message,
+            hardware_id: self.hardware_id.clone(),
+            values: values
+                .into_iter()
+                .map(|(key, value)| KeyValue {
+                    key: key.to_string(),
+                    value,
+                })
+                .collect(),
+        }
+    }
+}
+
+fn threshold(node: &Node, name: &str, default: f32) -> Result<f32> {
+    let threshold: Option<f64> = node
+        .get_parameter(&format!("diagnostics.{name}"))
+        .with_context(|| format!("Failed to get {name} diagnostics threshold."))?;
+
+    Ok(threshold.map_or(default, |threshold| threshold as f32))
+}
+
+/// Checks that a pair of thresholds are numbers that aren't negative, and that readings reach the
+/// warning before the error. `rising` is whether readings get worse going up.
+fn check_pair(name: &str, warn: f64, error: f64, rising: bool) -> Result<()> {
+    if !warn.is_finite() || !error.is_finite() || warn < 0.0 || error < 0.0 {
+        bail!("The {name} thresholds must be numbers that aren't negative, but are {warn} and {error}.");
+    }
+    if rising && error < warn {
+        bail!("The {name} error threshold can't be below the warning threshold.");
+    }
+    if !rising && error > warn {
+        bail!("The {name} error threshold can't be above the warning threshold.");
+    }
+
+    Ok(())
+}
+
+fn add(a: StreamStatistics, b: StreamStatistics) -> StreamStatistics {
+    StreamStatistics {
+        checksum_failures: a.checksum_failures + b.checksum_failures,
+        resyncs: a.resyncs + b.resyncs,
+        dropped_packets: a.dropped_packets + b.dropped_packets,
+    }
+}
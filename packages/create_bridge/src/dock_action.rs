@@ -0,0 +1,166 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use create_bridge::{
+    docking::{DockingMonitor, DockingPhase},
+    roomba_interface::SensorData,
+};
+use futures::stream::{self, LocalBoxStream, StreamExt};
+use r2r::{
+    create_bridge_interface::action::Dock, ActionServerCancelRequest, ActionServerGoal,
+    ActionServerGoalRequest, Node,
+};
+use tokio::time::{sleep_until, Instant};
+
+use crate::motion_actions::Outcome;
+
+/// How long each attempt at docking gets, and how many tries it gets.
+pub struct DockSettings {
+    timeout: Duration,
+    retries: u32,
+}
+
+impl DockSettings {
+    pub fn from_parameters(node: &Node) -> Result<Self> {
+        let timeout: Option<i64> = node
+            .get_parameter("dock.timeout_ms")
+            .context("Failed to get dock timeout.")?;
+        let retries: Option<i64> = node
+            .get_parameter("dock.retries")
+            .context("Failed to get dock retries.")?;
+
+        Ok(Self {
+            timeout: Duration::from_millis(timeout.unwrap_or(120_000).max(1) as u64),
+            retries: retries.unwrap_or(1).max(0) as u32,
+        })
+    }
+}
+
+pub enum DockingEvent {
+    CancelRequested(ActionServerCancelRequest),
+
+    /// The current attempt has taken too long.
+    TimedOut,
+}
+
+/// A `dock` goal that is being carried out, or the bridge sending the robot home on its own when
+/// the battery is critically low. The robot finds its own way to the dock, so all this does is
+/// watch, and send it looking again if it takes too long.
+pub struct ActiveDocking {
+    monitor: DockingMonitor,
+    /// There's no goal when the bridge started docking itself.
+    goal: Option<ActionServerGoal<Dock::Action>>,
+    cancel_requests: LocalBoxStream<'static, ActionServerCancelRequest>,
+
+    attempt: u32,
+    max_attempts: u32,
+    timeout: Duration,
+    deadline: Instant,
+}
+
+impl ActiveDocking {
+    /// It's up to the caller to send the robot looking for the dock.
+    pub fn new(
+        request: ActionServerGoalRequest<Dock::Action>,
+        settings: &DockSettings,
+    ) -> Result<Self> {
+        let (goal, cancel_requests) = request.accept()?;
+
+        let docking = Self {
+            goal: Some(goal),
+            cancel_requests: cancel_requests.boxed_local(),
+            ..Self::for_low_battery(settings)
+        };
+        docking.publish_feedback()?;
+
+        Ok(docking)
+    }
+
+    /// Docking that nobody asked for, so there's nobody to report to or to cancel it. It's up to
+    /// the caller to send the robot looking for the dock.
+    pub fn for_low_battery(settings: &DockSettings) -> Self {
+        Self {
+            monitor: DockingMonitor::new(),
+            goal: None,
+            cancel_requests: stream::pending().boxed_local(),
+            attempt: 1,
+            max_attempts: settings.retries + 1,
+            timeout: settings.timeout,
+            deadline: Instant::now() + settings.timeout,
+        }
+    }
+
+    pub fn phase(&self) -> DockingPhase {
+        self.monitor.phase()
+    }
+
+    /// Feed in a frame of sensor data. Returns the new phase whenever it changes.
+    pub fn update(&mut self, data: &[SensorData]) -> Result<Option<DockingPhase>> {
+        let mut changed = None;
+        for data in data {
+            changed = self.monitor.update(data).or(changed);
+        }
+
+        if changed.is_some() {
+            self.publish_feedback()?;
+        }
+
+        Ok(changed)
+    }
+
+    /// Start the next attempt, if there are any left. It's up to the caller to send the robot
+    /// looking for the dock again.
+    pub fn retry(&mut self) -> Result<bool> {
+        if self.attempt >= self.max_attempts {
+            return Ok(false);
+        }
+
+        self.attempt += 1;
+        self.deadline = Instant::now() + self.timeout;
+        self.publish_feedback()?;
+
+        Ok(true)
+    }
+
+    /// Completes once someone asks for the goal to be canceled, or the current attempt has taken
+    /// too long.
+    pub async fn next_event(&mut self) -> DockingEvent {
+        let deadline = self.deadline;
+
+        tokio::select! {
+            // If nobody is left to ask, only the timeout is left.
+            Some(request) = self.cancel_requests.next() => DockingEvent::CancelRequested(request),
+            _ = sleep_until(deadline) => DockingEvent::TimedOut,
+        }
+    }
+
+    /// Report how docking ended.
+    pub fn finish(self, outcome: Outcome) -> Result<()> {
+        let Some(mut goal) = self.goal else {
+            return Ok(());
+        };
+        let result = |message| Dock::Result {
+            attempts: self.attempt,
+            message,
+        };
+
+        match outcome {
+            Outcome::Succeeded => goal.succeed(result(String::new()))?,
+            Outcome::Canceled => goal.cancel(result(String::new()))?,
+            Outcome::Aborted(message) => goal.abort(result(message))?,
+        }
+
+        Ok(())
+    }
+
+    fn publish_feedback(&self) -> Result<()> {
+        if let Some(goal) = &self.goal {
+            goal.publish_feedback(Dock::Feedback {
+                phase: self.phase().into(),
+                attempt: self.attempt,
+            })?;
+        }
+
+        Ok(())
+    }
+}
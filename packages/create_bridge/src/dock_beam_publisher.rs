@@ -0,0 +1,70 @@
+use anyhow::Result;
+use create_bridge::roomba_interface::{DockBeams, SensorData};
+use r2r::{
+    create_bridge_interface::msg::{DockBeamState, DockBeams as DockBeamsMessage},
+    std_msgs::msg::Header,
+    Node, Publisher, QosProfile,
+};
+
+use crate::timestamp::Timestamp;
+
+/// Decodes what the infrared receivers picked up into which of the dock's beams they can see,
+/// for anything that wants to find its own way back to the dock.
+pub struct DockBeamPublisher {
+    publisher: Publisher<DockBeamState>,
+}
+
+impl DockBeamPublisher {
+    pub fn new(node: &mut Node) -> Result<Self> {
+        Ok(Self {
+            publisher: node
+                .create_publisher::<DockBeamState>("dock_beams", QosProfile::default())?,
+        })
+    }
+
+    /// All the data should be from the same stream frame. Nothing is published unless one of the
+    /// infrared receivers is in it.
+    pub fn publish(&self, data: &[SensorData], timestamp: &Timestamp) -> Result<()> {
+        let mut state = DockBeamState::default();
+        let mut present = false;
+
+        for data in data {
+            // Anything other than a dock means no beams are in view.
+            let (receiver_present, beams, character) = match data {
+                SensorData::InfraredCharacterOmni(character) => {
+                    (&mut state.omni_present, &mut state.omni, character)
+                }
+                SensorData::InfraredCharacterLeft(character) => {
+                    (&mut state.left_present, &mut state.left, character)
+                }
+                SensorData::InfraredCharacterRight(character) => {
+                    (&mut state.right_present, &mut state.right, character)
+                }
+                _ => continue,
+            };
+
+            *receiver_present = true;
+            *beams = dock_beams_message(character.dock_beams().unwrap_or_default());
+            present = true;
+        }
+
+        if present {
+            state.header = Header {
+                stamp: timestamp.ros.clone(),
+                frame_id: String::new(),
+            };
+
+            self.publisher.publish(&state)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn dock_beams_message(beams: DockBeams) -> DockBeamsMessage {
+    DockBeamsMessage {
+        red_buoy: beams.red_buoy,
+        green_buoy: beams.green_buoy,
+        force_field: beams.force_field,
+    }
+}
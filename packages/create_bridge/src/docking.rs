@@ -0,0 +1,180 @@
+//! Following the robot's progress as it finds its way back to the dock.
+
+use num_enum::IntoPrimitive;
+
+use crate::roomba_interface::{ChargingState, SensorData};
+
+/// How far along docking is, in the order it normally goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, IntoPrimitive)]
+#[repr(u8)]
+pub enum DockingPhase {
+    /// None of the dock's beams are in view.
+    #[default]
+    Searching = 0,
+
+    /// The robot can see the dock, and is heading for it.
+    Approaching = 1,
+
+    /// The robot is sitting on the dock, but isn't charging yet.
+    Docked = 2,
+
+    Charging = 3,
+}
+
+/// Works out the docking phase from the charging sources, charging state and infrared characters.
+/// The charging sources and charging state are needed to tell when the robot has docked, and the
+/// infrared characters to tell when it has found the dock.
+#[derive(Debug, Default)]
+pub struct DockingMonitor {
+    phase: DockingPhase,
+    on_dock: bool,
+    charging: bool,
+}
+
+impl DockingMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn phase(&self) -> DockingPhase {
+        self.phase
+    }
+
+    /// Feed in sensor data as it arrives. Returns the new phase whenever it changes.
+    pub fn update(&mut self, data: &SensorData) -> Option<DockingPhase> {
+        match data {
+            SensorData::ChargingSourcesAvailable { home_base, .. } => self.on_dock = *home_base,
+            SensorData::ChargingState(state) => {
+                self.charging = matches!(
+                    state,
+                    ChargingState::ReconditioningCharging
+                        | ChargingState::FullCharging
+                        | ChargingState::TrickleCharging
+                );
+            }
+            SensorData::InfraredCharacterOmni(character)
+            | SensorData::InfraredCharacterLeft(character)
+            | SensorData::InfraredCharacterRight(character) => {
+                let beams = character.dock_beams().unwrap_or_default();
+                let dock_in_view = beams.red_buoy || beams.green_buoy || beams.force_field;
+
+                // Losing sight of the dock for a moment is normal as the robot lines itself up,
+                // so this only ever moves forward.
+                if dock_in_view && self.phase == DockingPhase::Searching {
+                    return self.set_phase(DockingPhase::Approaching);
+                }
+
+                return None;
+            }
+            _ => return None,
+        }
+
+        let phase = match (self.on_dock, self.charging) {
+            (true, true) => DockingPhase::Charging,
+            (true, false) => DockingPhase::Docked,
+
+            // Charging from the internal charger doesn't count, since that means it's not on the
+            // dock. Falling off the dock means starting the search again.
+            (false, _) if self.phase >= DockingPhase::Docked => DockingPhase::Searching,
+            (false, _) => self.phase,
+        };
+
+        self.set_phase(phase)
+    }
+
+    fn set_phase(&mut self, phase: DockingPhase) -> Option<DockingPhase> {
+        (phase != self.phase).then(|| {
+            self.phase = phase;
+            phase
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::roomba_interface::{DockBeams, InfraredCharacter, RemoteButton};
+
+    fn charging_sources(home_base: bool) -> SensorData {
+        SensorData::ChargingSourcesAvailable {
+            home_base,
+            internal_charger: false,
+        }
+    }
+
+    #[test]
+    fn docking() {
+        let mut monitor = DockingMonitor::new();
+
+        assert_eq!(monitor.update(&charging_sources(false)), None);
+        assert_eq!(
+            monitor.update(&SensorData::InfraredCharacterOmni(
+                InfraredCharacter::Remote(RemoteButton::Clean)
+            )),
+            None
+        );
+        assert_eq!(monitor.phase(), DockingPhase::Searching);
+
+        let green_buoy = InfraredCharacter::Roomba600Charger(DockBeams {
+            green_buoy: true,
+            ..Default::default()
+        });
+        assert_eq!(
+            monitor.update(&SensorData::InfraredCharacterLeft(green_buoy)),
+            Some(DockingPhase::Approaching)
+        );
+        assert_eq!(
+            monitor.update(&SensorData::InfraredCharacterLeft(
+                InfraredCharacter::NoSignal
+            )),
+            None
+        );
+
+        assert_eq!(
+            monitor.update(&SensorData::ChargingState(ChargingState::NotCharging)),
+            None
+        );
+        assert_eq!(
+            monitor.update(&charging_sources(true)),
+            Some(DockingPhase::Docked)
+        );
+        assert_eq!(
+            monitor.update(&SensorData::ChargingState(ChargingState::FullCharging)),
+            Some(DockingPhase::Charging)
+        );
+        assert_eq!(monitor.update(&charging_sources(true)), None);
+    }
+
+    #[test]
+    fn knocked_off_the_dock() {
+        let mut monitor = DockingMonitor::new();
+
+        monitor.update(&charging_sources(true));
+        monitor.update(&SensorData::ChargingState(ChargingState::TrickleCharging));
+        assert_eq!(monitor.phase(), DockingPhase::Charging);
+
+        assert_eq!(
+            monitor.update(&SensorData::ChargingState(ChargingState::NotCharging)),
+            Some(DockingPhase::Docked)
+        );
+        assert_eq!(
+            monitor.update(&charging_sources(false)),
+            Some(DockingPhase::Searching)
+        );
+    }
+
+    #[test]
+    fn internal_charger() {
+        let mut monitor = DockingMonitor::new();
+
+        monitor.update(&SensorData::ChargingState(ChargingState::FullCharging));
+        assert_eq!(
+            monitor.update(&SensorData::ChargingSourcesAvailable {
+                home_base: false,
+                internal_charger: true,
+            }),
+            None
+        );
+        assert_eq!(monitor.phase(), DockingPhase::Searching);
+    }
+}
@@ -0,0 +1,118 @@
+//! Stops the robot when whoever is driving it goes quiet.
+
+use std::{future::Future, time::Duration};
+
+use tokio::time::{sleep_until, Instant};
+
+/// Keeps track of when the last drive command arrived. Once the timeout passes without a new one,
+/// [`DriveWatchdog::expired`] completes and it's up to the caller to stop the robot and call
+/// [`DriveWatchdog::trip`]. Driving stays disabled until the next command comes in.
+#[derive(Debug)]
+pub struct DriveWatchdog {
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    tripped: bool,
+}
+
+impl DriveWatchdog {
+    /// A timeout of `None` disables the watchdog.
+    pub fn new(timeout: Option<Duration>) -> Self {
+        Self {
+            timeout,
+            deadline: None,
+            tripped: false,
+        }
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Driving is enabled unless the watchdog has tripped since the last command.
+    pub fn is_enabled(&self) -> bool {
+        !self.tripped
+    }
+
+    /// Call for every drive command. Commands that leave the robot stopped don't need to be
+    /// repeated, so they disarm the watchdog. Returns true if this re-enabled driving.
+    pub fn feed(&mut self, moving: bool) -> bool {
+        self.deadline = match self.timeout {
+            Some(timeout) if moving => Some(Instant::now() + timeout),
+            _ => None,
+        };
+
+        std::mem::replace(&mut self.tripped, false)
+    }
+
+    /// Completes once the robot has gone too long without a drive command. Never completes if the
+    /// robot isn't moving. This doesn't borrow the watchdog, so it can be polled while other
+    /// branches of a `select!` feed it.
+    pub fn expired(&self) -> impl Future<Output = ()> {
+        let deadline = self.deadline;
+
+        async move {
+            match deadline {
+                Some(deadline) => sleep_until(deadline).await,
+                None => std::future::pending().await,
+            }
+        }
+    }
+
+    /// Disable driving until the next command arrives.
+    pub fn trip(&mut self) {
+        self.deadline = None;
+        self.tripped = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::{sleep, timeout};
+
+    const TIMEOUT: Duration = Duration::from_millis(500);
+
+    #[tokio::test(start_paused = true)]
+    async fn expires_without_commands() {
+        let mut watchdog = DriveWatchdog::new(Some(TIMEOUT));
+
+        // Nothing to watch until the robot starts moving.
+        assert!(timeout(TIMEOUT * 4, watchdog.expired()).await.is_err());
+
+        assert!(!watchdog.feed(true));
+        sleep(TIMEOUT / 2).await;
+        assert!(!watchdog.feed(true));
+
+        let start = Instant::now();
+        watchdog.expired().await;
+        assert_eq!(start.elapsed(), TIMEOUT);
+
+        watchdog.trip();
+        assert!(!watchdog.is_enabled());
+        assert!(timeout(TIMEOUT * 4, watchdog.expired()).await.is_err());
+
+        // Commands coming back re-enables driving.
+        assert!(watchdog.feed(true));
+        assert!(watchdog.is_enabled());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stopping_disarms() {
+        let mut watchdog = DriveWatchdog::new(Some(TIMEOUT));
+
+        watchdog.feed(true);
+        watchdog.feed(false);
+
+        assert!(timeout(TIMEOUT * 4, watchdog.expired()).await.is_err());
+        assert!(watchdog.is_enabled());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn disabled() {
+        let mut watchdog = DriveWatchdog::new(None);
+
+        watchdog.feed(true);
+
+        assert!(timeout(TIMEOUT * 4, watchdog.expired()).await.is_err());
+    }
+}
@@ -0,0 +1,350 @@
+//! Judging from the sensors whether the battery, motors and charging are in good shape.
+
+use crate::{
+    battery::LowBatteryThresholds,
+    roomba_interface::{ChargingState, SensorData},
+};
+
+/// How healthy a part of the robot is, from best to worst.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HealthLevel {
+    Ok,
+    Warn,
+    Error,
+
+    /// Nothing has been read to judge it by.
+    Stale,
+}
+
+/// How one part of the robot is doing, and the readings that went into deciding that.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Health {
+    pub level: HealthLevel,
+    pub message: String,
+    pub values: Vec<(&'static str, String)>,
+}
+
+impl Health {
+    fn stale() -> Self {
+        Self {
+            level: HealthLevel::Stale,
+            message: "No readings".to_string(),
+            values: Vec::new(),
+        }
+    }
+
+    /// The worst of the problems decides the level, and they're all listed in the message.
+    fn from_problems(
+        problems: Vec<(HealthLevel, String)>,
+        healthy: &str,
+        values: Vec<(&'static str, String)>,
+    ) -> Self {
+        let level = problems
+            .iter()
+            .map(|(level, _)| *level)
+            .max()
+            .unwrap_or(HealthLevel::Ok);
+
+        let message = if problems.is_empty() {
+            healthy.to_string()
+        } else {
+            problems
+                .into_iter()
+                .map(|(_, problem)| problem)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        Self {
+            level,
+            message,
+            values,
+        }
+    }
+}
+
+/// Where readings go from fine to worth a warning, and from that to an error.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HealthThresholds {
+    /// In degrees Celsius.
+    pub battery_temperature_warn: f32,
+    pub battery_temperature_error: f32,
+
+    /// The state of charge, from 0 to 1.
+    pub battery_charge_warn: f32,
+    pub battery_charge_error: f32,
+
+    /// In mA, going either way.
+    pub motor_current_warn: f32,
+    pub motor_current_error: f32,
+}
+
+impl Default for HealthThresholds {
+    /// The battery charge goes by the low battery thresholds, so the two agree on when the
+    /// battery is low.
+    fn default() -> Self {
+        let low_battery = LowBatteryThresholds::default();
+
+        Self {
+            battery_temperature_warn: 45.0,
+            battery_temperature_error: 55.0,
+            battery_charge_warn: low_battery.low,
+            battery_charge_error: low_battery.critical,
+            motor_current_warn: 1000.0,
+            motor_current_error: 2000.0,
+        }
+    }
+}
+
+/// Checks `value` against a pair of thresholds. Use negative values for thresholds that are
+/// crossed going down.
+fn check(value: f32, warn: f32, error: f32) -> Option<HealthLevel> {
+    if value >= error {
+        Some(HealthLevel::Error)
+    } else if value >= warn {
+        Some(HealthLevel::Warn)
+    } else {
+        None
+    }
+}
+
+const MOTORS: [&str; 4] = ["Left wheel", "Right wheel", "Main brush", "Side brush"];
+
+/// Feed in sensor data as it arrives, and ask how each part of the robot is doing whenever it's
+/// time to report on it. The latest reading of everything is kept.
+#[derive(Debug, Default)]
+pub struct HealthMonitor {
+    thresholds: HealthThresholds,
+
+    voltage: Option<u16>,
+    charge: Option<u16>,
+    capacity: Option<u16>,
+    temperature: Option<i8>,
+    charging_state: Option<ChargingState>,
+
+    /// In the same order as `MOTORS`.
+    motor_currents: [Option<i16>; 4],
+    overcurrents: Option<[bool; 4]>,
+}
+
+impl HealthMonitor {
+    pub fn new(thresholds: HealthThresholds) -> Self {
+        Self {
+            thresholds,
+            ..Default::default()
+        }
+    }
+
+    /// Forget everything that has been read, for when the readings may have stopped coming.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.thresholds);
+    }
+
+    pub fn update(&mut self, data: &SensorData) {
+        match data {
+            SensorData::Voltage(voltage) => self.voltage = Some(*voltage),
+            SensorData::BatteryCharge(charge) => self.charge = Some(*charge),
+            SensorData::BatteryCapacity(capacity) => self.capacity = Some(*capacity),
+            SensorData::BatteryTemperature(temperature) => self.temperature = Some(*temperature),
+            SensorData::ChargingState(charging_state) => {
+                self.charging_state = Some(*charging_state)
+            }
+            SensorData::LeftMotorCurrent(current) => self.motor_currents[0] = Some(*current),
+            SensorData::RightMotorCurrent(current) => self.motor_currents[1] = Some(*current),
+            SensorData::MainBrushMotorCurrent(current) => self.motor_currents[2] = Some(*current),
+            SensorData::SideBrushMotorCurrent(current) => self.motor_currents[3] = Some(*current),
+            SensorData::WheelOvercurrents {
+                left_wheel,
+                right_wheel,
+                main_brush,
+                side_brush,
+            } => self.overcurrents = Some([*left_wheel, *right_wheel, *main_brush, *side_brush]),
+            _ => {}
+        }
+    }
+
+    pub fn battery(&self) -> Health {
+        if self.voltage.is_none()
+            && self.charge.is_none()
+            && self.capacity.is_none()
+            && self.temperature.is_none()
+        {
+            return Health::stale();
+        }
+
+        let mut problems = Vec::new();
+        let mut values = Vec::new();
+
+        if let Some(voltage) = self.voltage {
+            values.push(("Voltage (V)", format!("{:.2}", voltage as f32 / 1000.0)));
+        }
+
+        if let (Some(charge), Some(capacity)) = (self.charge, self.capacity) {
+            values.push(("Charge (Ah)", format!("{:.2}", charge as f32 / 1000.0)));
+            values.push(("Capacity (Ah)", format!("{:.2}", capacity as f32 / 1000.0)));
+
+            if capacity > 0 {
+                let state_of_charge = (charge as f32 / capacity as f32).clamp(0.0, 1.0);
+                values.push((
+                    "State of charge (%)",
+                    format!("{:.0}", state_of_charge * 100.0),
+                ));
+
+                let thresholds = &self.thresholds;
+                if let Some(level) = check(
+                    -state_of_charge,
+                    -thresholds.battery_charge_warn,
+                    -thresholds.battery_charge_error,
+                ) {
+                    problems.push((
+                        level,
+                        format!("Battery low ({:.0}%)", state_of_charge * 100.0),
+                    ));
+                }
+            }
+        }
+
+        if let Some(temperature) = self.temperature {
+            values.push(("Temperature (°C)", temperature.to_string()));
+
+            if let Some(level) = check(
+                temperature as f32,
+                self.thresholds.battery_temperature_warn,
+                self.thresholds.battery_temperature_error,
+            ) {
+                problems.push((level, format!("Battery hot ({temperature}°C)")));
+            }
+        }
+
+        Health::from_problems(problems, "Battery OK", values)
+    }
+
+    pub fn motors(&self) -> Health {
+        if self.overcurrents.is_none() && self.motor_currents.iter().all(Option::is_none) {
+            return Health::stale();
+        }
+
+        let mut problems = Vec::new();
+        let mut values = Vec::new();
+
+        for (motor, current) in MOTORS.iter().zip(self.motor_currents) {
+            let Some(current) = current else {
+                continue;
+            };
+            values.push((*motor, format!("{current} mA")));
+
+            if let Some(level) = check(
+                (current as f32).abs(),
+                self.thresholds.motor_current_warn,
+                self.thresholds.motor_current_error,
+            ) {
+                problems.push((level, format!("{motor} drawing {current} mA")));
+            }
+        }
+
+        // The robot's own overcurrent detection gets the final say.
+        for (motor, overcurrent) in MOTORS.iter().zip(self.overcurrents.unwrap_or_default()) {
+            if overcurrent {
+                problems.push((HealthLevel::Error, format!("{motor} overcurrent")));
+            }
+        }
+
+        Health::from_problems(problems, "Motors OK", values)
+    }
+
+    pub fn charging(&self) -> Health {
+        let Some(charging_state) = self.charging_state else {
+            return Health::stale();
+        };
+
+        let (level, message) = match charging_state {
+            ChargingState::NotCharging => (HealthLevel::Ok, "Not charging"),
+            ChargingState::ReconditioningCharging => (HealthLevel::Ok, "Reconditioning"),
+            ChargingState::FullCharging => (HealthLevel::Ok, "Charging"),
+            ChargingState::TrickleCharging => (HealthLevel::Ok, "Trickle charging"),
+            ChargingState::Waiting => (HealthLevel::Ok, "Waiting to charge"),
+            ChargingState::ChargingFaultCondition => (HealthLevel::Error, "Charging fault"),
+        };
+
+        Health {
+            level,
+            message: message.to_string(),
+            values: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_until_read() {
+        let monitor = HealthMonitor::new(HealthThresholds::default());
+
+        assert_eq!(monitor.battery().level, HealthLevel::Stale);
+        assert_eq!(monitor.motors().level, HealthLevel::Stale);
+        assert_eq!(monitor.charging().level, HealthLevel::Stale);
+    }
+
+    #[test]
+    fn battery_thresholds() {
+        let mut monitor = HealthMonitor::new(HealthThresholds::default());
+        monitor.update(&SensorData::BatteryCapacity(2_000));
+        monitor.update(&SensorData::BatteryCharge(1_000));
+        monitor.update(&SensorData::BatteryTemperature(30));
+        assert_eq!(monitor.battery().level, HealthLevel::Ok);
+
+        monitor.update(&SensorData::BatteryCharge(300));
+        let battery = monitor.battery();
+        assert_eq!(battery.level, HealthLevel::Warn);
+        assert_eq!(battery.message, "Battery low (15%)");
+
+        monitor.update(&SensorData::BatteryTemperature(60));
+        let battery = monitor.battery();
+        assert_eq!(battery.level, HealthLevel::Error);
+        assert_eq!(battery.message, "Battery low (15%), Battery hot (60°C)");
+
+        // Critical, as far as the low battery monitor is concerned.
+        monitor.update(&SensorData::BatteryTemperature(30));
+        monitor.update(&SensorData::BatteryCharge(200));
+        assert_eq!(monitor.battery().level, HealthLevel::Error);
+
+        monitor.reset();
+        assert_eq!(monitor.battery().level, HealthLevel::Stale);
+    }
+
+    #[test]
+    fn motors() {
+        let mut monitor = HealthMonitor::new(HealthThresholds::default());
+        monitor.update(&SensorData::LeftMotorCurrent(-300));
+        monitor.update(&SensorData::MainBrushMotorCurrent(1_200));
+        assert_eq!(monitor.motors().level, HealthLevel::Warn);
+
+        monitor.update(&SensorData::WheelOvercurrents {
+            left_wheel: true,
+            right_wheel: false,
+            main_brush: false,
+            side_brush: false,
+        });
+        let motors = monitor.motors();
+        assert_eq!(motors.level, HealthLevel::Error);
+        assert_eq!(
+            motors.message,
+            "Main brush drawing 1200 mA, Left wheel overcurrent"
+        );
+    }
+
+    #[test]
+    fn charging_fault() {
+        let mut monitor = HealthMonitor::new(HealthThresholds::default());
+
+        monitor.update(&SensorData::ChargingState(ChargingState::Waiting));
+        assert_eq!(monitor.charging().level, HealthLevel::Ok);
+
+        monitor.update(&SensorData::ChargingState(
+            ChargingState::ChargingFaultCondition,
+        ));
+        assert_eq!(monitor.charging().level, HealthLevel::Error);
+    }
+}
@@ -0,0 +1,12 @@
+pub mod battery;
+pub mod docking;
+pub mod drive_watchdog;
+pub mod health;
+pub mod motion;
+pub mod odometry;
+pub mod publish_policy;
+pub mod roomba_interface;
+pub mod safety;
+pub mod sensor_geometry;
+pub mod seven_segment;
+pub mod virtual_roomba;
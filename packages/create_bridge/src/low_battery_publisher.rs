@@ -0,0 +1,96 @@
+use anyhow::{bail, Context, Result};
+use create_bridge::{
+    battery::{BatteryLevel, LowBatteryMonitor, LowBatteryThresholds},
+    roomba_interface::SensorData,
+};
+use r2r::{
+    create_bridge_interface::msg::LowBattery, std_msgs::msg::Header, Node, Publisher, QosProfile,
+};
+
+use crate::timestamp::Timestamp;
+
+/// Keeps an eye on the state of charge, so the robot can be sent home before it runs flat. Off
+/// unless `low_battery.enabled` is set.
+pub struct LowBatteryPublisher {
+    monitor: Option<LowBatteryMonitor>,
+    publisher: Publisher<LowBattery>,
+}
+
+impl LowBatteryPublisher {
+    pub fn new(node: &mut Node) -> Result<Self> {
+        let enabled: Option<bool> = node
+            .get_parameter("low_battery.enabled")
+            .context("Failed to get low battery setting.")?;
+        let low: Option<f64> = node
+            .get_parameter("low_battery.warn_threshold")
+            .context("Failed to get low battery warning threshold.")?;
+        let critical: Option<f64> = node
+            .get_parameter("low_battery.dock_threshold")
+            .context("Failed to get low battery dock threshold.")?;
+
+        let defaults = LowBatteryThresholds::default();
+        let thresholds = LowBatteryThresholds {
+            low: low.map_or(defaults.low, |low| low as f32),
+            critical: critical.map_or(defaults.critical, |critical| critical as f32),
+        };
+
+        if !(0.0..=1.0).contains(&thresholds.low) || !(0.0..=1.0).contains(&thresholds.critical) {
+            bail!("Low battery thresholds must be between 0 and 1.");
+        }
+        if thresholds.critical > thresholds.low {
+            bail!("The low battery dock threshold can't be above the warning threshold.");
+        }
+
+        Ok(Self {
+            monitor: enabled
+                .unwrap_or(false)
+                .then(|| LowBatteryMonitor::new(thresholds)),
+            publisher: node.create_publisher::<LowBattery>(
+                "battery/low",
+                QosProfile::default().transient_local(),
+            )?,
+        })
+    }
+
+    /// Returns the new level whenever it changes.
+    pub fn update(&mut self, data: &SensorData) -> Option<BatteryLevel> {
+        self.monitor.as_mut()?.update(data)
+    }
+
+    /// There's no point going looking for the dock when the robot is already on it.
+    pub fn on_charger(&self) -> bool {
+        self.monitor
+            .as_ref()
+            .is_some_and(|monitor| monitor.on_charger())
+    }
+
+    /// Whether the robot should be kept heading for its dock. Never, unless this is enabled.
+    pub fn needs_dock(&self) -> bool {
+        self.monitor
+            .as_ref()
+            .is_some_and(|monitor| monitor.needs_dock())
+    }
+
+    /// Announce the current level, along with what was done about it.
+    pub fn publish(&self, message: String, timestamp: &Timestamp) -> Result<()> {
+        let Some(monitor) = &self.monitor else {
+            return Ok(());
+        };
+
+        self.publisher.publish(&LowBattery {
+            header: Header {
+                stamp: timestamp.ros.clone(),
+                frame_id: String::new(),
+            },
+            level: monitor.level().into(),
+            state_of_charge: monitor.state_of_charge().unwrap_or(f32::NAN),
+            message,
+        })?;
+
+        Ok(())
+    }
+
+    pub fn state_of_charge(&self) -> Option<f32> {
+        self.monitor.as_ref()?.state_of_charge()
+    }
+}
@@ -1,25 +1,81 @@
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
 
-use anyhow::{Context, Result};
-use futures::stream::StreamExt;
+use anyhow::{bail, Context, Result};
+use battery_publisher::BatteryPublisher;
+use create_bridge::{
+    battery::BatteryLevel,
+    docking::DockingPhase,
+    drive_watchdog::DriveWatchdog,
+    roomba_interface::{
+        self, DriveCommand, LedState, MotorState, Note, OIMode, Roomba, SchedulingLedState, Sensor,
+        Song, StreamStatistics, TimeOfDay, TurnDirection, Weekday, WeeklySchedule,
+    },
+    safety::{Movement, SafetySupervisor},
+    seven_segment::ScrollingText,
+};
+use diagnostics_publisher::DiagnosticsPublisher;
+use dock_action::{ActiveDocking, DockSettings, DockingEvent};
+use dock_beam_publisher::DockBeamPublisher;
+use futures::stream::{FuturesUnordered, StreamExt};
+use low_battery_publisher::LowBatteryPublisher;
+use motion_actions::{ActiveMotion, MotionSettings, Outcome};
+use obstacle_publisher::ObstaclePublisher;
+use odometry_publisher::OdometryPublisher;
 use r2r::{
-    create_bridge_interface::msg::{DirectDrive, DriveArc, LEDState, SensorQuery},
-    std_msgs::msg::{Bool, Empty, Int16},
-    Node, QosProfile,
+    create_bridge_interface::{
+        action::{Dock, DriveDistance, RotateAngle},
+        msg::{
+            DayTime, DigitSegments, DirectDrive, DriveArc, LEDState, LinkStatus, MotorPWM, Motors,
+            OIMode as OIModeMessage, SchedulingLEDState, SensorQuery, SongDefinition,
+            WeeklySchedule as WeeklyScheduleMessage,
+        },
+        srv::QuerySensors,
+    },
+    std_msgs::msg::{Bool, Empty, Int16, UInt8},
+    ActionServerCancelRequest, Node, Publisher, QosProfile,
 };
-use roomba_interface::{DriveCommand, LedState, Roomba, TurnDirection};
-use sensors::SensorSet;
+use safety_publisher::SafetyPublisher;
+use sensors::SensorPublisher;
+use timestamp::Timestamper;
 use tokio::{
-    io::AsyncRead,
+    io::{ReadHalf, WriteHalf},
     signal::unix::{signal, SignalKind},
+    time::{sleep_until, Instant},
 };
-use tokio_serial::SerialStream;
+use tokio_serial::{SerialPortBuilder, SerialStream};
 
-pub mod roomba_interface;
+mod battery_publisher;
+mod diagnostics_publisher;
+mod dock_action;
+mod dock_beam_publisher;
+mod low_battery_publisher;
+mod motion_actions;
+mod obstacle_publisher;
+mod odometry_publisher;
+mod safety_publisher;
 mod sensors;
+mod timestamp;
+
+type SerialRoomba = Roomba<ReadHalf<SerialStream>, WriteHalf<SerialStream>>;
+
+/// How long to wait before trying to reconnect to the Roomba. This doubles with every failed
+/// attempt, up to the maximum.
+const RECONNECT_DELAY_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_DELAY_MAX: Duration = Duration::from_secs(30);
+
+/// How often diagnostics are published.
+const DIAGNOSTICS_PERIOD: Duration = Duration::from_secs(1);
+
+/// Why commands that would take control of the robot are turned away while the battery is
+/// critically low.
+const CRITICAL_BATTERY: &str =
+    "The battery is critically low, and the robot is on its way to its dock";
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -43,42 +99,54 @@ async fn main_trampoline(node: Node, log_name: &str) -> Result<()> {
     let baud_rate: Option<i64> = node
         .get_parameter("baud_rate")
         .context("Failed to get device baud rate.")?;
-    let baud_rate = baud_rate.unwrap_or(115200);
+    let baud_rate = baud_rate.unwrap_or(115200) as u32;
 
     r2r::log_info!(
         log_name,
         "Opening serial interface {serial_device} with baud rate {baud_rate}"
     );
 
-    let serial_config = tokio_serial::new(serial_device, baud_rate as u32);
-    let serial_interface =
-        SerialStream::open(&serial_config).context("Failed to open serial port")?;
-
-    let (read, write) = tokio::io::split(serial_interface);
-    let mut roomba = Roomba::new(read, write).await?;
-
-    r2r::log_info!(log_name, "Interface opened.");
+    let serial_config = tokio_serial::new(&serial_device, baud_rate);
 
-    if let Err(error) = roomba_trampoline(&mut roomba, node).await {
-        r2r::log_error!(log_name, "Fatal error: {error}");
-    }
+    roomba_trampoline(node, serial_config, baud_rate, serial_device).await
+}
 
-    roomba.close().await?;
+/// The `hardware_id` is used to tell this robot apart from others in diagnostics.
+async fn roomba_trampoline(
+    mut node: Node,
+    serial_config: SerialPortBuilder,
+    baud_rate: u32,
+    hardware_id: String,
+) -> Result<()> {
+    let log_name = node.logger().to_string();
 
-    Ok(())
-}
+    // The robot has 15ms to send each stream frame, and a byte takes 10 bits on the wire.
+    let stream_frame_budget = baud_rate as usize / 10 * 15 / 1000;
 
-async fn roomba_trampoline<R, W>(roomba: &mut Roomba<R, W>, mut node: Node) -> Result<()>
-where
-    R: AsyncRead + std::marker::Unpin + Send + 'static,
-    W: tokio::io::AsyncWrite + std::marker::Unpin,
-{
     let mut clean_service = node.subscribe::<Empty>("clean", QosProfile::default())?;
     let mut spot_clean_service = node.subscribe::<Empty>("spot_clean", QosProfile::default())?;
     let mut dock_service = node.subscribe::<Empty>("dock", QosProfile::default())?;
+    let mut dock_server = node.create_action_server::<Dock::Action>("dock")?;
+    let dock_settings = DockSettings::from_parameters(&node)?;
+
+    // The dock goal being carried out, if there is one.
+    let mut docking: Option<ActiveDocking> = None;
     let mut led_state = node.subscribe::<LEDState>("led_state", QosProfile::default())?;
     let mut display_text =
         node.subscribe::<r2r::std_msgs::msg::String>("display_text", QosProfile::default())?;
+    let mut display_segments =
+        node.subscribe::<DigitSegments>("display_segments", QosProfile::default())?;
+    let mut scheduling_led_state =
+        node.subscribe::<SchedulingLEDState>("scheduling_led_state", QosProfile::default())?;
+
+    let scroll_period: Option<i64> = node
+        .get_parameter("display_scroll_period_ms")
+        .context("Failed to get display scroll period.")?;
+    let scroll_period = Duration::from_millis(scroll_period.unwrap_or(400).max(1) as u64);
+
+    // Text too long for the display, and when to move it along next.
+    let mut scrolling_text: Option<ScrollingText> = None;
+    let mut next_scroll = Instant::now();
 
     let mut drive_straight = node.subscribe::<Int16>("drive/straight", QosProfile::default())?;
     let mut drive_left = node.subscribe::<Int16>("drive/left", QosProfile::default())?;
@@ -89,15 +157,90 @@ where
     let mut drive_stop = node.subscribe::<Empty>("drive/stop", QosProfile::default())?;
     let mut direct_drive = node.subscribe::<DirectDrive>("drive/direct", QosProfile::default())?;
 
+    let drive_timeout: Option<i64> = node
+        .get_parameter("drive_timeout_ms")
+        .context("Failed to get drive timeout.")?;
+
+    // Zero or less turns the watchdog off.
+    let drive_timeout = drive_timeout.unwrap_or(500);
+    let drive_timeout = (drive_timeout > 0).then(|| Duration::from_millis(drive_timeout as u64));
+
+    let mut drive_watchdog = DriveWatchdog::new(drive_timeout);
+    let drive_enabled =
+        node.create_publisher::<Bool>("drive/enabled", QosProfile::default().transient_local())?;
+    drive_enabled.publish(&Bool { data: true })?;
+
+    let mut drive_distance_server =
+        node.create_action_server::<DriveDistance::Action>("drive_distance")?;
+    let mut rotate_angle_server =
+        node.create_action_server::<RotateAngle::Action>("rotate_angle")?;
+    let motion_settings = MotionSettings::from_parameters(&node)?;
+
+    // The drive distance or rotate angle goal that's in control of the wheels, if there is one.
+    let mut motion: Option<ActiveMotion> = None;
+
+    // Full mode leaves the robot to drive off cliffs, so this can keep an eye out for it instead.
+    let mut safety = safety_publisher::safety_supervisor_from_parameters(&node)?;
+    let mut safety_status = SafetyPublisher::new(&mut node, &safety)?;
+
+    let mut motors = node.subscribe::<Motors>("motors", QosProfile::default())?;
+    let mut motor_pwm = node.subscribe::<MotorPWM>("motors/pwm", QosProfile::default())?;
+
+    let sync_clock: Option<bool> = node
+        .get_parameter("sync_clock")
+        .context("Failed to get clock sync setting.")?;
+    let sync_clock = sync_clock.unwrap_or(true);
+
+    let mut clock_set = node.subscribe::<DayTime>("clock/set", QosProfile::default())?;
+    let mut clock_sync = node.subscribe::<Empty>("clock/sync", QosProfile::default())?;
+    let mut schedule =
+        node.subscribe::<WeeklyScheduleMessage>("schedule", QosProfile::default())?;
+
+    let mut song_define = node.subscribe::<SongDefinition>("song/define", QosProfile::default())?;
+    let mut song_play = node.subscribe::<UInt8>("song/play", QosProfile::default())?;
+
     let mut sensor_query = node.subscribe::<SensorQuery>("sensor/query", QosProfile::default())?;
     let mut sensor_start_stream =
         node.subscribe::<SensorQuery>("sensor/start_stream", QosProfile::default())?;
     let mut sensor_pause = node.subscribe::<Bool>("sensor/pause", QosProfile::default())?;
+    let mut sensor_read_service =
+        node.create_service::<QuerySensors::Service>("sensor/read", QosProfile::default())?;
+
+    let initial_mode: Option<String> = node
+        .get_parameter("initial_mode")
+        .context("Failed to get initial OI mode.")?;
+    let initial_mode = match initial_mode.as_deref() {
+        None | Some("passive") => OIMode::Passive,
+        Some("safe") => OIMode::Safe,
+        Some("full") => OIMode::Full,
+        Some(mode) => bail!("Unknown initial OI mode {mode}. Expected passive, safe, or full."),
+    };
+
+    let mut mode_request = node.subscribe::<OIModeMessage>("mode", QosProfile::default())?;
+    let current_mode = node.create_publisher::<OIModeMessage>(
+        "mode/current",
+        QosProfile::default().transient_local(),
+    )?;
+
+    let link_status = node
+        .create_publisher::<LinkStatus>("link_status", QosProfile::default().transient_local())?;
 
-    let sensor_set = SensorSet::new(&mut node)?;
-    let mut sensor_stream = roomba
-        .take_sensor_stream()
-        .expect("Sensor stream was already taken");
+    let per_sensor_topics: Option<bool> = node
+        .get_parameter("per_sensor_topics")
+        .context("Failed to get per sensor topics setting.")?;
+    let per_sensor_topics = per_sensor_topics.unwrap_or(false);
+
+    let mut sensor_publisher = SensorPublisher::new(&mut node, per_sensor_topics)?;
+    let mut timestamper = Timestamper::new()?;
+    let mut odometry = OdometryPublisher::new(&mut node)?;
+    let mut battery = BatteryPublisher::new(&mut node)?;
+    let mut low_battery = LowBatteryPublisher::new(&mut node)?;
+    let obstacles = ObstaclePublisher::new(&mut node)?;
+    let dock_beams = DockBeamPublisher::new(&mut node)?;
+    let mut diagnostics = DiagnosticsPublisher::new(&mut node, hardware_id)?;
+    let mut diagnostics_timer = tokio::time::interval(DIAGNOSTICS_PERIOD);
+    let mut pending_queries = FuturesUnordered::new();
+    let mut pending_reads = FuturesUnordered::new();
 
     let shutdown = Arc::new(AtomicBool::new(false));
     let shutdown_view = shutdown.clone();
@@ -113,123 +256,1110 @@ where
     let mut sig_interrupt =
         signal(SignalKind::interrupt()).context("Failed to hook into interrupt signal.")?;
 
-    loop {
-        tokio::select! {
-            _ = sig_terminate.recv() => {
-                break;
-            }
-            _ = sig_interrupt.recv() => {
-                break;
+    let mut session = Session {
+        mode: Some(initial_mode),
+        ..Default::default()
+    };
+    let mut attempt = 0;
+    let mut retry_delay = RECONNECT_DELAY_MIN;
+
+    'connection: loop {
+        attempt += 1;
+        publish_link_status(
+            &link_status,
+            &mut diagnostics,
+            LinkStatus::CONNECTING,
+            attempt,
+            String::new(),
+        )?;
+
+        let connection = tokio::select! {
+            _ = sig_terminate.recv() => break,
+            _ = sig_interrupt.recv() => break,
+            connection = connect(&serial_config, &session, sync_clock) => connection,
+        };
+
+        let mut roomba = match connection {
+            Ok(roomba) => roomba,
+            Err(error) => {
+                r2r::log_warn!(
+                    &log_name,
+                    "Failed to connect to the Roomba, retrying in {}s: {error:#}",
+                    retry_delay.as_secs()
+                );
+                publish_link_status(
+                    &link_status,
+                    &mut diagnostics,
+                    LinkStatus::DISCONNECTED,
+                    attempt,
+                    format!("{error:#}"),
+                )?;
+
+                // Keep reporting the link as down while we wait.
+                let retry_at = Instant::now() + retry_delay;
+                loop {
+                    tokio::select! {
+                        _ = sig_terminate.recv() => break 'connection,
+                        _ = sig_interrupt.recv() => break 'connection,
+                        _ = sleep_until(retry_at) => break,
+                        _ = diagnostics_timer.tick() => {
+                            diagnostics.publish(StreamStatistics::default(), false)?;
+                        }
+                    }
+                }
+
+                retry_delay = (retry_delay * 2).min(RECONNECT_DELAY_MAX);
+                continue;
             }
+        };
+
+        r2r::log_info!(&log_name, "Interface opened.");
+        publish_link_status(
+            &link_status,
+            &mut diagnostics,
+            LinkStatus::CONNECTED,
+            attempt,
+            String::new(),
+        )?;
+        attempt = 0;
+        retry_delay = RECONNECT_DELAY_MIN;
+
+        let mut sensor_stream = roomba
+            .take_sensor_stream()
+            .expect("Sensor stream was already taken");
+
+        let mut reported_mode = roomba.mode();
+        current_mode.publish(&OIModeMessage {
+            mode: reported_mode.into(),
+        })?;
+
+        // Runs until we're asked to shut down, or something goes wrong.
+        let result: Result<()> = async {
+            loop {
+                let motion_stall = motion
+                    .as_ref()
+                    .map_or_else(Instant::now, ActiveMotion::stall_deadline);
+
+                tokio::select! {
+                    _ = sig_terminate.recv() => {
+                        return Ok(());
+                    }
+                    _ = sig_interrupt.recv() => {
+                        return Ok(());
+                    }
+
+                    _ = clean_service.next() => {
+                        reject_invalid_command(&log_name, roomba.clean().await)?;
+                    }
+                    _ = spot_clean_service.next() => {
+                        reject_invalid_command(&log_name, roomba.spot().await)?;
+                    }
+                    _ = dock_service.next() => {
+                        reject_invalid_command(&log_name, roomba.seek_dock().await)?;
+                    }
+                    goal_request = dock_server.next() => {
+                        let goal_request = goal_request.unwrap();
+
+                        match docking_unavailable(&roomba, &session) {
+                            Some(reason) => {
+                                r2r::log_warn!(&log_name, "Rejected dock goal: {reason}");
+                                goal_request.reject()?;
+                            }
+                            None => {
+                                abort_motion(&mut motion, "Interrupted by docking")?;
+                                abort_docking(&mut docking, "Replaced by a new goal")?;
+
+                                roomba.seek_dock().await?;
+                                docking = Some(ActiveDocking::new(goal_request, &dock_settings)?);
+                            }
+                        }
+                    }
+                    docking_event = next_docking_event(&mut docking) => {
+                        let Some(mut active) = docking.take() else {
+                            continue;
+                        };
+
+                        match docking_event {
+                            DockingEvent::CancelRequested(cancel_request) => {
+                                cancel_request.accept();
+
+                                // Once it's on the dock, there's nothing to stop.
+                                if active.phase() < DockingPhase::Docked {
+                                    stop_behavior(&mut roomba).await?;
+                                }
+
+                                active.finish(Outcome::Canceled)?;
+                            }
+                            DockingEvent::TimedOut if active.phase() >= DockingPhase::Docked => {
+                                active.finish(Outcome::Aborted("Docked, but the battery isn't charging".to_string()))?;
+                            }
+                            DockingEvent::TimedOut => {
+                                if active.retry()? {
+                                    r2r::log_warn!(&log_name, "Docking is taking too long, sending the robot looking again.");
+                                    roomba.seek_dock().await?;
+                                    docking = Some(active);
+                                } else if low_battery.needs_dock() {
+                                    // Giving up would leave the robot to run flat, so it keeps looking, whoever asked for it.
+                                    r2r::log_warn!(&log_name, "Still no dock, but the battery is critically low, so the robot keeps looking.");
+                                    active.finish(Outcome::Aborted("Couldn't find the dock in time".to_string()))?;
+                                    roomba.seek_dock().await?;
+                                    docking = Some(ActiveDocking::for_low_battery(&dock_settings));
+                                } else {
+                                    stop_behavior(&mut roomba).await?;
+                                    active.finish(Outcome::Aborted("Couldn't find the dock in time".to_string()))?;
+                                }
+                            }
+                        }
+                    }
+                    new_led_state = led_state.next() => {
+                        let new_led_state = new_led_state.unwrap();
+                        let new_led_state = LedState {
+                            check_robot: new_led_state.check_robot,
+                            dock: new_led_state.dock,
+                            spot: new_led_state.spot,
+                            debris: new_led_state.debris,
+                            power_color: new_led_state.power_color,
+                            power_intensity: new_led_state.power_intensity,
+                        };
+                        reject_invalid_command(&log_name, roomba.set_leds(new_led_state).await)?;
+                    }
+                    display_text = display_text.next() => {
+                        let display_text = display_text.unwrap();
+                        let mut text = ScrollingText::new(&display_text.data);
+
+                        // If it's rejected there's no point trying the rest of it.
+                        let shown = reject_invalid_command(&log_name, roomba.set_seven_segment(&text.next_frame()).await)?;
+                        scrolling_text = (shown.is_some() && text.scrolls()).then_some(text);
+                        next_scroll = Instant::now() + scroll_period;
+                    }
+                    _ = sleep_until(next_scroll), if scrolling_text.is_some() => {
+                        let frame = scrolling_text.as_mut().map(ScrollingText::next_frame).unwrap_or_default();
+                        next_scroll = Instant::now() + scroll_period;
+
+                        if reject_invalid_command(&log_name, roomba.set_seven_segment(&frame).await)?.is_none() {
+                            scrolling_text = None;
+                        }
+                    }
+                    digit_segments = display_segments.next() => {
+                        let digit_segments = digit_segments.unwrap();
+                        scrolling_text = None;
+
+                        let mut digits = [0u8; 4];
+                        for (digit, segments) in digits.iter_mut().zip(&digit_segments.digits) {
+                            *digit = *segments;
+                        }
+
+                        match roomba.set_digit_segments(digits).await {
+                            Err(error @ roomba_interface::Error::InvalidDigitSegments(_)) => {
+                                r2r::log_warn!(&log_name, "Rejected digit segments: {error}");
+                            }
+                            result => {
+                                reject_invalid_command(&log_name, result)?;
+                            }
+                        }
+                    }
+                    new_scheduling_led_state = scheduling_led_state.next() => {
+                        let new_scheduling_led_state = new_scheduling_led_state.unwrap();
+
+                        let mut days = [false; 7];
+                        for (day, lit) in days.iter_mut().zip(&new_scheduling_led_state.days) {
+                            *day = *lit;
+                        }
+
+                        let new_scheduling_led_state = SchedulingLedState {
+                            days,
+                            colon: new_scheduling_led_state.colon,
+                            pm: new_scheduling_led_state.pm,
+                            am: new_scheduling_led_state.am,
+                            clock: new_scheduling_led_state.clock,
+                            schedule: new_scheduling_led_state.schedule,
+                        };
+                        reject_invalid_command(&log_name, roomba.set_scheduling_leds(new_scheduling_led_state).await)?;
+                    }
+
+                    drive_straight = drive_straight.next() => {
+                        let drive_straight = drive_straight.unwrap();
+                        if reject_for_low_battery(&log_name, &low_battery) {
+                            continue;
+                        }
+
+                        feed_watchdog(&mut drive_watchdog, &drive_enabled, drive_straight.data != 0)?;
+                        abort_motion(&mut motion, "Interrupted by a drive command")?;
+
+                        reject_invalid_command(&log_name, roomba.drive(supervised(&log_name, &mut safety, DriveCommand::Straight(drive_straight.data))).await)?;
+                    }
+                    drive_left = drive_left.next() => {
+                        let drive_left = drive_left.unwrap();
+                        if reject_for_low_battery(&log_name, &low_battery) {
+                            continue;
+                        }
+
+                        // Values less than 0 are invalid, so we'll just default them to zero.
+                        let value = drive_left.data.max(0) as u16;
+                        feed_watchdog(&mut drive_watchdog, &drive_enabled, value != 0)?;
+                        abort_motion(&mut motion, "Interrupted by a drive command")?;
 
-            _ = clean_service.next() => {
-                roomba.clean().await?;
+                        reject_invalid_command(&log_name, roomba.drive(supervised(&log_name, &mut safety, DriveCommand::Turn(TurnDirection::Left(value)))).await)?;
+                    }
+                    drive_right = drive_right.next() => {
+                        let drive_right = drive_right.unwrap();
+                        if reject_for_low_battery(&log_name, &low_battery) {
+                            continue;
+                        }
+
+                        // Values less than 0 are invalid, so we'll just default them to zero.
+                        let value = drive_right.data.max(0) as u16;
+                        feed_watchdog(&mut drive_watchdog, &drive_enabled, value != 0)?;
+                        abort_motion(&mut motion, "Interrupted by a drive command")?;
+
+                        reject_invalid_command(&log_name, roomba.drive(supervised(&log_name, &mut safety, DriveCommand::Turn(TurnDirection::Right(value)))).await)?;
+                    }
+                    drive_arc_left = drive_arc_left.next() => {
+                        let drive_arc = drive_arc_left.unwrap();
+                        if reject_for_low_battery(&log_name, &low_battery) {
+                            continue;
+                        }
+
+                        let speed = drive_arc.speed;
+                        feed_watchdog(&mut drive_watchdog, &drive_enabled, speed != 0)?;
+                        abort_motion(&mut motion, "Interrupted by a drive command")?;
+
+                        // Values less than 0 are invalid, so we'll just default them to zero.
+                        let radius = drive_arc.radius.max(0);
+                        let radius = TurnDirection::Left(radius as u16);
+
+                        reject_invalid_command(&log_name, roomba.drive(supervised(&log_name, &mut safety, DriveCommand::Arc { radius, speed })).await)?;
+                    }
+                    drive_arc_right = drive_arc_right.next() => {
+                        let drive_arc = drive_arc_right.unwrap();
+                        if reject_for_low_battery(&log_name, &low_battery) {
+                            continue;
+                        }
+
+                        let speed = drive_arc.speed;
+                        feed_watchdog(&mut drive_watchdog, &drive_enabled, speed != 0)?;
+                        abort_motion(&mut motion, "Interrupted by a drive command")?;
+
+                        // Values less than 0 are invalid, so we'll just default them to zero.
+                        let radius = drive_arc.radius.max(0);
+                        let radius = TurnDirection::Right(radius as u16);
+
+                        reject_invalid_command(&log_name, roomba.drive(supervised(&log_name, &mut safety, DriveCommand::Arc { radius, speed })).await)?;
+                    }
+                    _ = drive_stop.next() => {
+                        feed_watchdog(&mut drive_watchdog, &drive_enabled, false)?;
+                        abort_motion(&mut motion, "Interrupted by a drive command")?;
+                        reject_invalid_command(&log_name, roomba.drive(supervised(&log_name, &mut safety, DriveCommand::Stop)).await)?;
+                    }
+                    direct_drive = direct_drive.next() => {
+                        let direct_drive = direct_drive.unwrap();
+                        if reject_for_low_battery(&log_name, &low_battery) {
+                            continue;
+                        }
+
+                        let moving = direct_drive.left_wheel_velocity != 0 || direct_drive.right_wheel_velocity != 0;
+                        feed_watchdog(&mut drive_watchdog, &drive_enabled, moving)?;
+                        abort_motion(&mut motion, "Interrupted by a drive command")?;
+
+                        let (left, right) = (direct_drive.left_wheel_velocity, direct_drive.right_wheel_velocity);
+                        let (left, right) = if vetoed(&log_name, &mut safety, Movement::of_direct_drive(left, right)) {
+                            (0, 0)
+                        } else {
+                            (left, right)
+                        };
+                        reject_invalid_command(&log_name, roomba.drive_direct(left, right).await)?;
+                    }
+                    goal_request = drive_distance_server.next() => {
+                        let goal_request = goal_request.unwrap();
+
+                        let reason = motion_unavailable(&roomba, &session, &low_battery).or_else(|| {
+                            (!goal_request.goal.distance.is_finite()).then_some("The distance must be a finite number")
+                        });
+                        match reason {
+                            Some(reason) => {
+                                r2r::log_warn!(&log_name, "Rejected drive distance goal: {reason}");
+                                goal_request.reject()?;
+                            }
+                            None => {
+                                let new_motion = ActiveMotion::drive_distance(goal_request, &motion_settings)?;
+                                start_motion(&mut roomba, &mut motion, new_motion, &mut safety, &mut drive_watchdog, &drive_enabled).await?;
+                            }
+                        }
+                    }
+                    goal_request = rotate_angle_server.next() => {
+                        let goal_request = goal_request.unwrap();
+
+                        let reason = motion_unavailable(&roomba, &session, &low_battery).or_else(|| {
+                            (!goal_request.goal.angle.is_finite()).then_some("The angle must be a finite number")
+                        });
+                        match reason {
+                            Some(reason) => {
+                                r2r::log_warn!(&log_name, "Rejected rotate angle goal: {reason}");
+                                goal_request.reject()?;
+                            }
+                            None => {
+                                let new_motion = ActiveMotion::rotate_angle(goal_request, &motion_settings)?;
+                                start_motion(&mut roomba, &mut motion, new_motion, &mut safety, &mut drive_watchdog, &drive_enabled).await?;
+                            }
+                        }
+                    }
+                    cancel_request = next_cancel_request(&mut motion) => {
+                        cancel_request.accept();
+                        stop_motion(&mut roomba, &mut motion, &mut safety, Outcome::Canceled).await?;
+                    }
+                    _ = sleep_until(motion_stall), if motion.is_some() => {
+                        // The encoders are the only way a motion knows when to stop.
+                        r2r::log_warn!(&log_name, "The motion stopped making progress, stopping the robot.");
+                        stop_motion(&mut roomba, &mut motion, &mut safety, Outcome::Aborted("No progress from the wheel encoders".to_string())).await?;
+                    }
+                    _ = drive_watchdog.expired() => {
+                        r2r::log_warn!(
+                            &log_name,
+                            "No drive commands for {}ms, stopping the robot.",
+                            drive_watchdog.timeout().unwrap_or_default().as_millis()
+                        );
+
+                        drive_watchdog.trip();
+
+                        // If we've lost control, the robot has already stopped on its own.
+                        if roomba.mode() >= OIMode::Safe {
+                            roomba.drive(DriveCommand::Stop).await?;
+                        }
+                        safety.stopped();
+                        drive_enabled.publish(&Bool { data: false })?;
+                    }
+                    motors = motors.next() => {
+                        let motors = motors.unwrap();
+                        let motors = MotorState {
+                            main_brush: motors.main_brush,
+                            main_brush_outward: motors.main_brush_outward,
+                            side_brush: motors.side_brush,
+                            side_brush_clockwise: motors.side_brush_clockwise,
+                            vacuum: motors.vacuum,
+                        };
+
+                        reject_invalid_command(&log_name, roomba.set_motors(motors).await)?;
+                    }
+                    motor_pwm = motor_pwm.next() => {
+                        let motor_pwm = motor_pwm.unwrap();
+
+                        match roomba.set_motor_pwm(motor_pwm.main_brush, motor_pwm.side_brush, motor_pwm.vacuum).await {
+                            Err(error @ roomba_interface::Error::MotorPwmRange) => {
+                                r2r::log_warn!(&log_name, "Rejected motor duty cycles: {error}");
+                            }
+                            result => {
+                                reject_invalid_command(&log_name, result)?;
+                            }
+                        }
+                    }
+                    day_time = clock_set.next() => {
+                        let day_time = day_time.unwrap();
+
+                        match day_time_from_ros_message(&day_time) {
+                            Ok((day, time)) => {
+                                reject_invalid_command(&log_name, roomba.set_day_time(day, time).await)?;
+                            }
+                            Err(error) => r2r::log_warn!(&log_name, "Rejected clock setting: {error:#}"),
+                        }
+                    }
+                    _ = clock_sync.next() => {
+                        match local_day_time() {
+                            Ok((day, time)) => {
+                                reject_invalid_command(&log_name, roomba.set_day_time(day, time).await)?;
+                            }
+                            Err(error) => r2r::log_warn!(&log_name, "Failed to sync the clock: {error:#}"),
+                        }
+                    }
+                    schedule = schedule.next() => {
+                        let schedule = schedule.unwrap();
+
+                        match schedule_from_ros_message(&schedule) {
+                            Ok(schedule) => {
+                                reject_invalid_command(&log_name, roomba.set_schedule(&schedule).await)?;
+                            }
+                            Err(error) => r2r::log_warn!(&log_name, "Rejected cleaning schedule: {error}"),
+                        }
+                    }
+                    song_define = song_define.next() => {
+                        let song_define = song_define.unwrap();
+
+                        // A bad song from a client shouldn't take down the whole bridge.
+                        match song_from_ros_message(&song_define) {
+                            Ok(song) => match roomba.define_song(song_define.song_number, &song).await {
+                                Err(error @ (roomba_interface::Error::InvalidSongNumber(_) | roomba_interface::Error::ModeTooLow { .. })) => {
+                                    r2r::log_warn!(&log_name, "Rejected song definition: {error}");
+                                }
+                                result => result?,
+                            },
+                            Err(error) => r2r::log_warn!(&log_name, "Rejected song definition: {error}"),
+                        }
+                    }
+                    song_play = song_play.next() => {
+                        let song_play = song_play.unwrap();
+
+                        match roomba.play_song(song_play.data).await {
+                            Err(error @ (roomba_interface::Error::InvalidSongNumber(_) | roomba_interface::Error::ModeTooLow { .. })) => {
+                                r2r::log_warn!(&log_name, "Rejected request to play song: {error}");
+                            }
+                            result => result?,
+                        }
+                    }
+                    sensor_query = sensor_query.next() => {
+                        let sensor_query = sensor_query.unwrap();
+
+                        let sensor_list = sensors::query_list_from_ros_message(&sensor_query);
+                        if let Some(response) = reject_invalid_command(&log_name, roomba.query_list(&sensor_list).await)? {
+                            pending_queries.push(response);
+                        }
+                    }
+                    query_response = pending_queries.next(), if !pending_queries.is_empty() => {
+                        // The results get published just like streamed sensor data.
+                        // Queries get cut off when the link goes down, but that gets dealt with elsewhere.
+                        let query_response = match query_response.unwrap() {
+                            Ok(query_response) => query_response,
+                            Err(error) => {
+                                r2r::log_warn!(&log_name, "Sensor query failed: {error}");
+                                continue;
+                            }
+                        };
+
+                        // Queries don't say when they were answered, so this is as close as we get.
+                        let timestamp = timestamper.stamp(Instant::now())?;
+
+                        for sensor_data in &query_response {
+                            battery.update(sensor_data, &timestamp)?;
+                        }
+                        diagnostics.update(&query_response);
+                        obstacles.publish(&query_response, &timestamp)?;
+                        dock_beams.publish(&query_response, &timestamp)?;
+                        sensor_publisher.publish(query_response, &timestamp)?;
+                    }
+                    read_request = sensor_read_service.next() => {
+                        let read_request = read_request.unwrap();
+
+                        // We can't wait on the response here without holding up the sensor stream,
+                        // so it gets answered once the Roomba replies.
+                        let sensor_list = sensors::query_list_from_ros_message(&read_request.message.sensors);
+                        match reject_invalid_command(&log_name, roomba.query_list(&sensor_list).await)? {
+                            Some(response) => pending_reads.push(async move { (read_request, response.await) }),
+                            None => {
+                                let response = QuerySensors::Response {
+                                    success: false,
+                                    readings: Default::default(),
+                                };
+
+                                read_request.respond(response).context("Failed to respond to sensor read")?;
+                            }
+                        }
+                    }
+                    read_response = pending_reads.next(), if !pending_reads.is_empty() => {
+                        let (read_request, read_response) = read_response.unwrap();
+
+                        let response = match read_response {
+                            Ok(sensor_data) => {
+                                diagnostics.update(&sensor_data);
+
+                                QuerySensors::Response {
+                                    success: true,
+                                    readings: sensors::readings_from_sensor_data(sensor_data),
+                                }
+                            }
+                            Err(error) => {
+                                r2r::log_warn!(&log_name, "Failed to read sensors: {error}");
+
+                                QuerySensors::Response {
+                                    success: false,
+                                    readings: Default::default(),
+                                }
+                            }
+                        };
+
+                        read_request.respond(response).context("Failed to respond to sensor read")?;
+                    }
+                    sensor_query = sensor_start_stream.next() => {
+                        let sensor_query = sensor_query.unwrap();
+
+                        let sensor_list = sensors::query_list_from_ros_message(&sensor_query);
+
+                        // Add the header, length and checksum.
+                        let frame_size = roomba_interface::frame_length(&sensor_list) + 3;
+                        if frame_size > stream_frame_budget {
+                            r2r::log_warn!(&log_name, "Rejected sensor stream with {frame_size} byte frames, when only {stream_frame_budget} bytes can be sent every 15ms at {baud_rate} baud");
+                        } else if reject_invalid_command(&log_name, roomba.start_stream(&sensor_list).await)?.is_some() {
+                            session.stream = Some(sensor_list);
+                            session.stream_paused = false;
+                            stop_blind_motion(&mut roomba, &mut motion, &mut safety, &session).await?;
+                        }
+                    }
+                    paused = sensor_pause.next() => {
+                        let paused = paused.unwrap();
+                        let paused = paused.data;
+
+                        if reject_invalid_command(&log_name, roomba.pause_stream(paused).await)?.is_some() {
+                            session.stream_paused = paused;
+                            stop_blind_motion(&mut roomba, &mut motion, &mut safety, &session).await?;
+                        }
+                    }
+                    mode = mode_request.next() => {
+                        let mode = mode.unwrap();
+
+                        match OIMode::try_from(mode.mode) {
+                            Ok(mode) => {
+                                // Taking control would keep the robot from getting back to its dock.
+                                if mode >= OIMode::Safe && reject_for_low_battery(&log_name, &low_battery) {
+                                    continue;
+                                }
+
+                                let restarting = roomba.mode() == OIMode::Off && mode == OIMode::Passive;
+
+                                let accepted = reject_invalid_command(&log_name, roomba.set_mode(mode).await)?.is_some();
+
+                                if accepted && restarting {
+                                    // The stream stopped along with the Open Interface.
+                                    session.restore(&mut roomba).await?;
+                                }
+
+                                // Taking control of the robot, or turning it off, stops it from
+                                // looking for the dock. Going by what we asked for rather than
+                                // what the robot reports keeps a stale report from a frame in
+                                // flight from aborting a dock goal that was just accepted.
+                                if accepted && mode != OIMode::Passive {
+                                    abort_docking(&mut docking, "Interrupted by a mode change")?;
+                                }
+                            }
+                            Err(_) => r2r::log_warn!(&log_name, "Rejected request for unknown OI mode {}", mode.mode),
+                        }
+                    }
+                    sensor_frame = sensor_stream.recv() => {
+                        // The reader only stops on its own when the link is gone.
+                        let sensor_frame =
+                            sensor_frame.ok_or(roomba_interface::Error::ReaderStopped)??;
+
+                        // Everything in a frame was read at the same time.
+                        let timestamp = timestamper.stamp(sensor_frame.received)?;
+
+                        for sensor_data in &sensor_frame.data {
+                            odometry.update(sensor_data, &timestamp)?;
+                            battery.update(sensor_data, &timestamp)?;
+                        }
+                        diagnostics.count_frame();
+                        diagnostics.update(&sensor_frame.data);
+                        obstacles.publish(&sensor_frame.data, &timestamp)?;
+                        dock_beams.publish(&sensor_frame.data, &timestamp)?;
+
+                        let outcome = match &mut motion {
+                            Some(motion) => motion.update(&sensor_frame.data)?,
+                            None => None,
+                        };
+                        if let Some(outcome) = outcome {
+                            stop_motion(&mut roomba, &mut motion, &mut safety, outcome).await?;
+                        }
+
+                        let hazard = sensor_frame.data.iter().filter_map(|data| safety.update(data)).last();
+                        if let Some(hazard) = hazard {
+                            r2r::log_warn!(&log_name, "Stopping the robot, because {hazard}.");
+                            stop_motion(&mut roomba, &mut motion, &mut safety, Outcome::Aborted(format!("Stopped for safety, because {hazard}"))).await?;
+                        }
+                        safety_status.publish(&safety, &timestamp)?;
+
+                        let battery_level = sensor_frame.data.iter().filter_map(|data| low_battery.update(data)).last();
+
+                        // For as long as the battery is critical, the robot keeps heading for its dock, whatever got in
+                        // the way of the last try.
+                        let unavailable = docking_unavailable(&roomba, &session);
+                        if low_battery.needs_dock() && docking.is_none() && unavailable.is_none() {
+                            abort_motion(&mut motion, "Interrupted by low battery")?;
+                            feed_watchdog(&mut drive_watchdog, &drive_enabled, false)?;
+                            safety.stopped();
+
+                            roomba.seek_dock().await?;
+                            docking = Some(ActiveDocking::for_low_battery(&dock_settings));
+                        }
+
+                        if let Some(level) = battery_level {
+                            let percentage = (low_battery.state_of_charge().unwrap_or_default() * 100.0).round();
+                            let message = match level {
+                                BatteryLevel::Ok => format!("Battery back up to {percentage}%"),
+                                BatteryLevel::Low => format!("Battery low at {percentage}%"),
+                                BatteryLevel::Critical if !low_battery.needs_dock() => {
+                                    format!("Battery critically low at {percentage}%")
+                                }
+                                BatteryLevel::Critical => match unavailable {
+                                    Some(reason) => format!("Battery critically low at {percentage}%, but the robot can't be sent to its dock. {reason}"),
+                                    None => format!("Battery critically low at {percentage}%, sending the robot to its dock"),
+                                },
+                            };
+
+                            match level {
+                                BatteryLevel::Ok => r2r::log_info!(&log_name, "{message}."),
+                                _ => r2r::log_warn!(&log_name, "{message}."),
+                            }
+                            low_battery.publish(message, &timestamp)?;
+                        }
+
+                        let docking_phase = match &mut docking {
+                            Some(docking) => docking.update(&sensor_frame.data)?,
+                            None => None,
+                        };
+                        match docking_phase {
+                            // The battery only charges in passive mode.
+                            Some(DockingPhase::Docked) => roomba.set_mode(OIMode::Passive).await?,
+                            Some(DockingPhase::Charging) => {
+                                if let Some(docked) = docking.take() {
+                                    docked.finish(Outcome::Succeeded)?;
+                                }
+                            }
+                            _ => {}
+                        }
+
+                        sensor_publisher.publish(sensor_frame.data, &timestamp)?;
+                    }
+                    _ = diagnostics_timer.tick() => {
+                        let streaming = session.stream.is_some() && !session.stream_paused;
+                        diagnostics.publish(roomba.stream_statistics(), streaming)?;
+                    }
+                }
+
+                roomba.flush().await?;
+
+                // The mode can change from a request, or from the robot deciding to on its own.
+                if roomba.mode() != reported_mode {
+                    reported_mode = roomba.mode();
+                    session.mode = Some(reported_mode);
+                    current_mode.publish(&OIModeMessage {
+                        mode: reported_mode.into(),
+                    })?;
+
+                    // A cliff or wheel drop in safe mode drops the robot back to passive, which
+                    // already stopped it.
+                    if reported_mode < OIMode::Safe {
+                        abort_motion(&mut motion, "The robot left safe mode")?;
+                    }
+                }
             }
-            _ = spot_clean_service.next() => {
-                roomba.spot().await?;
+        }
+        .await;
+
+        diagnostics.end_session(roomba.stream_statistics());
+        abort_motion(&mut motion, "Lost the connection to the robot")?;
+        abort_docking(&mut docking, "Lost the connection to the robot")?;
+
+        match result {
+            Ok(()) => {
+                roomba.close().await?;
+                break;
             }
-            _ = dock_service.next() => {
-                roomba.seek_dock().await?;
+            Err(error) if is_link_lost(&error) => {
+                r2r::log_warn!(&log_name, "Lost the link to the Roomba: {error:#}");
+                publish_link_status(
+                    &link_status,
+                    &mut diagnostics,
+                    LinkStatus::DISCONNECTED,
+                    attempt,
+                    format!("{error:#}"),
+                )?;
+
+                roomba.disconnect();
             }
-            new_led_state = led_state.next() => {
-                let new_led_state = new_led_state.unwrap();
-                let new_led_state = LedState {
-                    check_robot: new_led_state.check_robot,
-                    dock: new_led_state.dock,
-                    spot: new_led_state.spot,
-                    debris: new_led_state.debris,
-                    power_color: new_led_state.power_color,
-                    power_intensity: new_led_state.power_intensity,
-                };
-                roomba.set_leds(new_led_state).await?;
+            Err(error) => {
+                r2r::log_error!(&log_name, "Fatal error: {error}");
+                roomba.close().await?;
+                break;
             }
-            display_text = display_text.next() => {
-                let display_text = display_text.unwrap();
+        }
+    }
 
-                roomba.set_seven_segment(&display_text.data).await?;
-            }
+    shutdown.store(true, Ordering::SeqCst);
+    spin_handle.await.context("ROS spinner panicked")?;
+
+    Ok(())
+}
 
-            drive_straight = drive_straight.next() => {
-                let drive_straight = drive_straight.unwrap();
+/// What the bridge has asked of the Roomba, so that it can be put back the way it was after the
+/// link drops and we reconnect.
+#[derive(Default)]
+struct Session {
+    /// The mode last asked for, or that the robot went to by itself.
+    mode: Option<OIMode>,
+    stream: Option<Vec<Sensor>>,
+    stream_paused: bool,
+}
 
-                roomba.drive(DriveCommand::Straight(drive_straight.data)).await?;
+impl Session {
+    /// Whether `sensor` is part of the running sensor stream.
+    fn streams(&self, sensor: Sensor) -> bool {
+        match &self.stream {
+            Some(stream) if !self.stream_paused => {
+                stream.iter().any(|packet| packet.includes(sensor))
             }
-            drive_left = drive_left.next() => {
-                let drive_left = drive_left.unwrap();
+            _ => false,
+        }
+    }
+
+    async fn restore(&self, roomba: &mut SerialRoomba) -> Result<(), roomba_interface::Error> {
+        // The stream can't be started with the Open Interface off.
+        if let Some(mode) = self.mode.filter(|mode| *mode != OIMode::Off) {
+            roomba.set_mode(mode).await?;
+        }
 
-                // Values less than 0 are invalid, so we'll just default them to zero.
-                let value = drive_left.data.max(0) as u16;
+        if let Some(sensors) = &self.stream {
+            roomba.start_stream(sensors).await?;
 
-                roomba.drive(DriveCommand::Turn(TurnDirection::Left(value))).await?;
+            if self.stream_paused {
+                roomba.pause_stream(true).await?;
             }
-            drive_right = drive_right.next() => {
-                let drive_right = drive_right.unwrap();
+        }
 
-                // Values less than 0 are invalid, so we'll just default them to zero.
-                let value = drive_right.data.max(0) as u16;
+        roomba.flush().await
+    }
+}
 
-                roomba.drive(DriveCommand::Turn(TurnDirection::Right(value))).await?;
-            }
-            drive_arc_left = drive_arc_left.next() => {
-                let drive_arc = drive_arc_left.unwrap();
+/// Open the serial port, start up the Roomba, and pick up where the last session left off.
+/// The Roomba forgets the time whenever its battery is pulled, so if asked to, we set its clock
+/// from ours.
+async fn connect(
+    serial_config: &SerialPortBuilder,
+    session: &Session,
+    sync_clock: bool,
+) -> Result<SerialRoomba> {
+    let local_time = sync_clock.then(local_day_time).transpose()?;
 
-                let speed = drive_arc.speed;
+    let serial_interface =
+        SerialStream::open(serial_config).context("Failed to open serial port")?;
 
-                // Values less than 0 are invalid, so we'll just default them to zero.
-                let radius = drive_arc.radius.max(0);
-                let radius = TurnDirection::Left(radius as u16);
+    let (read, write) = tokio::io::split(serial_interface);
+    let mut roomba = Roomba::new(read, write).await?;
 
-                roomba.drive(DriveCommand::Arc { radius, speed }).await?;
-            }
-            drive_arc_right = drive_arc_right.next() => {
-                let drive_arc = drive_arc_right.unwrap();
+    if let Err(error) = session.restore(&mut roomba).await {
+        roomba.disconnect();
+        return Err(error).context("Failed to restore session");
+    }
 
-                let speed = drive_arc.speed;
+    // The session always leaves the robot in at least passive mode, so this can only fail from
+    // the link going down.
+    if let Some((day, time)) = local_time {
+        if let Err(error) = roomba.set_day_time(day, time).await {
+            roomba.disconnect();
+            return Err(error).context("Failed to set the clock");
+        }
+    }
 
-                // Values less than 0 are invalid, so we'll just default them to zero.
-                let radius = drive_arc.radius.max(0);
-                let radius = TurnDirection::Right(radius as u16);
+    Ok(roomba)
+}
 
-                roomba.drive(DriveCommand::Arc { radius, speed }).await?;
-            }
-            _ = drive_stop.next() => {
-                roomba.drive(DriveCommand::Stop).await?;
-            }
-            direct_drive = direct_drive.next() => {
-                let direct_drive = direct_drive.unwrap();
+/// Commands the robot isn't in the right mode for, or that ask for more sensors than fit in a
+/// request, are the client's mistake, so they get rejected with a warning instead of taking down
+/// the bridge. Returns `None` when the command was rejected.
+fn reject_invalid_command<T>(
+    log_name: &str,
+    result: Result<T, roomba_interface::Error>,
+) -> Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(
+            error @ (roomba_interface::Error::ModeTooLow { .. }
+            | roomba_interface::Error::TooManySensors(_)
+            | roomba_interface::Error::FrameTooLong(_)),
+        ) => {
+            r2r::log_warn!(log_name, "Rejected command: {error}");
+            Ok(None)
+        }
+        Err(error) => Err(error.into()),
+    }
+}
 
-                roomba.drive_direct(direct_drive.left_wheel_velocity, direct_drive.right_wheel_velocity).await?;
-            }
-            sensor_query = sensor_query.next() => {
-                let sensor_query = sensor_query.unwrap();
+/// Rejects commands that would take control of the robot, while it needs to get back to its dock.
+/// Returns whether it rejected the command.
+fn reject_for_low_battery(log_name: &str, low_battery: &LowBatteryPublisher) -> bool {
+    if !low_battery.needs_dock() {
+        return false;
+    }
 
-                let sensor_list = sensors::query_list_from_ros_message(&sensor_query);
-                roomba.query_list(&sensor_list).await?;
-            }
-            sensor_query = sensor_start_stream.next() => {
-                let sensor_query = sensor_query.unwrap();
+    r2r::log_warn!(log_name, "Rejected command: {CRITICAL_BATTERY}");
+    true
+}
 
-                let sensor_list = sensors::query_list_from_ros_message(&sensor_query);
-                roomba.start_stream(&sensor_list).await?;
-            }
-            paused = sensor_pause.next() => {
-                let paused = paused.unwrap();
-                let paused = paused.data;
+/// IO errors mean the serial link is gone, which we can recover from by reconnecting. So does
+/// the reader stopping, since that's what it does when it hits one, and a robot that stopped
+/// answering, which a fresh connection resets.
+fn is_link_lost(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<roomba_interface::Error>(),
+        Some(
+            roomba_interface::Error::IO(_)
+                | roomba_interface::Error::ReaderStopped
+                | roomba_interface::Error::Silent(_)
+        )
+    )
+}
 
-                roomba.pause_stream(paused).await?;
-            }
-            sensor_data = sensor_stream.recv() => {
-                let sensor_data = sensor_data.unwrap()?;
+/// Announce the state of the link, and keep diagnostics in the loop.
+fn publish_link_status(
+    link_status: &Publisher<LinkStatus>,
+    diagnostics: &mut DiagnosticsPublisher,
+    state: u8,
+    attempt: u32,
+    error: String,
+) -> Result<()> {
+    diagnostics.set_link_status(state, attempt, &error);
+    link_status.publish(&LinkStatus {
+        state,
+        attempt,
+        error,
+    })?;
 
-                sensor_set.publish(sensor_data)?;
-            }
+    Ok(())
+}
+
+/// Why a drive distance or rotate angle goal can't be carried out right now, if it can't.
+fn motion_unavailable(
+    roomba: &SerialRoomba,
+    session: &Session,
+    low_battery: &LowBatteryPublisher,
+) -> Option<&'static str> {
+    if low_battery.needs_dock() {
+        return Some(CRITICAL_BATTERY);
+    }
+
+    if roomba.mode() < OIMode::Safe {
+        return Some("The robot must be in safe or full mode");
+    }
+
+    if !session.streams(Sensor::LeftEncoderCounts) || !session.streams(Sensor::RightEncoderCounts) {
+        return Some("The sensor stream doesn't include the encoder counts");
+    }
+
+    None
+}
+
+/// Why a dock goal can't be carried out right now, if it can't.
+fn docking_unavailable(roomba: &SerialRoomba, session: &Session) -> Option<&'static str> {
+    if roomba.mode() == OIMode::Off {
+        return Some("The Open Interface is off");
+    }
+
+    if !session.streams(Sensor::ChargingSourcesAvailable) || !session.streams(Sensor::ChargingState)
+    {
+        return Some("The sensor stream doesn't include the charging sources and charging state");
+    }
+
+    None
+}
+
+/// Give up on docking when something else takes over the robot.
+fn abort_docking(docking: &mut Option<ActiveDocking>, reason: &str) -> Result<()> {
+    if let Some(docking) = docking.take() {
+        docking.finish(Outcome::Aborted(reason.to_string()))?;
+    }
+
+    Ok(())
+}
+
+/// Built in behaviors like seeking the dock keep going until we take control of the robot.
+async fn stop_behavior(roomba: &mut SerialRoomba) -> Result<()> {
+    // With the Open Interface off, nothing is running, and we can't take control anyway.
+    if roomba.mode() == OIMode::Off {
+        return Ok(());
+    }
+
+    roomba.set_mode(OIMode::Safe).await?;
+    roomba.drive(DriveCommand::Stop).await?;
+
+    Ok(())
+}
+
+/// Completes once something happens to the dock goal. Never completes if there isn't one.
+async fn next_docking_event(docking: &mut Option<ActiveDocking>) -> DockingEvent {
+    match docking {
+        Some(docking) => docking.next_event().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Hand the wheels over to a motion, replacing any that was already going.
+async fn start_motion(
+    roomba: &mut SerialRoomba,
+    motion: &mut Option<ActiveMotion>,
+    new_motion: ActiveMotion,
+    safety: &mut SafetySupervisor,
+    watchdog: &mut DriveWatchdog,
+    drive_enabled: &Publisher<Bool>,
+) -> Result<()> {
+    abort_motion(motion, "Replaced by a new goal")?;
+
+    // The motion keeps the robot going on its own, so the watchdog shouldn't stop it.
+    feed_watchdog(watchdog, drive_enabled, false)?;
+
+    if new_motion.is_done() {
+        roomba.drive(DriveCommand::Stop).await?;
+        return new_motion.finish(Outcome::Succeeded);
+    }
+
+    if let Err(hazard) = safety.check(Movement::of_drive(&new_motion.drive_command())) {
+        roomba.drive(DriveCommand::Stop).await?;
+        return new_motion.finish(Outcome::Aborted(format!(
+            "Not safe to move, because {hazard}"
+        )));
+    }
+
+    roomba.drive(new_motion.drive_command()).await?;
+    *motion = Some(new_motion);
+
+    Ok(())
+}
+
+/// Stop the robot, and report how the motion ended.
+async fn stop_motion(
+    roomba: &mut SerialRoomba,
+    motion: &mut Option<ActiveMotion>,
+    safety: &mut SafetySupervisor,
+    outcome: Outcome,
+) -> Result<()> {
+    // If we've lost control, the robot has already stopped on its own.
+    if roomba.mode() >= OIMode::Safe {
+        roomba.drive(DriveCommand::Stop).await?;
+    }
+    safety.stopped();
+
+    if let Some(motion) = motion.take() {
+        motion.finish(outcome)?;
+    }
+
+    Ok(())
+}
+
+/// Stop a motion that can't see the encoder counts anymore, since it would never find out it's done.
+async fn stop_blind_motion(
+    roomba: &mut SerialRoomba,
+    motion: &mut Option<ActiveMotion>,
+    safety: &mut SafetySupervisor,
+    session: &Session,
+) -> Result<()> {
+    if motion.is_some()
+        && (!session.streams(Sensor::LeftEncoderCounts)
+            || !session.streams(Sensor::RightEncoderCounts))
+    {
+        let reason = "The sensor stream no longer includes the encoder counts";
+        stop_motion(roomba, motion, safety, Outcome::Aborted(reason.to_string())).await?;
+    }
+
+    Ok(())
+}
+
+/// Give up on a motion when something else takes over the wheels. Whatever took over is
+/// responsible for the robot from here on.
+fn abort_motion(motion: &mut Option<ActiveMotion>, reason: &str) -> Result<()> {
+    if let Some(motion) = motion.take() {
+        motion.finish(Outcome::Aborted(reason.to_string()))?;
+    }
+
+    Ok(())
+}
+
+/// Whether the safety supervisor stops a drive command from going through, with a warning if it
+/// does.
+fn vetoed(log_name: &str, safety: &mut SafetySupervisor, movement: Movement) -> bool {
+    match safety.check(movement) {
+        Ok(()) => false,
+        Err(hazard) => {
+            r2r::log_warn!(log_name, "Vetoed drive command, because {hazard}.");
+            true
         }
+    }
+}
 
-        roomba.flush().await?;
+/// Swaps drive commands the safety supervisor vetoes for a stop.
+fn supervised(
+    log_name: &str,
+    safety: &mut SafetySupervisor,
+    command: DriveCommand,
+) -> DriveCommand {
+    if vetoed(log_name, safety, Movement::of_drive(&command)) {
+        DriveCommand::Stop
+    } else {
+        command
     }
+}
 
-    shutdown.store(true, Ordering::SeqCst);
-    spin_handle.await.context("ROS spinner panicked")?;
+/// Completes once the running motion is asked to cancel. Never completes if there isn't one.
+async fn next_cancel_request(motion: &mut Option<ActiveMotion>) -> ActionServerCancelRequest {
+    match motion {
+        Some(motion) => motion.cancel_requested().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Let the watchdog know a drive command arrived, and announce it if that re-enabled driving.
+fn feed_watchdog(
+    watchdog: &mut DriveWatchdog,
+    drive_enabled: &Publisher<Bool>,
+    moving: bool,
+) -> Result<()> {
+    if watchdog.feed(moving) {
+        drive_enabled.publish(&Bool { data: true })?;
+    }
 
     Ok(())
 }
+
+/// The day and time according to the host, in its local time zone.
+fn local_day_time() -> Result<(Weekday, TimeOfDay)> {
+    // SAFETY: `time` accepts a null pointer, and `localtime_r` only writes to the struct we give it.
+    let local = unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut local: libc::tm = std::mem::zeroed();
+
+        if libc::localtime_r(&now, &mut local).is_null() {
+            bail!("Failed to get the local time");
+        }
+
+        local
+    };
+
+    let day = Weekday::try_from(local.tm_wday as u8).context("Invalid day of the week")?;
+    let time = TimeOfDay::new(local.tm_hour as u8, local.tm_min as u8)?;
+
+    Ok((day, time))
+}
+
+fn day_time_from_ros_message(message: &DayTime) -> Result<(Weekday, TimeOfDay)> {
+    let day = Weekday::try_from(message.day)
+        .map_err(|_| anyhow::anyhow!("Invalid day of the week {}", message.day))?;
+    let time = TimeOfDay::new(message.hour, message.minute)?;
+
+    Ok((day, time))
+}
+
+fn schedule_from_ros_message(
+    message: &WeeklyScheduleMessage,
+) -> Result<WeeklySchedule, roomba_interface::Error> {
+    let mut schedule = WeeklySchedule::new();
+
+    // The days are in the same order the Roomba numbers them, starting from Sunday.
+    for (day, entry) in (0u8..)
+        .map_while(|day| Weekday::try_from(day).ok())
+        .zip(&message.days)
+    {
+        if entry.enabled {
+            schedule.set(day, Some(TimeOfDay::new(entry.hour, entry.minute)?));
+        }
+    }
+
+    Ok(schedule)
+}
+
+fn song_from_ros_message(message: &SongDefinition) -> Result<Song, roomba_interface::Error> {
+    let notes = message
+        .notes
+        .iter()
+        .map(|note| {
+            if note.pitch == 0 {
+                Ok(Note::rest(note.duration))
+            } else {
+                Note::new(note.pitch, note.duration)
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Song::new(notes)
+}
@@ -0,0 +1,311 @@
+//! Driving a set distance, or turning a set angle, by watching the wheel encoders.
+
+use crate::{
+    odometry::{WheelGeometry, WheelOdometry},
+    roomba_interface::{DriveCommand, SensorData, TurnDirection},
+    safety::Movement,
+};
+
+/// The fastest either wheel can go, in mm/s.
+const MAX_WHEEL_SPEED: f64 = 500.0;
+
+/// A movement with a set end.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Motion {
+    /// Drive in a straight line. The distance is in meters, and negative drives backwards.
+    /// The speed is in m/s.
+    Drive { distance: f64, speed: f64 },
+
+    /// Turn on the spot. The angle is in radians, counter clockwise. The speed is in rad/s.
+    Rotate { angle: f64, speed: f64 },
+}
+
+/// Why a motion had to stop early.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Obstruction {
+    Bump,
+    Cliff,
+    WheelDrop,
+}
+
+impl std::fmt::Display for Obstruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Bump => write!(f, "The robot bumped into something"),
+            Self::Cliff => write!(f, "The robot found a cliff"),
+            Self::WheelDrop => write!(f, "A wheel dropped"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MotionStatus {
+    /// Still going.
+    Moving,
+
+    /// Gone as far as it was asked to.
+    Done,
+
+    /// Something is in the way, so the robot should stop where it is.
+    Blocked(Obstruction),
+}
+
+/// Keeps track of how far a [`Motion`] has gone. It's up to the caller to send the drive command
+/// at the start, and to stop the robot once it's over.
+pub struct MotionController {
+    motion: Motion,
+    geometry: WheelGeometry,
+    odometry: WheelOdometry,
+    left_counts: Option<u16>,
+
+    /// How far it has gone so far, in meters or radians.
+    progress: f64,
+}
+
+impl MotionController {
+    pub fn new(motion: Motion, geometry: WheelGeometry) -> Self {
+        Self {
+            motion,
+            geometry,
+            odometry: WheelOdometry::new(geometry),
+            left_counts: None,
+            progress: 0.0,
+        }
+    }
+
+    /// The command that gets the robot moving in the right direction.
+    pub fn drive_command(&self) -> DriveCommand {
+        match self.motion {
+            Motion::Drive { distance, speed } => {
+                let speed = wheel_speed(speed) as i16;
+
+                DriveCommand::Straight(if distance < 0.0 { -speed } else { speed })
+            }
+            Motion::Rotate { angle, speed } => {
+                // The wheels go around a circle as wide as the wheel base.
+                let speed = wheel_speed(speed * self.geometry.wheel_base / 2.0);
+
+                DriveCommand::Turn(if angle < 0.0 {
+                    TurnDirection::Right(speed)
+                } else {
+                    TurnDirection::Left(speed)
+                })
+            }
+        }
+    }
+
+    /// How far it has to go in total, in meters or radians.
+    pub fn target(&self) -> f64 {
+        match self.motion {
+            Motion::Drive { distance, .. } => distance,
+            Motion::Rotate { angle, .. } => angle,
+        }
+    }
+
+    /// How far it has gone so far, in meters or radians.
+    pub fn progress(&self) -> f64 {
+        self.progress
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.progress.abs() >= self.target().abs()
+    }
+
+    /// Feed in sensor data as it arrives. Returns `None` for data that has nothing to do with
+    /// the motion.
+    pub fn update(&mut self, data: &SensorData) -> Option<MotionStatus> {
+        let (left_counts, right_counts) = match data {
+            SensorData::BumpersAndWheelDrops {
+                wheel_drop_left,
+                wheel_drop_right,
+                bumper_left,
+                bumper_right,
+            } => {
+                // Whatever was bumped into is only in the way going forward, so the robot can
+                // still back away from it.
+                let forward = Movement::of_drive(&self.drive_command()) == Movement::Forward;
+
+                return if *wheel_drop_left || *wheel_drop_right {
+                    Some(MotionStatus::Blocked(Obstruction::WheelDrop))
+                } else if (*bumper_left || *bumper_right) && forward {
+                    Some(MotionStatus::Blocked(Obstruction::Bump))
+                } else {
+                    None
+                };
+            }
+            SensorData::CliffLeft(true)
+            | SensorData::CliffFrontLeft(true)
+            | SensorData::CliffFrontRight(true)
+            | SensorData::CliffRight(true) => {
+                return Some(MotionStatus::Blocked(Obstruction::Cliff));
+            }
+
+            // Like with odometry, the left count shows up first.
+            SensorData::LeftEncoderCounts(counts) => {
+                self.left_counts = Some(*counts);
+                return None;
+            }
+            SensorData::RightEncoderCounts(counts) => (self.left_counts.take()?, *counts),
+            _ => return None,
+        };
+
+        if let Some(displacement) = self.odometry.update(left_counts, right_counts) {
+            self.progress += match self.motion {
+                Motion::Drive { .. } => displacement.distance,
+                Motion::Rotate { .. } => displacement.rotation,
+            };
+        }
+
+        Some(if self.is_done() {
+            MotionStatus::Done
+        } else {
+            MotionStatus::Moving
+        })
+    }
+}
+
+/// Converts a speed in m/s to what the robot accepts, making sure it's not so slow that it
+/// never arrives.
+fn wheel_speed(speed: f64) -> u16 {
+    (speed.abs() * 1000.0).round().clamp(1.0, MAX_WHEEL_SPEED) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::{FRAC_PI_2, PI};
+
+    fn ticks_for(distance: f64) -> u16 {
+        let geometry = WheelGeometry::default();
+        let tick_distance = PI * geometry.wheel_diameter / geometry.ticks_per_revolution;
+
+        (distance / tick_distance).round() as i16 as u16
+    }
+
+    fn counts(controller: &mut MotionController, left: u16, right: u16) -> Option<MotionStatus> {
+        assert_eq!(
+            controller.update(&SensorData::LeftEncoderCounts(left)),
+            None
+        );
+        controller.update(&SensorData::RightEncoderCounts(right))
+    }
+
+    #[test]
+    fn drive_distance() {
+        let mut controller = MotionController::new(
+            Motion::Drive {
+                distance: -0.5,
+                speed: 0.2,
+            },
+            WheelGeometry::default(),
+        );
+
+        assert_eq!(controller.drive_command(), DriveCommand::Straight(-200));
+        assert_eq!(
+            counts(&mut controller, 100, 100),
+            Some(MotionStatus::Moving)
+        );
+
+        let halfway = 100u16.wrapping_add(ticks_for(-0.25));
+        assert_eq!(
+            counts(&mut controller, halfway, halfway),
+            Some(MotionStatus::Moving)
+        );
+        assert!((controller.progress() + 0.25).abs() < 1e-3);
+
+        let end = 100u16.wrapping_add(ticks_for(-0.5));
+        assert_eq!(counts(&mut controller, end, end), Some(MotionStatus::Done));
+    }
+
+    #[test]
+    fn rotate_angle() {
+        let geometry = WheelGeometry::default();
+        let mut controller = MotionController::new(
+            Motion::Rotate {
+                angle: FRAC_PI_2,
+                speed: 1.0,
+            },
+            geometry,
+        );
+
+        assert_eq!(
+            controller.drive_command(),
+            DriveCommand::Turn(TurnDirection::Left(118))
+        );
+
+        counts(&mut controller, 0, 0);
+
+        // A tick past, since the encoders can't land exactly on it.
+        let wheel = ticks_for(FRAC_PI_2 * geometry.wheel_base / 2.0) + 1;
+        assert_eq!(
+            counts(&mut controller, 0u16.wrapping_sub(wheel), wheel),
+            Some(MotionStatus::Done)
+        );
+        assert!((controller.progress() - FRAC_PI_2).abs() < 1e-2);
+    }
+
+    #[test]
+    fn nowhere_to_go() {
+        let controller = MotionController::new(
+            Motion::Rotate {
+                angle: 0.0,
+                speed: 1.0,
+            },
+            WheelGeometry::default(),
+        );
+
+        assert!(controller.is_done());
+    }
+
+    #[test]
+    fn blocked() {
+        let mut controller = MotionController::new(
+            Motion::Drive {
+                distance: 1.0,
+                speed: 10.0,
+            },
+            WheelGeometry::default(),
+        );
+
+        // Too fast for the robot, so it gets slowed down.
+        assert_eq!(controller.drive_command(), DriveCommand::Straight(500));
+
+        assert_eq!(controller.update(&SensorData::CliffLeft(false)), None);
+        assert_eq!(
+            controller.update(&SensorData::CliffFrontRight(true)),
+            Some(MotionStatus::Blocked(Obstruction::Cliff))
+        );
+        assert_eq!(
+            controller.update(&SensorData::BumpersAndWheelDrops {
+                wheel_drop_left: false,
+                wheel_drop_right: false,
+                bumper_left: true,
+                bumper_right: false,
+            }),
+            Some(MotionStatus::Blocked(Obstruction::Bump))
+        );
+    }
+
+    #[test]
+    fn back_away_from_bump() {
+        let mut controller = MotionController::new(
+            Motion::Drive {
+                distance: -0.1,
+                speed: 0.2,
+            },
+            WheelGeometry::default(),
+        );
+
+        let bumped = SensorData::BumpersAndWheelDrops {
+            wheel_drop_left: false,
+            wheel_drop_right: false,
+            bumper_left: true,
+            bumper_right: true,
+        };
+        assert_eq!(controller.update(&bumped), None);
+
+        counts(&mut controller, 0, 0);
+        let end = ticks_for(-0.1);
+        assert_eq!(counts(&mut controller, end, end), Some(MotionStatus::Done));
+    }
+}
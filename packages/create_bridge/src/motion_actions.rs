@@ -0,0 +1,228 @@
+use anyhow::{bail, Context, Result};
+use create_bridge::{
+    motion::{Motion, MotionController, MotionStatus},
+    odometry::WheelGeometry,
+    roomba_interface::{DriveCommand, SensorData},
+};
+use futures::stream::{LocalBoxStream, StreamExt};
+use r2r::{
+    create_bridge_interface::action::{DriveDistance, RotateAngle},
+    ActionServerCancelRequest, ActionServerGoal, ActionServerGoalRequest, Node,
+};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::odometry_publisher::wheel_geometry_from_parameters;
+
+/// How fast to go when a goal doesn't say.
+pub struct MotionSettings {
+    geometry: WheelGeometry,
+
+    /// In m/s.
+    drive_speed: f64,
+
+    /// In rad/s.
+    rotate_speed: f64,
+
+    /// How long a motion can go without the encoders showing any progress before it's given up on.
+    stall_timeout: Duration,
+}
+
+impl MotionSettings {
+    pub fn from_parameters(node: &Node) -> Result<Self> {
+        let drive_speed: Option<f64> = node
+            .get_parameter("motion.drive_speed")
+            .context("Failed to get motion drive speed.")?;
+        let rotate_speed: Option<f64> = node
+            .get_parameter("motion.rotate_speed")
+            .context("Failed to get motion rotate speed.")?;
+        let stall_timeout: Option<i64> = node
+            .get_parameter("motion.stall_timeout_ms")
+            .context("Failed to get motion stall timeout.")?;
+
+        let stall_timeout = stall_timeout.unwrap_or(1000);
+        if stall_timeout <= 0 {
+            bail!("motion.stall_timeout_ms must be greater than zero, but is {stall_timeout}");
+        }
+
+        Ok(Self {
+            geometry: wheel_geometry_from_parameters(node)?,
+            drive_speed: drive_speed.unwrap_or(0.2),
+            rotate_speed: rotate_speed.unwrap_or(1.0),
+            stall_timeout: Duration::from_millis(stall_timeout as u64),
+        })
+    }
+}
+
+/// How a motion ended.
+pub enum Outcome {
+    Succeeded,
+    Canceled,
+    Aborted(String),
+}
+
+enum MotionGoal {
+    DriveDistance(ActionServerGoal<DriveDistance::Action>),
+    RotateAngle(ActionServerGoal<RotateAngle::Action>),
+}
+
+/// A `drive_distance` or `rotate_angle` goal that is being carried out.
+pub struct ActiveMotion {
+    controller: MotionController,
+    goal: MotionGoal,
+    cancel_requests: LocalBoxStream<'static, ActionServerCancelRequest>,
+
+    /// Without progress by then, the encoders have stopped coming in or the robot is stuck.
+    stall_deadline: Instant,
+    stall_timeout: Duration,
+}
+
+impl ActiveMotion {
+    pub fn drive_distance(
+        request: ActionServerGoalRequest<DriveDistance::Action>,
+        settings: &MotionSettings,
+    ) -> Result<Self> {
+        let speed = match request.goal.speed {
+            speed if speed > 0.0 => speed,
+            _ => settings.drive_speed,
+        };
+        let motion = Motion::Drive {
+            distance: request.goal.distance,
+            speed,
+        };
+
+        let (goal, cancel_requests) = request.accept()?;
+
+        Ok(Self {
+            controller: MotionController::new(motion, settings.geometry),
+            goal: MotionGoal::DriveDistance(goal),
+            cancel_requests: cancel_requests.boxed_local(),
+            stall_deadline: Instant::now() + settings.stall_timeout,
+            stall_timeout: settings.stall_timeout,
+        })
+    }
+
+    pub fn rotate_angle(
+        request: ActionServerGoalRequest<RotateAngle::Action>,
+        settings: &MotionSettings,
+    ) -> Result<Self> {
+        let speed = match request.goal.speed {
+            speed if speed > 0.0 => speed,
+            _ => settings.rotate_speed,
+        };
+        let motion = Motion::Rotate {
+            angle: request.goal.angle,
+            speed,
+        };
+
+        let (goal, cancel_requests) = request.accept()?;
+
+        Ok(Self {
+            controller: MotionController::new(motion, settings.geometry),
+            goal: MotionGoal::RotateAngle(goal),
+            cancel_requests: cancel_requests.boxed_local(),
+            stall_deadline: Instant::now() + settings.stall_timeout,
+            stall_timeout: settings.stall_timeout,
+        })
+    }
+
+    pub fn drive_command(&self) -> DriveCommand {
+        self.controller.drive_command()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.controller.is_done()
+    }
+
+    /// When the motion should be given up on, unless it makes some progress first.
+    pub fn stall_deadline(&self) -> Instant {
+        self.stall_deadline
+    }
+
+    /// Feed in a frame of sensor data. Publishes feedback while the motion is still going, and
+    /// returns how it ended once it's over. It's up to the caller to stop the robot and `finish`.
+    pub fn update(&mut self, data: &[SensorData]) -> Result<Option<Outcome>> {
+        let mut moved = false;
+        let progress = self.controller.progress();
+
+        for data in data {
+            match self.controller.update(data) {
+                None => {}
+                Some(MotionStatus::Moving) => moved = true,
+                Some(MotionStatus::Done) => return Ok(Some(Outcome::Succeeded)),
+                Some(MotionStatus::Blocked(obstruction)) => {
+                    return Ok(Some(Outcome::Aborted(obstruction.to_string())))
+                }
+            }
+        }
+
+        if self.controller.progress() != progress {
+            self.stall_deadline = Instant::now() + self.stall_timeout;
+        }
+
+        if moved {
+            let progress = self.controller.progress();
+            let remaining = self.controller.target() - progress;
+
+            match &self.goal {
+                MotionGoal::DriveDistance(goal) => {
+                    goal.publish_feedback(DriveDistance::Feedback {
+                        distance_travelled: progress,
+                        distance_remaining: remaining,
+                    })?;
+                }
+                MotionGoal::RotateAngle(goal) => {
+                    goal.publish_feedback(RotateAngle::Feedback {
+                        angle_turned: progress,
+                        angle_remaining: remaining,
+                    })?;
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Completes once someone asks for the goal to be canceled.
+    pub async fn cancel_requested(&mut self) -> ActionServerCancelRequest {
+        match self.cancel_requests.next().await {
+            Some(request) => request,
+            // Nobody is left to ask.
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Report how the motion ended.
+    pub fn finish(self, outcome: Outcome) -> Result<()> {
+        let progress = self.controller.progress();
+
+        match self.goal {
+            MotionGoal::DriveDistance(mut goal) => {
+                let result = |message| DriveDistance::Result {
+                    distance_travelled: progress,
+                    message,
+                };
+
+                match outcome {
+                    Outcome::Succeeded => goal.succeed(result(String::new()))?,
+                    Outcome::Canceled => goal.cancel(result(String::new()))?,
+                    Outcome::Aborted(message) => goal.abort(result(message))?,
+                }
+            }
+            MotionGoal::RotateAngle(mut goal) => {
+                let result = |message| RotateAngle::Result {
+                    angle_turned: progress,
+                    message,
+                };
+
+                match outcome {
+                    Outcome::Succeeded => goal.succeed(result(String::new()))?,
+                    Outcome::Canceled => goal.cancel(result(String::new()))?,
+                    Outcome::Aborted(message) => goal.abort(result(message))?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
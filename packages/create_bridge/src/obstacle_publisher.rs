@@ -0,0 +1,318 @@
+use anyhow::{bail, Context, Result};
+use create_bridge::{
+    roomba_interface::SensorData,
+    sensor_geometry::{LightBumpCalibration, SensorGeometry, SensorPlacement},
+};
+use r2r::{
+    geometry_msgs::msg::{Quaternion, Transform, TransformStamped, Vector3},
+    sensor_msgs::msg::{PointCloud2, PointField, Range},
+    std_msgs::msg::Header,
+    tf2_msgs::msg::TFMessage,
+    Clock, ClockType, Node, Publisher, QosProfile,
+};
+
+use crate::timestamp::Timestamp;
+
+/// Each point is an x, y and z as 32 bit floats.
+const POINT_STEP: u32 = 12;
+
+/// In the order their topics are kept in.
+const LIGHT_BUMPS: [&str; 6] = [
+    "light_bump_left",
+    "light_bump_front_left",
+    "light_bump_center_left",
+    "light_bump_center_right",
+    "light_bump_front_right",
+    "light_bump_right",
+];
+
+/// Translates the bumpers, cliff sensors and light bumpers into standard messages, so tools like
+/// costmaps can mark what the robot runs into. Bumps and cliffs become a `sensor_msgs/PointCloud2`
+/// of where they were found, and each light bump signal a `sensor_msgs/Range`.
+pub struct ObstaclePublisher {
+    geometry: SensorGeometry,
+    calibration: LightBumpCalibration,
+
+    /// The obstacle points are given in this frame.
+    frame_id: String,
+
+    obstacle_publisher: Publisher<PointCloud2>,
+
+    /// The frame and topic of each light bump, in the same order as `LIGHT_BUMPS`.
+    light_bumps: Vec<(String, Publisher<Range>)>,
+
+    /// Static transforms are only sent once, so the publisher has to stick around for anyone who
+    /// subscribes later.
+    _transform_publisher: Option<Publisher<TFMessage>>,
+}
+
+impl ObstaclePublisher {
+    pub fn new(node: &mut Node) -> Result<Self> {
+        let frame_id: Option<String> = node
+            .get_parameter("sensor_geometry.frame_id")
+            .context("Failed to get sensor geometry frame ID.")?;
+        let frame_id = frame_id.unwrap_or_else(|| String::from("base_link"));
+        let publish_tf: Option<bool> = node
+            .get_parameter("sensor_geometry.publish_tf")
+            .context("Failed to get sensor geometry transform setting.")?;
+
+        let defaults = SensorGeometry::default();
+        let geometry = SensorGeometry {
+            bumper_left: placement_from_parameter(node, "bumper_left", defaults.bumper_left)?,
+            bumper_right: placement_from_parameter(node, "bumper_right", defaults.bumper_right)?,
+            cliff_left: placement_from_parameter(node, "cliff_left", defaults.cliff_left)?,
+            cliff_front_left: placement_from_parameter(
+                node,
+                "cliff_front_left",
+                defaults.cliff_front_left,
+            )?,
+            cliff_front_right: placement_from_parameter(
+                node,
+                "cliff_front_right",
+                defaults.cliff_front_right,
+            )?,
+            cliff_right: placement_from_parameter(node, "cliff_right", defaults.cliff_right)?,
+            light_bump_left: placement_from_parameter(
+                node,
+                "light_bump_left",
+                defaults.light_bump_left,
+            )?,
+            light_bump_front_left: placement_from_parameter(
+                node,
+                "light_bump_front_left",
+                defaults.light_bump_front_left,
+            )?,
+            light_bump_center_left: placement_from_parameter(
+                node,
+                "light_bump_center_left",
+                defaults.light_bump_center_left,
+            )?,
+            light_bump_center_right: placement_from_parameter(
+                node,
+                "light_bump_center_right",
+                defaults.light_bump_center_right,
+            )?,
+            light_bump_front_right: placement_from_parameter(
+                node,
+                "light_bump_front_right",
+                defaults.light_bump_front_right,
+            )?,
+            light_bump_right: placement_from_parameter(
+                node,
+                "light_bump_right",
+                defaults.light_bump_right,
+            )?,
+        };
+
+        let transform_publisher = if publish_tf.unwrap_or(true) {
+            Some(publish_sensor_transforms(node, &frame_id, &geometry)?)
+        } else {
+            None
+        };
+
+        let calibration = light_bump_calibration_from_parameters(node)?;
+
+        let mut light_bumps = Vec::new();
+        for name in LIGHT_BUMPS {
+            light_bumps.push((
+                sensor_frame_id(node, name)?,
+                node.create_publisher::<Range>(&format!("range/{name}"), QosProfile::default())?,
+            ));
+        }
+
+        Ok(Self {
+            geometry,
+            calibration,
+            frame_id,
+            obstacle_publisher: node
+                .create_publisher::<PointCloud2>("obstacles", QosProfile::default())?,
+            light_bumps,
+            _transform_publisher: transform_publisher,
+        })
+    }
+
+    /// All the data should be from the same stream frame, so that bumps and cliffs found together
+    /// go out in the same cloud.
+    pub fn publish(&self, data: &[SensorData], timestamp: &Timestamp) -> Result<()> {
+        if let Some(points) = self.geometry.obstacle_points(data) {
+            self.obstacle_publisher.publish(&point_cloud(
+                Header {
+                    stamp: timestamp.ros.clone(),
+                    frame_id: self.frame_id.clone(),
+                },
+                &points,
+            ))?;
+        }
+
+        for data in data {
+            let (index, signal) = match data {
+                SensorData::LightBumpLeftSignal(signal) => (0, *signal),
+                SensorData::LightBumpFrontLeftSignal(signal) => (1, *signal),
+                SensorData::LightBumpCenterLeftSignal(signal) => (2, *signal),
+                SensorData::LightBumpCenterRightSignal(signal) => (3, *signal),
+                SensorData::LightBumpFrontRightSignal(signal) => (4, *signal),
+                SensorData::LightBumpRightSignal(signal) => (5, *signal),
+                _ => continue,
+            };
+
+            let (frame_id, publisher) = &self.light_bumps[index];
+            publisher.publish(&Range {
+                header: Header {
+                    stamp: timestamp.ros.clone(),
+                    frame_id: frame_id.clone(),
+                },
+                radiation_type: Range::INFRARED,
+                field_of_view: self.calibration.field_of_view as f32,
+                min_range: self.calibration.min_range as f32,
+                max_range: self.calibration.max_range as f32,
+                range: self.calibration.range(signal) as f32,
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+fn point_cloud(header: Header, points: &[[f64; 3]]) -> PointCloud2 {
+    let fields = ["x", "y", "z"]
+        .into_iter()
+        .enumerate()
+        .map(|(index, name)| PointField {
+            name: name.to_string(),
+            offset: index as u32 * 4,
+            datatype: PointField::FLOAT32,
+            count: 1,
+        })
+        .collect();
+
+    let data = points
+        .iter()
+        .flatten()
+        .flat_map(|coordinate| (*coordinate as f32).to_le_bytes())
+        .collect();
+
+    PointCloud2 {
+        header,
+        height: 1,
+        width: points.len() as u32,
+        fields,
+        is_bigendian: false,
+        point_step: POINT_STEP,
+        row_step: POINT_STEP * points.len() as u32,
+        data,
+        is_dense: true,
+    }
+}
+
+/// The sensors don't move, so their transforms only need to be sent once.
+fn publish_sensor_transforms(
+    node: &mut Node,
+    frame_id: &str,
+    geometry: &SensorGeometry,
+) -> Result<Publisher<TFMessage>> {
+    let mut clock = Clock::create(ClockType::RosTime)?;
+    let header = Header {
+        stamp: Clock::to_builtin_time(&clock.get_now()?),
+        frame_id: frame_id.to_string(),
+    };
+
+    let mut transforms = Vec::new();
+    for (name, placement) in geometry.placements() {
+        transforms.push(TransformStamped {
+            header: header.clone(),
+            child_frame_id: sensor_frame_id(node, name)?,
+            transform: Transform {
+                translation: Vector3 {
+                    x: placement.x,
+                    y: placement.y,
+                    z: placement.z,
+                },
+                rotation: Quaternion {
+                    x: 0.0,
+                    y: 0.0,
+                    z: (placement.yaw / 2.0).sin(),
+                    w: (placement.yaw / 2.0).cos(),
+                },
+            },
+        });
+    }
+
+    let publisher =
+        node.create_publisher::<TFMessage>("/tf_static", QosProfile::default().transient_local())?;
+    publisher.publish(&TFMessage { transforms })?;
+
+    Ok(publisher)
+}
+
+/// Each sensor gets its own frame, named after it unless told otherwise.
+fn sensor_frame_id(node: &Node, name: &str) -> Result<String> {
+    let frame_id: Option<String> = node
+        .get_parameter(&format!("sensor_geometry.{name}.frame_id"))
+        .with_context(|| format!("Failed to get {name} frame ID."))?;
+
+    Ok(frame_id.unwrap_or_else(|| name.to_string()))
+}
+
+/// Placements are given as `[x, y, z, yaw]`, in meters and radians.
+fn placement_from_parameter(
+    node: &Node,
+    name: &str,
+    default: SensorPlacement,
+) -> Result<SensorPlacement> {
+    let parameter = format!("sensor_geometry.{name}.placement");
+    let placement: Option<Vec<f64>> = node
+        .get_parameter(&parameter)
+        .with_context(|| format!("Failed to get {parameter}."))?;
+
+    match placement.as_deref() {
+        None => Ok(default),
+        Some(&[x, y, z, yaw]) => Ok(SensorPlacement { x, y, z, yaw }),
+        Some(placement) => bail!(
+            "{parameter} must have exactly 4 values, x, y, z and yaw, but has {}",
+            placement.len()
+        ),
+    }
+}
+
+fn light_bump_calibration_from_parameters(node: &Node) -> Result<LightBumpCalibration> {
+    let defaults = LightBumpCalibration::default();
+
+    let min_range: Option<f64> = node
+        .get_parameter("light_bump.min_range")
+        .context("Failed to get light bump minimum range.")?;
+    let max_range: Option<f64> = node
+        .get_parameter("light_bump.max_range")
+        .context("Failed to get light bump maximum range.")?;
+    let field_of_view: Option<f64> = node
+        .get_parameter("light_bump.field_of_view")
+        .context("Failed to get light bump field of view.")?;
+    let detection_signal: Option<i64> = node
+        .get_parameter("light_bump.detection_signal")
+        .context("Failed to get light bump detection signal.")?;
+
+    let calibration = LightBumpCalibration {
+        min_range: min_range.unwrap_or(defaults.min_range),
+        max_range: max_range.unwrap_or(defaults.max_range),
+        field_of_view: field_of_view.unwrap_or(defaults.field_of_view),
+        detection_signal: detection_signal
+            .map(|signal| signal.clamp(1, u16::MAX as i64) as u16)
+            .unwrap_or(defaults.detection_signal),
+    };
+
+    // Ranges get clamped between the two, which panics if they're the wrong way around.
+    let LightBumpCalibration {
+        min_range,
+        max_range,
+        field_of_view,
+        ..
+    } = calibration;
+    if !min_range.is_finite() || !max_range.is_finite() || min_range < 0.0 || min_range > max_range
+    {
+        bail!("light_bump.min_range and light_bump.max_range must satisfy 0 <= min_range <= max_range, but are {min_range} and {max_range}");
+    }
+    if !field_of_view.is_finite() || field_of_view <= 0.0 {
+        bail!("light_bump.field_of_view must be a positive number, but is {field_of_view}");
+    }
+
+    Ok(calibration)
+}
@@ -0,0 +1,200 @@
+//! Dead reckoning from the Roomba's wheel encoders.
+
+use std::f64::consts::PI;
+
+/// The measurements needed to turn encoder counts into distances.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WheelGeometry {
+    /// The distance between the wheels, in meters.
+    pub wheel_base: f64,
+
+    /// In meters.
+    pub wheel_diameter: f64,
+
+    pub ticks_per_revolution: f64,
+}
+
+impl Default for WheelGeometry {
+    /// The measurements of a Create 2, as given by the Open Interface spec.
+    fn default() -> Self {
+        Self {
+            wheel_base: 0.235,
+            wheel_diameter: 0.072,
+            ticks_per_revolution: 508.8,
+        }
+    }
+}
+
+impl WheelGeometry {
+    /// How far a wheel travels for a single encoder tick, in meters.
+    fn tick_distance(&self) -> f64 {
+        PI * self.wheel_diameter / self.ticks_per_revolution
+    }
+}
+
+/// Where the robot is, relative to where it was when odometry started.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Pose {
+    /// In meters.
+    pub x: f64,
+
+    /// In meters.
+    pub y: f64,
+
+    /// Counter clockwise, in radians.
+    pub heading: f64,
+}
+
+/// How far the robot moved between two sets of encoder counts.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Displacement {
+    /// Forward, in meters.
+    pub distance: f64,
+
+    /// Counter clockwise, in radians.
+    pub rotation: f64,
+}
+
+pub struct WheelOdometry {
+    geometry: WheelGeometry,
+    last_counts: Option<(u16, u16)>,
+    pose: Pose,
+}
+
+impl WheelOdometry {
+    pub fn new(geometry: WheelGeometry) -> Self {
+        Self {
+            geometry,
+            last_counts: None,
+            pose: Pose::default(),
+        }
+    }
+
+    pub fn pose(&self) -> Pose {
+        self.pose
+    }
+
+    /// Feed in the latest encoder counts. Both counts should come from the same sample.
+    /// Returns how far the robot moved since the last update, or `None` for the very first one,
+    /// since there is nothing to compare it to yet.
+    pub fn update(&mut self, left_counts: u16, right_counts: u16) -> Option<Displacement> {
+        let (last_left, last_right) = self.last_counts.replace((left_counts, right_counts))?;
+
+        // The counters are 16 bits and wrap around, so as long as we're called often enough that
+        // a wheel can't move more than half the range between calls, this gives the true change.
+        let left_ticks = left_counts.wrapping_sub(last_left) as i16;
+        let right_ticks = right_counts.wrapping_sub(last_right) as i16;
+
+        let left = left_ticks as f64 * self.geometry.tick_distance();
+        let right = right_ticks as f64 * self.geometry.tick_distance();
+
+        let displacement = Displacement {
+            distance: (left + right) / 2.0,
+            rotation: (right - left) / self.geometry.wheel_base,
+        };
+
+        // Assume we moved along the average heading over this step.
+        let heading = self.pose.heading + displacement.rotation / 2.0;
+        self.pose.x += displacement.distance * heading.cos();
+        self.pose.y += displacement.distance * heading.sin();
+        self.pose.heading = normalize_angle(self.pose.heading + displacement.rotation);
+
+        Some(displacement)
+    }
+}
+
+/// Wrap an angle into the range of -PI to PI.
+fn normalize_angle(angle: f64) -> f64 {
+    let angle = angle.rem_euclid(2.0 * PI);
+
+    if angle > PI {
+        angle - 2.0 * PI
+    } else {
+        angle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f64 = 1e-9;
+
+    fn ticks_for(distance: f64) -> u16 {
+        (distance / WheelGeometry::default().tick_distance()).round() as u16
+    }
+
+    #[test]
+    fn first_update_only_sets_reference() {
+        let mut odometry = WheelOdometry::new(WheelGeometry::default());
+
+        assert_eq!(odometry.update(1000, 2000), None);
+        assert_eq!(odometry.pose(), Pose::default());
+    }
+
+    #[test]
+    fn straight_line() {
+        let geometry = WheelGeometry::default();
+        let mut odometry = WheelOdometry::new(geometry);
+
+        odometry.update(0, 0);
+        let displacement = odometry.update(500, 500).unwrap();
+
+        let expected = 500.0 * geometry.tick_distance();
+        assert!((displacement.distance - expected).abs() < EPSILON);
+        assert!(displacement.rotation.abs() < EPSILON);
+
+        let pose = odometry.pose();
+        assert!((pose.x - expected).abs() < EPSILON);
+        assert!(pose.y.abs() < EPSILON);
+        assert!(pose.heading.abs() < EPSILON);
+    }
+
+    #[test]
+    fn turn_in_place() {
+        let geometry = WheelGeometry::default();
+        let mut odometry = WheelOdometry::new(geometry);
+
+        // A quarter turn counter clockwise means each wheel travels a quarter of the circle
+        // with the wheel base as its diameter.
+        let ticks = ticks_for(PI * geometry.wheel_base / 4.0);
+
+        odometry.update(0, 0);
+        odometry.update(0u16.wrapping_sub(ticks), ticks);
+
+        let pose = odometry.pose();
+        assert!(pose.x.abs() < EPSILON);
+        assert!(pose.y.abs() < EPSILON);
+        assert!((pose.heading - PI / 2.0).abs() < 0.01, "{}", pose.heading);
+    }
+
+    #[test]
+    fn counter_wrap_around() {
+        let mut odometry = WheelOdometry::new(WheelGeometry::default());
+
+        odometry.update(65_500, 65_500);
+        let forward = odometry.update(100, 100).unwrap();
+
+        odometry.update(100, 100);
+        let backward = odometry.update(65_500, 65_500).unwrap();
+
+        assert!(forward.distance > 0.0);
+        assert!((forward.distance + backward.distance).abs() < EPSILON);
+    }
+
+    #[test]
+    fn heading_stays_in_range() {
+        for (angle, expected) in [
+            (0.0, 0.0),
+            (PI / 2.0, PI / 2.0),
+            (3.0 * PI / 2.0, -PI / 2.0),
+            (-3.0 * PI / 2.0, PI / 2.0),
+            (4.0 * PI, 0.0),
+        ] {
+            assert!(
+                (normalize_angle(angle) - expected).abs() < EPSILON,
+                "{angle}"
+            );
+        }
+    }
+}
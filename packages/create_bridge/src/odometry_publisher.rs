@@ -0,0 +1,237 @@
+use anyhow::{bail, Context, Result};
+use create_bridge::{
+    odometry::{WheelGeometry, WheelOdometry},
+    roomba_interface::SensorData,
+};
+use r2r::{
+    geometry_msgs::msg::{
+        Point, Pose, PoseWithCovariance, Quaternion, Transform, TransformStamped, Twist,
+        TwistWithCovariance, Vector3,
+    },
+    nav_msgs::msg::Odometry,
+    std_msgs::msg::Header,
+    tf2_msgs::msg::TFMessage,
+    Node, Publisher, QosProfile,
+};
+use tokio::time::Instant;
+
+use crate::timestamp::Timestamp;
+
+/// Variances of x, y, z, roll, pitch and yaw. We only move in 2D, so the rest are huge.
+const DEFAULT_POSE_COVARIANCE: [f64; 6] = [1e-3, 1e-3, 1e6, 1e6, 1e6, 1e-2];
+
+/// Variances of the linear x, y, z, and angular x, y, z velocities.
+const DEFAULT_TWIST_COVARIANCE: [f64; 6] = [1e-3, 1e-3, 1e6, 1e6, 1e6, 1e-2];
+
+/// Turns the wheel encoder counts from the sensor stream into `nav_msgs/Odometry` and the
+/// matching transform. The encoder counts need to be part of the sensor stream for this to do
+/// anything.
+pub struct OdometryPublisher {
+    odometry: WheelOdometry,
+    left_counts: Option<u16>,
+    last_update: Option<Instant>,
+
+    frame_id: String,
+    child_frame_id: String,
+    pose_covariance: Vec<f64>,
+    twist_covariance: Vec<f64>,
+
+    odometry_publisher: Publisher<Odometry>,
+    transform_publisher: Option<Publisher<TFMessage>>,
+}
+
+impl OdometryPublisher {
+    pub fn new(node: &mut Node) -> Result<Self> {
+        let geometry = wheel_geometry_from_parameters(node)?;
+
+        let frame_id: Option<String> = node
+            .get_parameter("odometry.frame_id")
+            .context("Failed to get odometry frame ID.")?;
+        let child_frame_id: Option<String> = node
+            .get_parameter("odometry.child_frame_id")
+            .context("Failed to get odometry child frame ID.")?;
+        let publish_tf: Option<bool> = node
+            .get_parameter("odometry.publish_tf")
+            .context("Failed to get odometry transform setting.")?;
+
+        let pose_covariance =
+            covariance_from_parameter(node, "odometry.pose_covariance", DEFAULT_POSE_COVARIANCE)?;
+        let twist_covariance =
+            covariance_from_parameter(node, "odometry.twist_covariance", DEFAULT_TWIST_COVARIANCE)?;
+
+        let transform_publisher = if publish_tf.unwrap_or(true) {
+            Some(node.create_publisher::<TFMessage>("/tf", QosProfile::default())?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            odometry: WheelOdometry::new(geometry),
+            left_counts: None,
+            last_update: None,
+            frame_id: frame_id.unwrap_or_else(|| String::from("odom")),
+            child_frame_id: child_frame_id.unwrap_or_else(|| String::from("base_link")),
+            pose_covariance,
+            twist_covariance,
+            odometry_publisher: node.create_publisher::<Odometry>("odom", QosProfile::default())?,
+            transform_publisher,
+        })
+    }
+
+    /// The timestamp should be of the frame the data came in.
+    pub fn update(&mut self, data: &SensorData, timestamp: &Timestamp) -> Result<()> {
+        // The left count comes before the right in a stream frame, so we hold on to it until
+        // its partner shows up.
+        let (left_counts, right_counts) = match data {
+            SensorData::LeftEncoderCounts(counts) => {
+                self.left_counts = Some(*counts);
+                return Ok(());
+            }
+            SensorData::RightEncoderCounts(counts) => match self.left_counts.take() {
+                Some(left_counts) => (left_counts, *counts),
+                None => return Ok(()),
+            },
+            _ => return Ok(()),
+        };
+
+        let now = timestamp.monotonic;
+        let last_update = self.last_update.replace(now);
+
+        let Some(displacement) = self.odometry.update(left_counts, right_counts) else {
+            // This was the first reading, which we have nothing to compare to.
+            return Ok(());
+        };
+
+        let elapsed = last_update
+            .map(|last_update| now.duration_since(last_update).as_secs_f64())
+            .unwrap_or_default();
+        let (linear, angular) = if elapsed > 0.0 {
+            (
+                displacement.distance / elapsed,
+                displacement.rotation / elapsed,
+            )
+        } else {
+            (0.0, 0.0)
+        };
+
+        let pose = self.odometry.pose();
+        let position = Point {
+            x: pose.x,
+            y: pose.y,
+            z: 0.0,
+        };
+        let orientation = Quaternion {
+            x: 0.0,
+            y: 0.0,
+            z: (pose.heading / 2.0).sin(),
+            w: (pose.heading / 2.0).cos(),
+        };
+
+        let header = Header {
+            stamp: timestamp.ros.clone(),
+            frame_id: self.frame_id.clone(),
+        };
+
+        self.odometry_publisher.publish(&Odometry {
+            header: header.clone(),
+            child_frame_id: self.child_frame_id.clone(),
+            pose: PoseWithCovariance {
+                pose: Pose {
+                    position: position.clone(),
+                    orientation: orientation.clone(),
+                },
+                covariance: self.pose_covariance.clone(),
+            },
+            twist: TwistWithCovariance {
+                twist: Twist {
+                    linear: Vector3 {
+                        x: linear,
+                        y: 0.0,
+                        z: 0.0,
+                    },
+                    angular: Vector3 {
+                        x: 0.0,
+                        y: 0.0,
+                        z: angular,
+                    },
+                },
+                covariance: self.twist_covariance.clone(),
+            },
+        })?;
+
+        if let Some(transform_publisher) = &self.transform_publisher {
+            transform_publisher.publish(&TFMessage {
+                transforms: vec![TransformStamped {
+                    header,
+                    child_frame_id: self.child_frame_id.clone(),
+                    transform: Transform {
+                        translation: Vector3 {
+                            x: position.x,
+                            y: position.y,
+                            z: position.z,
+                        },
+                        rotation: orientation,
+                    },
+                }],
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The physical measurements of the robot. Defaults to those of a Create 2.
+pub fn wheel_geometry_from_parameters(node: &Node) -> Result<WheelGeometry> {
+    let defaults = WheelGeometry::default();
+
+    let wheel_base: Option<f64> = node
+        .get_parameter("wheel_base")
+        .context("Failed to get wheel base.")?;
+    let wheel_diameter: Option<f64> = node
+        .get_parameter("wheel_diameter")
+        .context("Failed to get wheel diameter.")?;
+    let ticks_per_revolution: Option<f64> = node
+        .get_parameter("ticks_per_revolution")
+        .context("Failed to get encoder ticks per revolution.")?;
+
+    let geometry = WheelGeometry {
+        wheel_base: wheel_base.unwrap_or(defaults.wheel_base),
+        wheel_diameter: wheel_diameter.unwrap_or(defaults.wheel_diameter),
+        ticks_per_revolution: ticks_per_revolution.unwrap_or(defaults.ticks_per_revolution),
+    };
+
+    // Each of these gets divided by, so anything else would wreck the odometry.
+    for (name, value) in [
+        ("wheel_base", geometry.wheel_base),
+        ("wheel_diameter", geometry.wheel_diameter),
+        ("ticks_per_revolution", geometry.ticks_per_revolution),
+    ] {
+        if !value.is_finite() || value <= 0.0 {
+            bail!("{name} must be a positive number, but is {value}");
+        }
+    }
+
+    Ok(geometry)
+}
+
+/// Covariance parameters are the six values along the diagonal of the matrix.
+fn covariance_from_parameter(node: &Node, name: &str, default: [f64; 6]) -> Result<Vec<f64>> {
+    let diagonal: Option<Vec<f64>> = node
+        .get_parameter(name)
+        .with_context(|| format!("Failed to get {name}."))?;
+    let diagonal = diagonal.unwrap_or_else(|| default.to_vec());
+
+    if diagonal.len() != 6 {
+        bail!(
+            "{name} must have exactly 6 values, but has {}",
+            diagonal.len()
+        );
+    }
+
+    let mut covariance = vec![0.0; 36];
+    for (index, variance) in diagonal.into_iter().enumerate() {
+        covariance[index * 6 + index] = variance;
+    }
+
+    Ok(covariance)
+}
@@ -0,0 +1,141 @@
+//! Deciding which sensor readings are worth sending out. The sensor stream repeats every value
+//! every 15ms, which is far more than most listeners need.
+
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PublishPolicy {
+    /// Every reading.
+    Always,
+
+    /// Only readings that differ from the last one published.
+    OnChange,
+
+    /// Readings that differ from the last one published, and the same reading again once it has
+    /// gone unpublished for this long, so listeners can tell the value is still current.
+    OnChangeWithHeartbeat(Duration),
+
+    /// At most one reading per period, whether it changed or not.
+    Decimate(Duration),
+}
+
+impl PublishPolicy {
+    /// Decimate down to `rate` readings per second. Rates that aren't a positive number make no
+    /// sense, so they give `None`.
+    pub fn decimate(rate: f64) -> Option<Self> {
+        (rate.is_finite() && rate > 0.0)
+            .then(|| Self::Decimate(Duration::from_secs_f64(1.0 / rate)))
+    }
+}
+
+/// Applies a [`PublishPolicy`] to the readings of one sensor, remembering the last one published.
+#[derive(Debug)]
+pub struct PublishFilter<T> {
+    policy: PublishPolicy,
+    last_published: Option<(T, Instant)>,
+}
+
+impl<T: PartialEq + Clone> PublishFilter<T> {
+    pub fn new(policy: PublishPolicy) -> Self {
+        Self {
+            policy,
+            last_published: None,
+        }
+    }
+
+    /// Call for every reading, with the time it was taken. Returns true if it should be published.
+    pub fn filter(&mut self, value: &T, now: Instant) -> bool {
+        let Some((last_value, last_time)) = &self.last_published else {
+            // The first reading always goes out, so listeners have something to start from.
+            self.last_published = Some((value.clone(), now));
+            return true;
+        };
+
+        let since_last = now.saturating_duration_since(*last_time);
+        let (publish, published_at) = match self.policy {
+            PublishPolicy::Always => (true, now),
+            PublishPolicy::OnChange => (last_value != value, now),
+            PublishPolicy::OnChangeWithHeartbeat(heartbeat) => {
+                (last_value != value || since_last >= heartbeat, now)
+            }
+            PublishPolicy::Decimate(period) => {
+                // Readings only come in at the stream's pace, so they rarely land exactly on the
+                // period. Counting from when each one was due rather than when it went out stops
+                // the rate from drifting low. If we've fallen a whole period behind, start over.
+                let due = *last_time + period;
+                let published_at = if since_last < period * 2 { due } else { now };
+                (since_last >= period, published_at)
+            }
+        };
+
+        if publish {
+            self.last_published = Some((value.clone(), published_at));
+        }
+
+        publish
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: Duration = Duration::from_millis(15);
+
+    /// Run a filter over a reading per frame, and collect the indices of the ones it let through.
+    fn published(policy: PublishPolicy, readings: &[u8]) -> Vec<usize> {
+        let mut filter = PublishFilter::new(policy);
+        let start = Instant::now();
+
+        readings
+            .iter()
+            .enumerate()
+            .filter(|(index, reading)| filter.filter(*reading, start + FRAME * *index as u32))
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    #[test]
+    fn always() {
+        assert_eq!(
+            published(PublishPolicy::Always, &[1, 1, 1, 2]),
+            [0, 1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn on_change() {
+        assert_eq!(
+            published(PublishPolicy::OnChange, &[1, 1, 2, 2, 2, 1, 1]),
+            [0, 2, 5]
+        );
+    }
+
+    #[test]
+    fn on_change_with_heartbeat() {
+        let policy = PublishPolicy::OnChangeWithHeartbeat(FRAME * 3);
+
+        assert_eq!(
+            published(policy, &[1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2]),
+            [0, 3, 4, 7, 10]
+        );
+    }
+
+    #[test]
+    fn decimate() {
+        // 15ms frames decimated to 20Hz, which is one every three and a third frames.
+        let policy = PublishPolicy::decimate(20.0).unwrap();
+
+        assert_eq!(published(policy, &[0; 21]), [0, 4, 7, 10, 14, 17, 20]);
+    }
+
+    #[test]
+    fn invalid_rates() {
+        assert_eq!(PublishPolicy::decimate(0.0), None);
+        assert_eq!(PublishPolicy::decimate(-5.0), None);
+        assert_eq!(PublishPolicy::decimate(f64::NAN), None);
+        assert_eq!(PublishPolicy::decimate(f64::INFINITY), None);
+    }
+}
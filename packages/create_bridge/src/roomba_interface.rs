@@ -1,12 +1,21 @@
 use num_enum::{IntoPrimitive, TryFromPrimitive};
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
 use thiserror::Error;
+
+use crate::seven_segment::{display_character, ALL_SEGMENTS, DISPLAY_WIDTH};
 use tokio::{
     io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
-    sync::{mpsc, Notify},
+    sync::{mpsc, oneshot, Notify},
     task,
     task::JoinHandle,
-    time::sleep,
+    time::{sleep, sleep_until, Instant},
 };
 
 #[derive(Error, Debug)]
@@ -20,6 +29,9 @@ pub enum Error {
     #[error("Requested a drive command with the speed or arch angle out of valid range.")]
     DriveRange,
 
+    #[error("Motor duty cycles must be between -127 and 127, and the vacuum can't run backwards.")]
+    MotorPwmRange,
+
     #[error("Roomba sent data for an unsupported sensor type: {0}")]
     InvalidSensorTypeID(u8),
 
@@ -31,6 +43,42 @@ pub enum Error {
 
     #[error("Unexpected end of message.")]
     UnexpectedEnd,
+
+    #[error("The sensor reader stopped before the query could be answered.")]
+    QueryAbandoned,
+
+    #[error("The sensor reader stopped.")]
+    ReaderStopped,
+
+    #[error("Nothing was heard from the Roomba for {0:?} while it should have been streaming.")]
+    Silent(Duration),
+
+    #[error("The Roomba didn't answer the query within {0:?}.")]
+    QueryTimeout(Duration),
+
+    #[error("Note pitch {0} is out of range. Valid pitches are 31 through 127.")]
+    InvalidNotePitch(u8),
+
+    #[error("Songs must have between 1 and 16 notes, but {0} were given.")]
+    InvalidSongLength(usize),
+
+    #[error("Invalid song number: {0}. Valid song numbers are 0 through 3.")]
+    InvalidSongNumber(u8),
+
+    #[error("Invalid digit segments: {0:#04x}. Only the lowest 7 bits are segments.")]
+    InvalidDigitSegments(u8),
+
+    #[error("Invalid time of day: {hour}:{minute:02}")]
+    InvalidTime { hour: u8, minute: u8 },
+
+    #[error("Asked for {0} sensors, but at most 255 can be asked for at once.")]
+    TooManySensors(usize),
+
+    #[error("A stream frame of these sensors would have {0} bytes of data, but frames can hold at most 255.")]
+    FrameTooLong(usize),
+
+    #[error("This command needs the robot to be in {required:?} mode or higher, but it is in {current:?} mode.")]
+    ModeTooLow { required: OIMode, current: OIMode },
 }
 
 pub struct Roomba<
@@ -40,11 +88,43 @@ pub struct Roomba<
     write_stream: WriteStream,
     _read_stream: std::marker::PhantomData<ReadStream>,
     _sensor_task: Option<JoinHandle<()>>,
-    sensor_rx: Option<mpsc::Receiver<Result<SensorData, Error>>>,
+    sensor_rx: Option<mpsc::Receiver<Result<SensorFrame, Error>>>,
+    query_tx: mpsc::UnboundedSender<PendingQuery>,
+    stream_tx: mpsc::UnboundedSender<StreamChange>,
     shutdown_notice: Arc<Notify>,
+    mode: Arc<Mutex<ModeTracker>>,
+    stream_statistics: Arc<Mutex<StreamStatistics>>,
+
+    /// Whether the robot is sending stream frames, as far as we've asked it to.
+    stream_running: bool,
+    disconnected: bool,
 }
 
-#[derive(Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive)]
+/// The sensor data from one frame of a sensor stream. The Roomba reads all of them at once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SensorFrame {
+    /// When the start of the frame arrived. The data can sit in a queue for a while after this, so
+    /// use this rather than when you got the frame.
+    pub received: Instant,
+
+    /// In the order the sensors were asked for in.
+    pub data: Vec<SensorData>,
+}
+
+/// How well the sensor stream has been coming through, counted from when the `Roomba` was created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamStatistics {
+    /// Frames thrown away because their checksum didn't add up.
+    pub checksum_failures: u64,
+
+    /// How many times we lost track of where frames start, and had to go looking for the next one.
+    pub resyncs: u64,
+
+    /// Sensor packets lost along with the frames that were thrown away.
+    pub dropped_packets: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, IntoPrimitive, TryFromPrimitive)]
 #[repr(u8)]
 pub enum Sensor {
     BumpersAndWheelDrops = 7,
@@ -56,7 +136,7 @@ pub enum Sensor {
     VirtualWall = 13,
     WheelOvercurrents = 14,
     DirtDetect = 15,
-    InfraredCharacterOmni = 16,
+    InfraredCharacterOmni = 17,
     InfraredCharacterLeft = 52,
     InfraredCharacterRight = 53,
     Buttons = 18,
@@ -96,9 +176,124 @@ pub enum Sensor {
     MainBrushMotorCurrent = 56,
     SideBrushMotorCurrent = 57,
     IsMovingForward = 58,
+
+    // Group packets, which get the data of a range of sensors all at once.
+    /// Packets 7 through 26.
+    Group0 = 0,
+    /// Packets 7 through 16.
+    Group1 = 1,
+    /// Packets 17 through 20.
+    Group2 = 2,
+    /// Packets 21 through 26.
+    Group3 = 3,
+    /// Packets 27 through 34.
+    Group4 = 4,
+    /// Packets 35 through 42.
+    Group5 = 5,
+    /// Packets 7 through 42.
+    Group6 = 6,
+    /// Every sensor, packets 7 through 58.
+    Group100 = 100,
+    /// Packets 43 through 58.
+    Group101 = 101,
+    /// The light bump signals, packets 46 through 51.
+    Group106 = 106,
+    /// Packets 54 through 58.
+    Group107 = 107,
+}
+
+/// One of the packets that make up a group packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum GroupMember {
+    Sensor(Sensor),
+
+    /// A packet ID the Create 2 doesn't use. It still takes up this many bytes in the group.
+    Unused(usize),
+}
+
+impl Sensor {
+    /// The packets a group packet is made of, in the order the Roomba sends them.
+    /// Returns `None` if this isn't a group packet.
+    pub(crate) fn group_members(self) -> Option<impl Iterator<Item = GroupMember>> {
+        let packet_ids = match self {
+            Sensor::Group0 => 7..=26,
+            Sensor::Group1 => 7..=16,
+            Sensor::Group2 => 17..=20,
+            Sensor::Group3 => 21..=26,
+            Sensor::Group4 => 27..=34,
+            Sensor::Group5 => 35..=42,
+            Sensor::Group6 => 7..=42,
+            Sensor::Group100 => 7..=58,
+            Sensor::Group101 => 43..=58,
+            Sensor::Group106 => 46..=51,
+            Sensor::Group107 => 54..=58,
+            _ => return None,
+        };
+
+        Some(
+            packet_ids.map(|packet_id| match Sensor::try_from_primitive(packet_id) {
+                Ok(sensor) => GroupMember::Sensor(sensor),
+                // Packets 16 and 32 are one byte, and 33 is two.
+                Err(_) if packet_id == 33 => GroupMember::Unused(2),
+                Err(_) => GroupMember::Unused(1),
+            }),
+        )
+    }
+
+    /// Whether asking for this packet gets the data of `sensor`, either because it is that sensor,
+    /// or because it's a group with that sensor in it.
+    pub fn includes(self, sensor: Sensor) -> bool {
+        match self.group_members() {
+            Some(mut members) => members.any(|member| member == GroupMember::Sensor(sensor)),
+            None => self == sensor,
+        }
+    }
+
+    /// The number of bytes the Roomba sends for this sensor's data.
+    pub fn data_length(self) -> usize {
+        if let Some(members) = self.group_members() {
+            return members
+                .map(|member| match member {
+                    GroupMember::Sensor(sensor) => sensor.data_length(),
+                    GroupMember::Unused(length) => length,
+                })
+                .sum();
+        }
+
+        match self {
+            Sensor::Distance
+            | Sensor::Angle
+            | Sensor::Voltage
+            | Sensor::Current
+            | Sensor::BatteryCharge
+            | Sensor::BatteryCapacity
+            | Sensor::WallSignal
+            | Sensor::CliffLeftSignal
+            | Sensor::CliffFrontLeftSignal
+            | Sensor::CliffFrontRightSignal
+            | Sensor::CliffRightSignal
+            | Sensor::RequestedVelocity
+            | Sensor::RequestedRadius
+            | Sensor::RequestedRightVelocity
+            | Sensor::RequestedLeftVelocity
+            | Sensor::LeftEncoderCounts
+            | Sensor::RightEncoderCounts
+            | Sensor::LightBumpLeftSignal
+            | Sensor::LightBumpFrontLeftSignal
+            | Sensor::LightBumpCenterLeftSignal
+            | Sensor::LightBumpCenterRightSignal
+            | Sensor::LightBumpFrontRightSignal
+            | Sensor::LightBumpRightSignal
+            | Sensor::LeftMotorCurrent
+            | Sensor::RightMotorCurrent
+            | Sensor::MainBrushMotorCurrent
+            | Sensor::SideBrushMotorCurrent => 2,
+            _ => 1,
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum SensorData {
     BumpersAndWheelDrops {
         wheel_drop_left: bool,
@@ -119,9 +314,9 @@ pub enum SensorData {
         side_brush: bool,
     },
     DirtDetect(u8),
-    InfraredCharacterOmni(u8),
-    InfraredCharacterLeft(u8),
-    InfraredCharacterRight(u8),
+    InfraredCharacterOmni(InfraredCharacter),
+    InfraredCharacterLeft(InfraredCharacter),
+    InfraredCharacterRight(InfraredCharacter),
     Buttons {
         clock: bool,
         schedule: bool,
@@ -207,9 +402,138 @@ pub enum SensorData {
     IsMovingForward(bool),
 }
 
-// TODO we need a list of Infrared codes.
+/// The buttons of the remotes that come with Roombas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoPrimitive, TryFromPrimitive)]
+#[repr(u8)]
+pub enum RemoteButton {
+    Left = 129,
+    Forward = 130,
+    Right = 131,
+    Spot = 132,
+    Max = 133,
+    Small = 134,
+    Medium = 135,
+    /// Labelled Large on some remotes.
+    Clean = 136,
+    Stop = 137,
+    Power = 138,
+    ArcLeft = 139,
+    ArcRight = 140,
+    /// The spec lists stop twice. Which one gets sent depends on the remote.
+    StopAlternate = 141,
+    /// From the scheduling remote.
+    Download = 142,
+    /// From the scheduling remote.
+    SeekDock = 143,
+}
+
+/// Which beams of a charging dock are in view.
+/// The buoys are on either side of the dock, red on the left and green on the right when facing
+/// it. The force field is a short range beam all around it, so the robot doesn't run into it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DockBeams {
+    pub red_buoy: bool,
+    pub green_buoy: bool,
+    pub force_field: bool,
+}
+
+impl DockBeams {
+    const RED_BUOY: u8 = 0x08;
+    const GREEN_BUOY: u8 = 0x04;
+
+    fn from_code(code: u8, force_field: u8) -> Self {
+        Self {
+            red_buoy: code & Self::RED_BUOY != 0,
+            green_buoy: code & Self::GREEN_BUOY != 0,
+            force_field: code & force_field != 0,
+        }
+    }
+
+    fn to_code(self, base: u8, force_field: u8) -> u8 {
+        let flag = |value: bool, bit: u8| if value { bit } else { 0 };
+
+        base | flag(self.red_buoy, Self::RED_BUOY)
+            | flag(self.green_buoy, Self::GREEN_BUOY)
+            | flag(self.force_field, force_field)
+    }
+}
+
+/// What one of the infrared receivers picked up. Only one character can be received at a time, so
+/// docks send every beam that's in view as a single character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InfraredCharacter {
+    NoSignal,
+    Remote(RemoteButton),
+    VirtualWall,
+    /// The Home Base that came with the Roomba Discovery.
+    HomeBase(DockBeams),
+    /// The drive-on charger of the Roomba 500 and 600 series, which the Create 2 comes with.
+    Roomba600Charger(DockBeams),
+    /// Anything the spec doesn't list.
+    Unknown(u8),
+}
+
+impl InfraredCharacter {
+    const HOME_BASE: u8 = 0xF0;
+    const HOME_BASE_FORCE_FIELD: u8 = 0x02;
+    const ROOMBA_600_CHARGER: u8 = 0xA0;
+    const ROOMBA_600_CHARGER_FORCE_FIELD: u8 = 0x01;
+    const VIRTUAL_WALL: u8 = 162;
+
+    /// The beams in view, if this came from a dock.
+    pub fn dock_beams(self) -> Option<DockBeams> {
+        match self {
+            Self::HomeBase(beams) | Self::Roomba600Charger(beams) => Some(beams),
+            _ => None,
+        }
+    }
+}
+
+impl From<u8> for InfraredCharacter {
+    fn from(code: u8) -> Self {
+        if let Ok(button) = RemoteButton::try_from(code) {
+            return Self::Remote(button);
+        }
+
+        match code {
+            0 => Self::NoSignal,
+            Self::VIRTUAL_WALL => Self::VirtualWall,
+
+            // The two chargers use different bits for the force field, and the codes in between
+            // aren't used.
+            0xA0..=0xAF if code & Self::HOME_BASE_FORCE_FIELD == 0 => Self::Roomba600Charger(
+                DockBeams::from_code(code, Self::ROOMBA_600_CHARGER_FORCE_FIELD),
+            ),
+            0xF0..=0xFF if code & Self::ROOMBA_600_CHARGER_FORCE_FIELD == 0 => {
+                Self::HomeBase(DockBeams::from_code(code, Self::HOME_BASE_FORCE_FIELD))
+            }
+
+            code => Self::Unknown(code),
+        }
+    }
+}
+
+impl From<InfraredCharacter> for u8 {
+    fn from(character: InfraredCharacter) -> Self {
+        match character {
+            InfraredCharacter::NoSignal => 0,
+            InfraredCharacter::Remote(button) => button.into(),
+            InfraredCharacter::VirtualWall => InfraredCharacter::VIRTUAL_WALL,
+            InfraredCharacter::HomeBase(beams) => beams.to_code(
+                InfraredCharacter::HOME_BASE,
+                InfraredCharacter::HOME_BASE_FORCE_FIELD,
+            ),
+            InfraredCharacter::Roomba600Charger(beams) => beams.to_code(
+                InfraredCharacter::ROOMBA_600_CHARGER,
+                InfraredCharacter::ROOMBA_600_CHARGER_FORCE_FIELD,
+            ),
+            InfraredCharacter::Unknown(code) => code,
+        }
+    }
+}
 
-#[derive(Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive)]
+/// Ordered by how much control we have over the robot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, IntoPrimitive, TryFromPrimitive)]
 #[repr(u8)]
 pub enum OIMode {
     Off = 0,
@@ -218,7 +542,7 @@ pub enum OIMode {
     Full = 3,
 }
 
-#[derive(Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoPrimitive, TryFromPrimitive)]
 #[repr(u8)]
 pub enum ChargingState {
     NotCharging = 0,
@@ -229,6 +553,7 @@ pub enum ChargingState {
     ChargingFaultCondition = 5,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub struct LedState {
     pub check_robot: bool,
     pub dock: bool,
@@ -240,14 +565,178 @@ pub struct LedState {
     pub power_intensity: u8,
 }
 
+/// The LEDs used to show the time and cleaning schedule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SchedulingLedState {
+    /// Indexed by `Weekday`, starting from Sunday.
+    pub days: [bool; 7],
+
+    pub colon: bool,
+    pub pm: bool,
+    pub am: bool,
+    pub clock: bool,
+    pub schedule: bool,
+}
+
+/// Which of the cleaning motors should be running, and in which direction.
+/// They run at full speed. Use `Roomba::set_motor_pwm` for more control.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MotorState {
+    pub main_brush: bool,
+
+    /// Run the main brush outward, rather than inward like it does when cleaning.
+    pub main_brush_outward: bool,
+
+    pub side_brush: bool,
+
+    /// Run the side brush clockwise, rather than counter clockwise like it does when cleaning.
+    pub side_brush_clockwise: bool,
+
+    pub vacuum: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TurnDirection {
     Left(u16),
     Right(u16),
 }
 
+/// A single note of a song.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Note {
+    pitch: u8,
+    duration: u8,
+}
+
+impl Note {
+    /// The pitch sent to the Roomba for a rest. Anything outside of the valid pitch range works.
+    const REST_PITCH: u8 = 0;
+
+    /// The pitch is a MIDI note number, from 31 (G1) to 127 (G9).
+    /// The duration is in 64ths of a second.
+    pub fn new(pitch: u8, duration: u8) -> Result<Self, Error> {
+        if (31..=127).contains(&pitch) {
+            Ok(Self { pitch, duration })
+        } else {
+            Err(Error::InvalidNotePitch(pitch))
+        }
+    }
+
+    /// A note that plays nothing for its duration.
+    /// The duration is in 64ths of a second.
+    pub fn rest(duration: u8) -> Self {
+        Self {
+            pitch: Self::REST_PITCH,
+            duration,
+        }
+    }
+
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    pub fn duration(&self) -> u8 {
+        self.duration
+    }
+}
+
+/// A song that can be stored in one of the Roomba's song slots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Song {
+    notes: Vec<Note>,
+}
+
+impl Song {
+    /// The most notes the Roomba can store for a single song.
+    pub const MAX_NOTES: usize = 16;
+
+    /// The number of song slots the Roomba has. Songs are numbered from 0.
+    pub const SLOTS: u8 = 4;
+
+    pub fn new(notes: Vec<Note>) -> Result<Self, Error> {
+        if notes.is_empty() || notes.len() > Self::MAX_NOTES {
+            Err(Error::InvalidSongLength(notes.len()))
+        } else {
+            Ok(Self { notes })
+        }
+    }
+
+    pub fn notes(&self) -> &[Note] {
+        &self.notes
+    }
+
+    fn check_song_number(song_number: u8) -> Result<(), Error> {
+        if song_number < Self::SLOTS {
+            Ok(())
+        } else {
+            Err(Error::InvalidSongNumber(song_number))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoPrimitive, TryFromPrimitive)]
+#[repr(u8)]
+pub enum Weekday {
+    Sunday = 0,
+    Monday = 1,
+    Tuesday = 2,
+    Wednesday = 3,
+    Thursday = 4,
+    Friday = 5,
+    Saturday = 6,
+}
+
+/// A time of day on a 24 hour clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeOfDay {
+    hour: u8,
+    minute: u8,
+}
+
+impl TimeOfDay {
+    pub fn new(hour: u8, minute: u8) -> Result<Self, Error> {
+        if hour < 24 && minute < 60 {
+            Ok(Self { hour, minute })
+        } else {
+            Err(Error::InvalidTime { hour, minute })
+        }
+    }
+
+    pub fn hour(&self) -> u8 {
+        self.hour
+    }
+
+    pub fn minute(&self) -> u8 {
+        self.minute
+    }
+}
+
+/// When the Roomba should start cleaning on its own, for each day of the week.
+/// Days without a time are skipped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WeeklySchedule {
+    days: [Option<TimeOfDay>; 7],
+}
+
+impl WeeklySchedule {
+    /// A schedule that never cleans. Sending this turns scheduling off.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&mut self, day: Weekday, time: Option<TimeOfDay>) {
+        self.days[u8::from(day) as usize] = time;
+    }
+
+    pub fn get(&self, day: Weekday) -> Option<TimeOfDay> {
+        self.days[u8::from(day) as usize]
+    }
+}
+
 /// Instructions on how the robot should drive.
 /// Speed must be between -500 to +500 mm/s
 /// Turn direction/radius can be between -2000 to 2000 mm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DriveCommand {
     Straight(i16),
     Turn(TurnDirection),
@@ -260,134 +749,39 @@ impl<
         WriteStream: AsyncWrite + std::marker::Unpin,
     > Roomba<ReadStream, WriteStream>
 {
-    pub async fn new<'a>(
+    pub async fn new(
         read_stream: ReadStream,
         write_stream: WriteStream,
     ) -> Result<Roomba<ReadStream, WriteStream>, Error> {
         let shutdown_notice = Arc::new(Notify::new());
         let (sensor_tx, sensor_rx) = mpsc::channel(10);
-
-        let sensor_task = {
-            let shutdown_notice = shutdown_notice.clone();
-
-            tokio::spawn(async move {
-                // We're going to do a lot of small reads, which is a bad idea for a lot of IO streams, so let's buffer it.
-                let mut read_stream = BufReader::new(read_stream);
-
-                let mut prelude = [0u8];
-                let mut payload = Vec::new();
-
-                async fn read<ReadStream: AsyncRead + std::marker::Unpin + Send + 'static>(
-                    shutdown_notice: &Notify,
-                    read_stream: &mut ReadStream,
-                    payload: &mut [u8],
-                ) -> Option<Result<(), Error>> {
-                    tokio::select! {
-                        _ = shutdown_notice.notified() => {
-                            None
-                        },
-                        result = read_stream.read_exact(payload) => {
-                            if let Err(error) = result {
-                                Some(Err(error.into()))
-                            } else {
-                                Some(Ok(()))
-                            }
-                        }
-                    }
-                }
-
-                while let Some(result) =
-                    read(&shutdown_notice, &mut read_stream, &mut prelude).await
-                {
-                    // let debug = String::from_utf8_lossy(&prelude);
-                    // print!("{}", debug);
-                    // println!("READ: {:?}", prelude);
-                    // println!("TEXT: {}", debug);
-
-                    if let Err(error) = result {
-                        sensor_tx.send(Err(error)).await.ok();
-                    } else if prelude[0] == 19 {
-                        // We got the magic number! Time to read a packet.
-
-                        let mut length = [0u8];
-
-                        if let Some(result) =
-                            read(&shutdown_notice, &mut read_stream, &mut length).await
-                        {
-                            if let Err(error) = result {
-                                sensor_tx.send(Err(error)).await.ok();
-                            } else {
-                                let length = length[0];
-                                payload.resize(length as usize + 1, 0u8);
-
-                                if let Some(result) =
-                                    read(&shutdown_notice, &mut read_stream, &mut payload).await
-                                {
-                                    if let Err(error) = result {
-                                        sensor_tx.send(Err(error)).await.ok();
-                                    } else {
-                                        // Check the sum.
-                                        let checksum = payload
-                                            .iter()
-                                            .chain([19u8, length].iter())
-                                            .fold(0u8, |acc, b| acc.wrapping_add(*b));
-                                        if checksum == 0 {
-                                            // We finally have a payload.
-                                            // We're going to iterate it.
-                                            let mut payload =
-                                                payload[..payload.len() - 1].iter().copied();
-
-                                            // We go until we run out of sensor IDs.
-                                            while let Some(sensor_id) = payload.next() {
-                                                if let Ok(sensor_id) =
-                                                    Sensor::try_from_primitive(sensor_id)
-                                                {
-                                                    let sensor_data =
-                                                        parse_sensor_data(sensor_id, &mut payload);
-
-                                                    match sensor_data {
-                                                        Ok(sensor_data) => {
-                                                            sensor_tx
-                                                                .send(Ok(sensor_data))
-                                                                .await
-                                                                .ok();
-                                                        }
-                                                        Err(error) => {
-                                                            sensor_tx.send(Err(error)).await.ok();
-                                                        }
-                                                    }
-                                                } else {
-                                                    sensor_tx
-                                                        .send(Err(Error::InvalidSensorTypeID(
-                                                            sensor_id,
-                                                        )))
-                                                        .await
-                                                        .ok();
-                                                    break;
-                                                }
-                                            }
-                                        }
-                                    }
-                                } else {
-                                    // We are shutting down.
-                                    break;
-                                }
-                            }
-                        } else {
-                            // We are shutting down.
-                            break;
-                        }
-                    }
-                }
-            })
-        };
+        let (query_tx, query_rx) = mpsc::unbounded_channel();
+        let (stream_tx, stream_rx) = mpsc::unbounded_channel();
+        let mode = Arc::new(Mutex::new(ModeTracker::new()));
+        let stream_statistics = Arc::new(Mutex::new(StreamStatistics::default()));
+
+        let sensor_task = tokio::spawn(sensor_reader(
+            read_stream,
+            shutdown_notice.clone(),
+            sensor_tx,
+            query_rx,
+            stream_rx,
+            mode.clone(),
+            stream_statistics.clone(),
+        ));
 
         let mut roomba = Roomba {
             write_stream,
-            _read_stream: std::marker::PhantomData::default(),
+            _read_stream: std::marker::PhantomData,
             _sensor_task: Some(sensor_task),
             sensor_rx: Some(sensor_rx),
+            query_tx,
+            stream_tx,
             shutdown_notice,
+            mode,
+            stream_statistics,
+            stream_running: false,
+            disconnected: false,
         };
 
         roomba.reset().await?;
@@ -397,51 +791,107 @@ impl<
     }
 
     async fn start(&mut self) -> Result<(), Error> {
-        self.write_stream.write_all(&[128]).await?;
-
-        Ok(())
+        self.set_mode(OIMode::Passive).await?;
+        self.flush().await
     }
 
     async fn reset(&mut self) -> Result<(), Error> {
         self.write_stream.write_all(&[7]).await?;
+        self.command_mode(OIMode::Off);
         sleep(Duration::from_secs(5)).await;
 
         Ok(())
     }
 
     async fn stop(&mut self) -> Result<(), Error> {
-        self.write_stream.write_all(&[173]).await?;
+        self.set_mode(OIMode::Off).await
+    }
+
+    /// Switch the Open Interface to another mode.
+    /// Passive only allows reading sensors and running the built in behaviors. Safe gives us control,
+    /// but the robot will still stop itself at cliffs and wheel drops. Full gives us complete control.
+    /// Off stops the Open Interface entirely, along with any stream. The robot then ignores
+    /// everything until Passive starts the Open Interface again, so Safe and Full are refused.
+    pub async fn set_mode(&mut self, mode: OIMode) -> Result<(), Error> {
+        if matches!(mode, OIMode::Safe | OIMode::Full) {
+            self.require_mode(OIMode::Passive)?;
+        }
+
+        let opcode = match mode {
+            OIMode::Off => 173,
+            OIMode::Passive => 128,
+            OIMode::Safe => 131,
+            OIMode::Full => 132,
+        };
+
+        self.write_stream.write_all(&[opcode]).await?;
+        self.command_mode(mode);
+
+        if mode == OIMode::Off {
+            // Stopping the Open Interface stops the stream with it.
+            self.stream_tx.send(StreamChange::Stopped).ok();
+            self.stream_running = false;
+        }
 
         Ok(())
     }
 
-    async fn take_control(&mut self) -> Result<(), Error> {
-        self.write_stream.write_all(&[131]).await?;
+    /// The mode we believe the Open Interface is in. This follows the modes we ask for, and the
+    /// `OIMode` sensor whenever it's streamed or queried. The robot changes mode on its own at
+    /// times, such as dropping to Passive when it detects a cliff in Safe mode, so stream that
+    /// sensor if you need to know about it. Reports that disagree with a mode we just asked for are
+    /// ignored until the robot has had time to act on the request, since they can be older than it.
+    pub fn mode(&self) -> OIMode {
+        self.mode.lock().expect("Mode was poisoned").mode
+    }
 
-        Ok(())
+    fn command_mode(&mut self, mode: OIMode) {
+        self.mode.lock().expect("Mode was poisoned").command(mode);
     }
 
-    pub async fn clean(&mut self) -> Result<(), Error> {
-        self.write_stream.write_all(&[135]).await?;
+    /// How many stream frames have been lost so far, and how.
+    pub fn stream_statistics(&self) -> StreamStatistics {
+        *self
+            .stream_statistics
+            .lock()
+            .expect("Stream statistics were poisoned")
+    }
 
-        Ok(())
+    fn require_mode(&self, required: OIMode) -> Result<(), Error> {
+        let current = self.mode();
+
+        if current >= required {
+            Ok(())
+        } else {
+            Err(Error::ModeTooLow { required, current })
+        }
     }
 
-    pub async fn spot(&mut self) -> Result<(), Error> {
-        self.write_stream.write_all(&[134]).await?;
+    /// Start a cleaning cycle. Like all the built in behaviors, this puts the robot in Passive mode.
+    pub async fn clean(&mut self) -> Result<(), Error> {
+        self.start_behavior(135).await
+    }
 
-        Ok(())
+    pub async fn spot(&mut self) -> Result<(), Error> {
+        self.start_behavior(134).await
     }
 
     pub async fn seek_dock(&mut self) -> Result<(), Error> {
-        self.write_stream.write_all(&[143]).await?;
+        self.start_behavior(143).await
+    }
+
+    async fn start_behavior(&mut self, opcode: u8) -> Result<(), Error> {
+        self.require_mode(OIMode::Passive)?;
+
+        self.write_stream.write_all(&[opcode]).await?;
+        self.command_mode(OIMode::Passive);
 
         Ok(())
     }
 
     pub async fn drive(&mut self, command: DriveCommand) -> Result<(), Error> {
         // This command only works in safe or full mode.
-        self.take_control().await?;
+        self.require_mode(OIMode::Safe)?;
 
         let (velocity, radius) = match command {
             DriveCommand::Straight(speed) => (speed, 0x7FFF),
@@ -477,8 +927,8 @@ impl<
         left_wheel_velocity: i16,
         right_wheel_velocity: i16,
     ) -> Result<(), Error> {
-        self.take_control().await?;
-        
+        self.require_mode(OIMode::Safe)?;
+
         self.write_stream.write_all(&[145]).await?;
         self.write_stream
             .write_all(&right_wheel_velocity.to_be_bytes())
@@ -509,7 +959,7 @@ impl<
             leds |= 0x01;
         }
 
-        self.take_control().await?;
+        self.require_mode(OIMode::Safe)?;
         self.write_stream
             .write_all(&[139, leds, led_state.power_color, led_state.power_intensity])
             .await?;
@@ -517,18 +967,72 @@ impl<
         Ok(())
     }
 
+    /// Turn the cleaning motors on or off.
+    pub async fn set_motors(&mut self, motor_state: MotorState) -> Result<(), Error> {
+        let mut motors = 0x00u8;
+
+        if motor_state.main_brush_outward {
+            motors |= 0x10;
+        }
+
+        if motor_state.side_brush_clockwise {
+            motors |= 0x08;
+        }
+
+        if motor_state.main_brush {
+            motors |= 0x04;
+        }
+
+        if motor_state.vacuum {
+            motors |= 0x02;
+        }
+
+        if motor_state.side_brush {
+            motors |= 0x01;
+        }
+
+        // This command only works in safe or full mode.
+        self.require_mode(OIMode::Safe)?;
+        self.write_stream.write_all(&[138, motors]).await?;
+
+        Ok(())
+    }
+
+    /// Run the cleaning motors at a duty cycle between -127 and 127, where 127 is full speed.
+    /// Positive values run the brushes in the direction they go when cleaning, and negative values
+    /// run them in reverse. The vacuum only goes one way, so it can't be negative.
+    pub async fn set_motor_pwm(
+        &mut self,
+        main_brush: i8,
+        side_brush: i8,
+        vacuum: i8,
+    ) -> Result<(), Error> {
+        if main_brush == i8::MIN || side_brush == i8::MIN || vacuum < 0 {
+            return Err(Error::MotorPwmRange);
+        }
+
+        // This command only works in safe or full mode.
+        self.require_mode(OIMode::Safe)?;
+        self.write_stream
+            .write_all(&[144, main_brush as u8, side_brush as u8, vacuum as u8])
+            .await?;
+
+        Ok(())
+    }
+
     /// Note that while this will happily accept a full utf8 string, it can only
-    /// display capitalized alphanumeric text (don't worry it'll auto capitalize for you) plus spaces.
+    /// display capitalized ASCII text (don't worry it'll auto capitalize for you).
+    /// Anything else gets swapped for something close to it, see `seven_segment::display_character`.
     /// The display also only has 4 digits on it, so only the first 4 characters of any
-    /// string will be displayed.
+    /// string will be displayed. Use `seven_segment::ScrollingText` to show longer text.
     pub async fn set_seven_segment(&mut self, text: &str) -> Result<(), Error> {
-        let mut display_bytes = [b' '; 4];
+        let mut display_bytes = [b' '; DISPLAY_WIDTH];
 
         for (b, c) in display_bytes.iter_mut().zip(text.chars()) {
-            *b = c as u8;
+            *b = display_character(c);
         }
 
-        self.take_control().await?;
+        self.require_mode(OIMode::Safe)?;
 
         self.write_stream.write_all(&[164]).await?;
         self.write_stream.write_all(&display_bytes).await?;
@@ -536,81 +1040,280 @@ impl<
         Ok(())
     }
 
-    /// Take the sensor stream for this Roomba. Will stream messages sent up by the Roomba.
-    pub fn take_sensor_stream(&mut self) -> Option<mpsc::Receiver<Result<SensorData, Error>>> {
-        self.sensor_rx.take()
-    }
+    /// Light up the segments of each digit directly, from left to right.
+    /// See the `seven_segment::SEGMENT_*` constants for which bit is which segment.
+    pub async fn set_digit_segments(&mut self, digits: [u8; DISPLAY_WIDTH]) -> Result<(), Error> {
+        if let Some(digit) = digits.iter().find(|digit| **digit & !ALL_SEGMENTS != 0) {
+            return Err(Error::InvalidDigitSegments(*digit));
+        }
 
-    /// Query a single sensor.
-    /// You can get the result through the sensor stream provided by `take_sensor_stream`.
-    pub async fn query(&mut self, sensor: Sensor) -> Result<(), Error> {
-        self.take_control().await?;
+        self.require_mode(OIMode::Safe)?;
+
+        self.write_stream.write_all(&[163]).await?;
+        self.write_stream.write_all(&digits).await?;
 
-        self.write_stream.write_all(&[142, sensor.into()]).await?;
         Ok(())
     }
 
-    /// Query a list of sensors.
-    /// You can get the result through the sensor stream provided by `take_sensor_stream`.
-    pub async fn query_list(&mut self, sensors: &[Sensor]) -> Result<(), Error> {
-        self.take_control().await?;
+    pub async fn set_scheduling_leds(
+        &mut self,
+        led_state: SchedulingLedState,
+    ) -> Result<(), Error> {
+        let mut days = 0x00u8;
 
-        self.write_stream
-            .write_all(&[142, sensors.len() as u8])
-            .await?;
+        for (day, lit) in led_state.days.iter().enumerate() {
+            if *lit {
+                days |= 1 << day;
+            }
+        }
+
+        let mut leds = 0x00u8;
+
+        if led_state.schedule {
+            leds |= 0x10;
+        }
+
+        if led_state.clock {
+            leds |= 0x08;
+        }
+
+        if led_state.am {
+            leds |= 0x04;
+        }
+
+        if led_state.pm {
+            leds |= 0x02;
+        }
 
-        for sensor in sensors {
-            self.write_stream.write_all(&[(*sensor).into()]).await?;
+        if led_state.colon {
+            leds |= 0x01;
         }
 
+        self.require_mode(OIMode::Safe)?;
+        self.write_stream.write_all(&[162, days, leds]).await?;
+
         Ok(())
     }
 
-    /// Start a stream of sensor data.
-    /// You can get the results through the sensor stream provided by `take_sensor_stream`.
-    pub async fn start_stream(&mut self, sensors: &[Sensor]) -> Result<(), Error> {
-        self.take_control().await?;
+    /// Store a song in one of the Roomba's song slots, replacing whatever was there.
+    /// Use `play_song` to play it.
+    pub async fn define_song(&mut self, song_number: u8, song: &Song) -> Result<(), Error> {
+        Song::check_song_number(song_number)?;
+        self.require_mode(OIMode::Passive)?;
 
         self.write_stream
-            .write_all(&[148, sensors.len() as u8])
+            .write_all(&[140, song_number, song.notes.len() as u8])
             .await?;
 
-        for sensor in sensors.iter().copied() {
-            self.write_stream.write_all(&[sensor.into()]).await?;
+        for note in song.notes.iter() {
+            self.write_stream
+                .write_all(&[note.pitch, note.duration])
+                .await?;
         }
 
         Ok(())
     }
 
-    /// Set true to pause the stream, and false to resume.
-    pub async fn pause_stream(&mut self, paused: bool) -> Result<(), Error> {
-        let paused = if paused { 0x00 } else { 0x01 };
+    /// Play a song previously stored with `define_song`.
+    /// The `SongPlaying` sensor can be used to tell when it has finished.
+    pub async fn play_song(&mut self, song_number: u8) -> Result<(), Error> {
+        Song::check_song_number(song_number)?;
 
-        self.write_stream.write_all(&[150, paused]).await?;
+        // This command only works in safe or full mode.
+        self.require_mode(OIMode::Safe)?;
+
+        self.write_stream.write_all(&[141, song_number]).await?;
 
         Ok(())
     }
 
-    pub async fn flush(&mut self) -> Result<(), Error> {
-        self.write_stream.flush().await?;
+    /// Set the Roomba's clock, which its cleaning schedule runs off of.
+    pub async fn set_day_time(&mut self, day: Weekday, time: TimeOfDay) -> Result<(), Error> {
+        self.require_mode(OIMode::Passive)?;
+
+        self.write_stream
+            .write_all(&[168, day.into(), time.hour, time.minute])
+            .await?;
 
         Ok(())
     }
 
-    pub async fn close(mut self) -> Result<(), Error> {
-        self.stop().await?;
-        self.flush().await?;
+    /// Replace the Roomba's cleaning schedule.
+    pub async fn set_schedule(&mut self, schedule: &WeeklySchedule) -> Result<(), Error> {
+        self.require_mode(OIMode::Passive)?;
 
-        self.shutdown_notice.notify_waiters();
+        // A bit for each day that has a time, followed by the hour and minute for every day.
+        let mut message = vec![167, 0x00];
 
-        // if let Some(sensor_task) = self.sensor_task.take() {
-        //     sensor_task.await?;
-        // }
+        for (day, time) in schedule.days.iter().enumerate() {
+            match time {
+                Some(time) => {
+                    message[1] |= 1 << day;
+                    message.extend_from_slice(&[time.hour, time.minute]);
+                }
+                None => message.extend_from_slice(&[0, 0]),
+            }
+        }
 
-        std::mem::forget(self);
+        self.write_stream.write_all(&message).await?;
 
         Ok(())
     }
+
+    /// Take the sensor stream for this Roomba. Will stream messages sent up by the Roomba, a frame
+    /// at a time.
+    pub fn take_sensor_stream(&mut self) -> Option<mpsc::Receiver<Result<SensorFrame, Error>>> {
+        self.sensor_rx.take()
+    }
+
+    /// Query a single sensor.
+    /// The returned `QueryResponse` resolves to the sensor's value once the Roomba answers.
+    /// Remember to `flush` so the query actually gets sent.
+    pub async fn query(&mut self, sensor: Sensor) -> Result<QueryResponse, Error> {
+        self.send_query(vec![sensor], &[142, sensor.into()]).await
+    }
+
+    /// Query a list of sensors.
+    /// The returned `QueryResponse` resolves to the sensor values, in the order they were requested,
+    /// once the Roomba answers.
+    /// Remember to `flush` so the query actually gets sent.
+    pub async fn query_list(&mut self, sensors: &[Sensor]) -> Result<QueryResponse, Error> {
+        check_sensor_count(sensors)?;
+
+        let mut command = vec![149, sensors.len() as u8];
+        command.extend(sensors.iter().map(|sensor| u8::from(*sensor)));
+
+        self.send_query(sensors.to_vec(), &command).await
+    }
+
+    /// Send a query command. A running stream is paused while the robot answers, since the
+    /// response has no header of its own and could otherwise land in the middle of a frame.
+    async fn send_query(
+        &mut self,
+        sensors: Vec<Sensor>,
+        command: &[u8],
+    ) -> Result<QueryResponse, Error> {
+        self.require_mode(OIMode::Passive)?;
+
+        let streaming = self.stream_running;
+        let response = self.expect_query_response(sensors, streaming);
+
+        if streaming {
+            self.write_stream.write_all(&[150, 0]).await?;
+        }
+
+        self.write_stream.write_all(command).await?;
+
+        if streaming {
+            self.write_stream.write_all(&[150, 1]).await?;
+        }
+
+        Ok(response)
+    }
+
+    /// Query a list of sensors and wait for the answer, which has the sensor values in the order
+    /// they were requested. All of the values are read by the Roomba at the same time.
+    /// If a sensor stream is running, it must keep being read from while this waits, or the
+    /// response could get stuck behind stream data nobody is taking.
+    /// Fails with `Error::QueryTimeout` if the robot doesn't answer.
+    pub async fn query_sensors(&mut self, sensors: &[Sensor]) -> Result<Vec<SensorData>, Error> {
+        let response = self.query_list(sensors).await?;
+        self.flush().await?;
+
+        response.await
+    }
+
+    /// Let the sensor reader know that a query response is on its way.
+    /// This has to happen before the query is sent, or the reader could mistake the response for noise.
+    fn expect_query_response(&mut self, sensors: Vec<Sensor>, after_stream: bool) -> QueryResponse {
+        let (response_tx, response_rx) = oneshot::channel();
+
+        // If the reader is gone, the response will report that for us.
+        self.query_tx
+            .send(PendingQuery {
+                sensors,
+                response_tx,
+                after_stream,
+            })
+            .ok();
+
+        QueryResponse { response_rx }
+    }
+
+    /// Start a stream of sensor data.
+    /// You can get the results through the sensor stream provided by `take_sensor_stream`.
+    /// The robot sends a frame every 15ms, so a frame bigger than the baud rate can carry in that
+    /// time gets cut short. That's up to you to avoid.
+    pub async fn start_stream(&mut self, sensors: &[Sensor]) -> Result<(), Error> {
+        self.require_mode(OIMode::Passive)?;
+        check_sensor_count(sensors)?;
+
+        // The frame's length has to fit in its length byte.
+        let length = frame_length(sensors);
+        if length > u8::MAX as usize {
+            return Err(Error::FrameTooLong(length));
+        }
+
+        // Like queries, the reader has to know what's coming before it arrives, so it can tell
+        // real frames from noise.
+        self.stream_tx
+            .send(StreamChange::Started(sensors.to_vec()))
+            .ok();
+        self.stream_running = true;
+
+        self.write_stream
+            .write_all(&[148, sensors.len() as u8])
+            .await?;
+
+        for sensor in sensors.iter().copied() {
+            self.write_stream.write_all(&[sensor.into()]).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Set true to pause the stream, and false to resume.
+    pub async fn pause_stream(&mut self, paused: bool) -> Result<(), Error> {
+        self.require_mode(OIMode::Passive)?;
+
+        self.stream_tx.send(StreamChange::Paused(paused)).ok();
+        self.stream_running = !paused;
+
+        let paused = if paused { 0x00 } else { 0x01 };
+
+        self.write_stream.write_all(&[150, paused]).await?;
+
+        Ok(())
+    }
+
+    pub async fn flush(&mut self) -> Result<(), Error> {
+        self.write_stream.flush().await?;
+        self.mode.lock().expect("Mode was poisoned").sent();
+
+        Ok(())
+    }
+
+    pub async fn close(mut self) -> Result<(), Error> {
+        self.stop().await?;
+        self.flush().await?;
+
+        self.shutdown_notice.notify_waiters();
+
+        // if let Some(sensor_task) = self.sensor_task.take() {
+        //     sensor_task.await?;
+        // }
+
+        std::mem::forget(self);
+
+        Ok(())
+    }
+
+    /// Let go of a Roomba we have lost the connection to.
+    /// Unlike `close`, this doesn't try to stop the robot first, since there's no way to reach it.
+    pub fn disconnect(mut self) {
+        self.shutdown_notice.notify_waiters();
+        self.disconnected = true;
+    }
 }
 
 impl<
@@ -619,13 +1322,700 @@ impl<
     > Drop for Roomba<ReadStream, WriteStream>
 {
     fn drop(&mut self) {
+        if self.disconnected {
+            return;
+        }
+
         println!("Improper drop of Roomba controller. Call Roomba::close() when you are done with a Roomba. Not doing this can result in battery damage.");
     }
 }
 
+/// The first byte of every frame in a sensor stream.
+const STREAM_HEADER: u8 = 19;
+
+/// How often the Roomba sends a stream frame.
+const STREAM_PERIOD: Duration = Duration::from_millis(15);
+
+/// The mode we believe the Open Interface is in, shared with the sensor reader so it can follow
+/// what the robot reports.
+struct ModeTracker {
+    mode: OIMode,
+
+    /// A mode we asked for that the robot hasn't reported being in yet. Until it does, a report of
+    /// any other mode could have been read before the robot got the command.
+    commanded: Option<CommandedMode>,
+}
+
+#[derive(Clone, Copy)]
+struct CommandedMode {
+    mode: OIMode,
+
+    /// Data that started arriving after this was read once the command took effect. `None` until
+    /// the command has been sent.
+    settled: Option<Instant>,
+}
+
+impl ModeTracker {
+    fn new() -> Self {
+        Self {
+            mode: OIMode::Off,
+            commanded: None,
+        }
+    }
+
+    fn command(&mut self, mode: OIMode) {
+        self.mode = mode;
+        self.commanded = Some(CommandedMode {
+            mode,
+            settled: None,
+        });
+    }
+
+    /// Everything written so far has been sent to the robot.
+    fn sent(&mut self) {
+        if let Some(commanded) = &mut self.commanded {
+            // A frame the robot was already putting together when the command got there can still
+            // show up, so we give it one more frame.
+            commanded
+                .settled
+                .get_or_insert(Instant::now() + STREAM_PERIOD);
+        }
+    }
+
+    /// The robot reported being in `mode`, in data that started arriving at `received`.
+    fn report(&mut self, mode: OIMode, received: Instant) {
+        if let Some(commanded) = self.commanded {
+            let settled = commanded.settled.is_some_and(|settled| received >= settled);
+
+            if commanded.mode != mode && !settled {
+                // Old news.
+                return;
+            }
+        }
+
+        // Either the robot got there, or it has since changed mode on its own.
+        self.mode = mode;
+        self.commanded = None;
+    }
+}
+
+/// A query that has been sent to the Roomba, but not yet answered.
+struct PendingQuery {
+    sensors: Vec<Sensor>,
+    response_tx: oneshot::Sender<Result<Vec<SensorData>, Error>>,
+
+    /// The stream was running when the query was sent, so frames can arrive ahead of the response.
+    after_stream: bool,
+}
+
+/// The answer to a `Roomba::query` or `Roomba::query_list`.
+/// Await it to get the sensor values.
+pub struct QueryResponse {
+    response_rx: oneshot::Receiver<Result<Vec<SensorData>, Error>>,
+}
+
+impl Future for QueryResponse {
+    type Output = Result<Vec<SensorData>, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.response_rx)
+            .poll(cx)
+            .map(|result| result.unwrap_or(Err(Error::QueryAbandoned)))
+    }
+}
+
+/// Reads from the Roomba, with a way to put bytes back so they get read again.
+/// When a frame turns out to be bad, its header was most likely a 19 in the middle of some sensor
+/// data, so the real start of the next frame could be anywhere in what was read after it.
+struct Rescanner<ReadStream: AsyncRead + std::marker::Unpin> {
+    read_stream: BufReader<ReadStream>,
+    replay: VecDeque<u8>,
+}
+
+impl<ReadStream: AsyncRead + std::marker::Unpin> Rescanner<ReadStream> {
+    fn new(read_stream: ReadStream) -> Self {
+        Self {
+            // We're going to do a lot of small reads, which is a bad idea for a lot of IO streams, so let's buffer it.
+            read_stream: BufReader::new(read_stream),
+            replay: VecDeque::new(),
+        }
+    }
+
+    /// Bytes that were put back are read before anything new.
+    async fn read_exact(&mut self, buffer: &mut [u8]) -> std::io::Result<()> {
+        let replayed = buffer.len().min(self.replay.len());
+
+        for (byte, replayed) in buffer.iter_mut().zip(self.replay.drain(..replayed)) {
+            *byte = replayed;
+        }
+
+        if replayed < buffer.len() {
+            self.read_stream.read_exact(&mut buffer[replayed..]).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Put bytes back, so they're the next thing read.
+    fn replay(&mut self, bytes: &[u8]) {
+        for byte in bytes.iter().rev() {
+            self.replay.push_front(*byte);
+        }
+    }
+
+    fn is_replaying(&self) -> bool {
+        !self.replay.is_empty()
+    }
+}
+
+/// What the sensor reader expects to receive next.
+enum ReaderState {
+    /// Nothing has been asked for, so we look for the start of a stream frame.
+    Idle,
+
+    /// We got a stream header at this time, so the rest of a frame is coming. If a query is
+    /// waiting, the header could also be the start of its response, which we find out when the
+    /// frame doesn't check out.
+    StreamFrame(Instant, Option<WaitingQuery>),
+
+    /// A query was sent. Frames the robot sent before it paused the stream for the query can
+    /// still arrive ahead of the response.
+    QueryResponse(WaitingQuery),
+
+    /// The response to the query is next. It's the raw sensor data with no header or checksum,
+    /// so we can only make sense of it because we know what was asked for.
+    QueryData(WaitingQuery),
+}
+
+/// A query the reader is waiting on the response to.
+struct WaitingQuery {
+    query: PendingQuery,
+
+    /// When we give up on the robot answering.
+    deadline: Instant,
+}
+
+impl WaitingQuery {
+    /// Whichever comes first, the robot going silent or the query timing out.
+    fn deadline(&self, silence: Option<Deadline>) -> Deadline {
+        match silence {
+            Some(silence) if silence.at() < self.deadline => silence,
+            _ => Deadline::Query(self.deadline),
+        }
+    }
+
+    /// Let the query know the robot never answered it. Any other error is passed on, and takes
+    /// the query with it.
+    fn give_up(self, error: Error) -> Result<ReaderState, Error> {
+        if let Error::QueryTimeout(_) = error {
+            self.query.response_tx.send(Err(error)).ok();
+            Ok(ReaderState::Idle)
+        } else {
+            Err(error)
+        }
+    }
+}
+
+/// A point by which the robot has to have sent something, and what it means if it hasn't.
+#[derive(Debug, Clone, Copy)]
+enum Deadline {
+    /// The stream should have sent a frame.
+    Silence(Instant),
+
+    /// A query should have been answered.
+    Query(Instant),
+}
+
+impl Deadline {
+    fn at(self) -> Instant {
+        match self {
+            Deadline::Silence(at) | Deadline::Query(at) => at,
+        }
+    }
+
+    fn error(self) -> Error {
+        match self {
+            Deadline::Silence(_) => Error::Silent(SILENCE_TIMEOUT),
+            Deadline::Query(_) => Error::QueryTimeout(QUERY_TIMEOUT),
+        }
+    }
+}
+
+/// What the sensor reader needs to know about the stream, sent before the robot is asked for it.
+enum StreamChange {
+    /// A stream of these sensors was asked for.
+    Started(Vec<Sensor>),
+    Paused(bool),
+
+    /// The robot won't stream again until it's asked to, like after the Open Interface stops.
+    Stopped,
+}
+
+/// How long a running stream can go without a frame before we decide the robot has gone quiet.
+/// Frames normally come every 15ms.
+const SILENCE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long the robot gets to answer a query, from when the reader starts waiting on it.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Reads everything the Roomba sends us. Stream frames go to `sensor_tx`, and query responses go back to
+/// whoever sent the query.
+async fn sensor_reader<ReadStream: AsyncRead + std::marker::Unpin + Send + 'static>(
+    read_stream: ReadStream,
+    shutdown_notice: Arc<Notify>,
+    sensor_tx: mpsc::Sender<Result<SensorFrame, Error>>,
+    query_rx: mpsc::UnboundedReceiver<PendingQuery>,
+    stream_rx: mpsc::UnboundedReceiver<StreamChange>,
+    mode: Arc<Mutex<ModeTracker>>,
+    statistics: Arc<Mutex<StreamStatistics>>,
+) {
+    if let Err(error) = read_sensors(
+        read_stream,
+        &shutdown_notice,
+        &sensor_tx,
+        query_rx,
+        stream_rx,
+        &mode,
+        &statistics,
+    )
+    .await
+    {
+        // The stream is where the link is watched, so that's where the error goes. Queries still
+        // waiting are dropped, which tells them the reader is gone.
+        sensor_tx.send(Err(error)).await.ok();
+    }
+}
+
+/// The sensor reader, until we shut down or the Roomba is dropped. An error means the link to the
+/// robot is gone.
+async fn read_sensors<ReadStream: AsyncRead + std::marker::Unpin>(
+    read_stream: ReadStream,
+    shutdown_notice: &Notify,
+    sensor_tx: &mpsc::Sender<Result<SensorFrame, Error>>,
+    mut query_rx: mpsc::UnboundedReceiver<PendingQuery>,
+    mut stream_rx: mpsc::UnboundedReceiver<StreamChange>,
+    mode: &Mutex<ModeTracker>,
+    statistics: &Mutex<StreamStatistics>,
+) -> Result<(), Error> {
+    let mut read_stream = Rescanner::new(read_stream);
+
+    let mut pending_queries = VecDeque::new();
+    let mut state = ReaderState::Idle;
+    let mut payload = Vec::new();
+
+    // The sensors each frame of the stream should have. Frames of an old stream can still show up
+    // after a new one is asked for, so those stay valid until the first frame of the new one.
+    let mut layouts: VecDeque<Vec<Sensor>> = VecDeque::new();
+
+    // While the stream runs, the robot should never go quiet for long.
+    let mut stream_started = false;
+    let mut stream_paused = false;
+    let mut last_heard = Instant::now();
+
+    loop {
+        let silence = (stream_started && !stream_paused)
+            .then(|| Deadline::Silence(last_heard + SILENCE_TIMEOUT));
+
+        state = match state {
+            ReaderState::Idle => {
+                // Bytes being rescanned were all sent before the response to any query we're
+                // still waiting on.
+                let query = if read_stream.is_replaying() {
+                    None
+                } else {
+                    pending_queries.pop_front()
+                };
+
+                if let Some(query) = query {
+                    ReaderState::QueryResponse(WaitingQuery {
+                        query,
+                        deadline: Instant::now() + QUERY_TIMEOUT,
+                    })
+                } else {
+                    let mut header = [0u8];
+
+                    tokio::select! {
+                        // Changes to the stream and queries are registered before they're sent, so
+                        // checking for them first guarantees we never eat the first byte of a
+                        // response, or a frame of a stream we don't know about yet. Anything that
+                        // already arrived gets read before we call the robot silent.
+                        biased;
+
+                        _ = shutdown_notice.notified() => {
+                            // We are shutting down.
+                            return Ok(());
+                        }
+                        Some(change) = stream_rx.recv() => {
+                            match change {
+                                StreamChange::Started(layout) => {
+                                    layouts.push_back(layout);
+                                    stream_started = true;
+                                    stream_paused = false;
+                                }
+                                StreamChange::Paused(paused) => stream_paused = paused,
+                                StreamChange::Stopped => stream_started = false,
+                            }
+
+                            // Give the robot a chance to start sending.
+                            last_heard = Instant::now();
+                            ReaderState::Idle
+                        }
+                        query = query_rx.recv() => {
+                            let Some(query) = query else {
+                                // The Roomba has been dropped.
+                                return Ok(());
+                            };
+
+                            pending_queries.push_back(query);
+                            ReaderState::Idle
+                        }
+                        result = read_stream.read_exact(&mut header) => {
+                            result?;
+
+                            if header[0] == STREAM_HEADER {
+                                ReaderState::StreamFrame(Instant::now(), None)
+                            } else {
+                                ReaderState::Idle
+                            }
+                        }
+                        error = until(silence) => return Err(error),
+                    }
+                }
+            }
+            ReaderState::StreamFrame(received, waiting) => {
+                let deadline = match &waiting {
+                    Some(waiting) => Some(waiting.deadline(silence)),
+                    None => silence,
+                };
+
+                let mut length = [0u8];
+
+                match read(shutdown_notice, &mut read_stream, &mut length, deadline).await {
+                    None => return Ok(()),
+                    Some(Ok(())) => {}
+                    Some(Err(error)) => {
+                        state = match waiting {
+                            Some(waiting) => waiting.give_up(error)?,
+                            None => return Err(error),
+                        };
+                        continue;
+                    }
+                }
+
+                // Every frame has at least one sensor ID and a byte of its data. If we know what
+                // the stream has in it, we know exactly how long its frames are.
+                let length = length[0];
+                let plausible = if layouts.is_empty() {
+                    length >= 2
+                } else {
+                    layouts
+                        .iter()
+                        .any(|layout| frame_length(layout) == length as usize)
+                };
+
+                let sensors = if plausible {
+                    // The payload is followed by a checksum.
+                    payload.resize(length as usize + 1, 0u8);
+
+                    match read(shutdown_notice, &mut read_stream, &mut payload, deadline).await {
+                        None => return Ok(()),
+                        Some(Ok(())) => {}
+                        Some(Err(error)) => {
+                            state = match waiting {
+                                Some(waiting) => waiting.give_up(error)?,
+                                None => return Err(error),
+                            };
+                            continue;
+                        }
+                    }
+
+                    let checksum = payload
+                        .iter()
+                        .chain([STREAM_HEADER, length].iter())
+                        .fold(0u8, |acc, b| acc.wrapping_add(*b));
+
+                    let sensors = if checksum == 0 {
+                        frame_sensors(&payload[..payload.len() - 1])
+                    } else {
+                        // A query response has no checksum, so it not adding up is no failure.
+                        if waiting.is_none() {
+                            statistics
+                                .lock()
+                                .expect("Stream statistics were poisoned")
+                                .checksum_failures += 1;
+                        }
+                        None
+                    };
+
+                    // Anything that isn't what we asked for is more likely a checksum that added
+                    // up by chance than a real frame.
+                    sensors.filter(|sensors| {
+                        if layouts.is_empty() {
+                            return true;
+                        }
+
+                        let Some(index) = layouts.iter().position(|layout| layout == sensors)
+                        else {
+                            return false;
+                        };
+
+                        // Anything older than this won't be coming anymore.
+                        layouts.drain(..index);
+                        true
+                    })
+                } else {
+                    payload.clear();
+                    None
+                };
+
+                match (sensors, waiting) {
+                    (Some(sensors), waiting) => {
+                        let mut payload = payload.iter().copied();
+                        let mut data = Vec::new();
+
+                        for sensor in sensors {
+                            // Skip past the ID, which we already know.
+                            payload.next();
+
+                            for sensor_data in parse_sensor_data(sensor, &mut payload) {
+                                track_mode(mode, &sensor_data, received);
+
+                                // The frame checked out, so a value that makes no sense is
+                                // the robot's doing. We lose it, but keep the rest.
+                                match sensor_data {
+                                    Ok(sensor_data) => data.push(sensor_data),
+                                    Err(_) => lost_packet(statistics),
+                                }
+                            }
+                        }
+
+                        sensor_tx
+                            .send(Ok(SensorFrame { received, data }))
+                            .await
+                            .ok();
+
+                        // Counted from now rather than when the frame came in, so time spent
+                        // waiting on whoever reads the stream isn't blamed on the robot.
+                        last_heard = Instant::now();
+
+                        match waiting {
+                            Some(waiting) => ReaderState::QueryResponse(waiting),
+                            None => ReaderState::Idle,
+                        }
+                    }
+                    (None, Some(waiting)) => {
+                        // It wasn't a frame after all, so it was the start of the response.
+                        let mut rescan = vec![STREAM_HEADER, length];
+                        rescan.extend_from_slice(&payload);
+                        read_stream.replay(&rescan);
+
+                        ReaderState::QueryData(waiting)
+                    }
+                    (None, None) => {
+                        let dropped_packets = if plausible {
+                            layouts.back().map_or(1, Vec::len) as u64
+                        } else {
+                            0
+                        };
+                        lost_sync(statistics, dropped_packets);
+
+                        let mut rescan = vec![length];
+                        rescan.extend_from_slice(&payload);
+                        read_stream.replay(&rescan);
+
+                        ReaderState::Idle
+                    }
+                }
+            }
+            ReaderState::QueryResponse(waiting) => {
+                if waiting.query.after_stream {
+                    let mut header = [0u8];
+                    let deadline = Some(waiting.deadline(silence));
+
+                    match read(shutdown_notice, &mut read_stream, &mut header, deadline).await {
+                        None => return Ok(()),
+                        Some(Ok(())) if header[0] == STREAM_HEADER => {
+                            ReaderState::StreamFrame(Instant::now(), Some(waiting))
+                        }
+                        Some(Ok(())) => {
+                            read_stream.replay(&header);
+                            ReaderState::QueryData(waiting)
+                        }
+                        Some(Err(error)) => waiting.give_up(error)?,
+                    }
+                } else {
+                    // Without a stream, nothing else can come first.
+                    ReaderState::QueryData(waiting)
+                }
+            }
+            ReaderState::QueryData(waiting) => {
+                let length = waiting
+                    .query
+                    .sensors
+                    .iter()
+                    .map(|sensor| sensor.data_length())
+                    .sum();
+                payload.resize(length, 0u8);
+
+                let deadline = Some(waiting.deadline(silence));
+
+                match read(shutdown_notice, &mut read_stream, &mut payload, deadline).await {
+                    None => return Ok(()),
+                    Some(Ok(())) => {
+                        let received = Instant::now();
+                        let mut payload = payload.iter().copied();
+
+                        let response = waiting
+                            .query
+                            .sensors
+                            .iter()
+                            .flat_map(|sensor| parse_sensor_data(*sensor, &mut payload))
+                            .inspect(|sensor_data| track_mode(mode, sensor_data, received))
+                            .collect();
+
+                        // It's fine if nobody is listening anymore.
+                        waiting.query.response_tx.send(response).ok();
+                        last_heard = Instant::now();
+
+                        ReaderState::Idle
+                    }
+                    Some(Err(error)) => waiting.give_up(error)?,
+                }
+            }
+        };
+    }
+}
+
+/// How many bytes a stream frame's payload has, given the sensors in it. The header, length and
+/// checksum add another 3 bytes.
+pub fn frame_length(sensors: &[Sensor]) -> usize {
+    sensors.iter().map(|sensor| 1 + sensor.data_length()).sum()
+}
+
+/// The number of sensors in a query or stream request is sent as a single byte.
+fn check_sensor_count(sensors: &[Sensor]) -> Result<(), Error> {
+    if sensors.len() > u8::MAX as usize {
+        return Err(Error::TooManySensors(sensors.len()));
+    }
+
+    Ok(())
+}
+
+/// The sensors in a stream frame's payload, or `None` if it isn't made up of whole packets with
+/// IDs we know.
+fn frame_sensors(payload: &[u8]) -> Option<Vec<Sensor>> {
+    let mut sensors = Vec::new();
+    let mut rest = payload;
+
+    while let Some((sensor_id, data)) = rest.split_first() {
+        let sensor = Sensor::try_from_primitive(*sensor_id).ok()?;
+        rest = data.get(sensor.data_length()..)?;
+        sensors.push(sensor);
+    }
+
+    Some(sensors)
+}
+
+/// Count a bad frame. The caller is expected to go looking for the next one.
+fn lost_sync(statistics: &Mutex<StreamStatistics>, dropped_packets: u64) {
+    let mut statistics = statistics.lock().expect("Stream statistics were poisoned");
+
+    statistics.resyncs += 1;
+    statistics.dropped_packets += dropped_packets;
+}
+
+/// Count a packet with data that couldn't be read, in a frame that was otherwise fine.
+fn lost_packet(statistics: &Mutex<StreamStatistics>) {
+    statistics
+        .lock()
+        .expect("Stream statistics were poisoned")
+        .dropped_packets += 1;
+}
+
+/// Keep the Roomba's idea of what mode it's in up to date with what the robot tells us.
+fn track_mode(
+    mode: &Mutex<ModeTracker>,
+    sensor_data: &Result<SensorData, Error>,
+    received: Instant,
+) {
+    if let Ok(SensorData::OIMode(new_mode)) = sensor_data {
+        mode.lock()
+            .expect("Mode was poisoned")
+            .report(*new_mode, received);
+    }
+}
+
+/// Read exactly enough to fill `payload`, unless we get told to shut down first,
+/// in which case `None` is returned. If the deadline passes first, we get the error it stands for.
+async fn read<ReadStream: AsyncRead + std::marker::Unpin>(
+    shutdown_notice: &Notify,
+    read_stream: &mut Rescanner<ReadStream>,
+    payload: &mut [u8],
+    deadline: Option<Deadline>,
+) -> Option<Result<(), Error>> {
+    tokio::select! {
+        // Data that already arrived counts, however late we get to it.
+        biased;
+
+        _ = shutdown_notice.notified() => {
+            None
+        },
+        result = read_stream.read_exact(payload) => {
+            if let Err(error) = result {
+                Some(Err(error.into()))
+            } else {
+                Some(Ok(()))
+            }
+        }
+        error = until(deadline) => Some(Err(error)),
+    }
+}
+
+/// Wait until the deadline and get the error it stands for, or wait forever if there isn't one.
+async fn until(deadline: Option<Deadline>) -> Error {
+    match deadline {
+        Some(deadline) => {
+            sleep_until(deadline.at()).await;
+            deadline.error()
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Parse the data of a sensor. Group packets are split up into the data of each sensor in them.
 fn parse_sensor_data(
     sensor_id: Sensor,
     payload: &mut impl Iterator<Item = u8>,
+) -> Vec<Result<SensorData, Error>> {
+    let Some(members) = sensor_id.group_members() else {
+        return vec![parse_packet(sensor_id, payload)];
+    };
+
+    let mut sensor_data = Vec::new();
+
+    for member in members {
+        let result = match member {
+            GroupMember::Sensor(sensor) => parse_packet(sensor, payload),
+            GroupMember::Unused(length) if payload.take(length).count() == length => continue,
+            GroupMember::Unused(_) => Err(Error::UnexpectedEnd),
+        };
+
+        // Once we run out, the rest of the group is missing too.
+        let ended = matches!(result, Err(Error::UnexpectedEnd));
+        sensor_data.push(result);
+
+        if ended {
+            break;
+        }
+    }
+
+    sensor_data
+}
+
+/// Parse the data of a single sensor.
+fn parse_packet(
+    sensor_id: Sensor,
+    payload: &mut impl Iterator<Item = u8>,
 ) -> Result<SensorData, Error> {
     fn too_short(option: Option<u8>) -> Result<u8, Error> {
         match option {
@@ -657,8 +2047,8 @@ fn parse_sensor_data(
             let status = too_short(payload.next())?;
 
             Ok(SensorData::BumpersAndWheelDrops {
-                wheel_drop_left: status & 0x08 == 0,
-                wheel_drop_right: status & 0x04 == 0,
+                wheel_drop_left: status & 0x08 != 0,
+                wheel_drop_right: status & 0x04 != 0,
                 bumper_left: status & 0x02 != 0,
                 bumper_right: status & 0x01 != 0,
             })
@@ -680,15 +2070,15 @@ fn parse_sensor_data(
             })
         }
         Sensor::DirtDetect => Ok(SensorData::DirtDetect(too_short(payload.next())?)),
-        Sensor::InfraredCharacterOmni => Ok(SensorData::InfraredCharacterOmni(too_short(
-            payload.next(),
-        )?)),
-        Sensor::InfraredCharacterLeft => Ok(SensorData::InfraredCharacterLeft(too_short(
-            payload.next(),
-        )?)),
-        Sensor::InfraredCharacterRight => Ok(SensorData::InfraredCharacterLeft(too_short(
-            payload.next(),
-        )?)),
+        Sensor::InfraredCharacterOmni => Ok(SensorData::InfraredCharacterOmni(
+            too_short(payload.next())?.into(),
+        )),
+        Sensor::InfraredCharacterLeft => Ok(SensorData::InfraredCharacterLeft(
+            too_short(payload.next())?.into(),
+        )),
+        Sensor::InfraredCharacterRight => Ok(SensorData::InfraredCharacterRight(
+            too_short(payload.next())?.into(),
+        )),
         Sensor::Buttons => {
             let state = too_short(payload.next())?;
 
@@ -766,9 +2156,7 @@ fn parse_sensor_data(
                 left: state & 0x01 != 0,
             })
         }
-        Sensor::LightBumpLeftSignal => {
-            Ok(SensorData::LightBumpCenterLeftSignal(take_u16(payload)?))
-        }
+        Sensor::LightBumpLeftSignal => Ok(SensorData::LightBumpLeftSignal(take_u16(payload)?)),
         Sensor::LightBumpFrontLeftSignal => {
             Ok(SensorData::LightBumpFrontLeftSignal(take_u16(payload)?))
         }
@@ -787,5 +2175,1189 @@ fn parse_sensor_data(
         Sensor::MainBrushMotorCurrent => Ok(SensorData::MainBrushMotorCurrent(take_i16(payload)?)),
         Sensor::SideBrushMotorCurrent => Ok(SensorData::SideBrushMotorCurrent(take_i16(payload)?)),
         Sensor::IsMovingForward => Ok(SensorData::IsMovingForward(too_short(payload.next())? != 0)),
+        Sensor::Group0
+        | Sensor::Group1
+        | Sensor::Group2
+        | Sensor::Group3
+        | Sensor::Group4
+        | Sensor::Group5
+        | Sensor::Group6
+        | Sensor::Group100
+        | Sensor::Group101
+        | Sensor::Group106
+        | Sensor::Group107 => unreachable!("Group packets are split up by parse_sensor_data"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        motion::{Motion, MotionController},
+        odometry::WheelGeometry,
+        seven_segment::{SEGMENT_A, SEGMENT_D},
+        virtual_roomba::{Behavior, VirtualRoomba},
+    };
+    use std::f64::consts::FRAC_PI_2;
+    use tokio::io::{DuplexStream, ReadHalf, WriteHalf};
+
+    type TestRoomba = Roomba<ReadHalf<DuplexStream>, WriteHalf<DuplexStream>>;
+
+    async fn connect() -> (TestRoomba, VirtualRoomba) {
+        let (virtual_roomba, stream) = VirtualRoomba::spawn();
+        let (read, write) = tokio::io::split(stream);
+        let roomba = Roomba::new(read, write).await.unwrap();
+
+        (roomba, virtual_roomba)
+    }
+
+    /// The virtual Roomba handles commands in order, so once a query comes back we know
+    /// everything sent before it has been handled too.
+    async fn sync(roomba: &mut TestRoomba) {
+        roomba.query_sensors(&[Sensor::OIMode]).await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn new_starts_the_robot() {
+        let (mut roomba, virtual_roomba) = connect().await;
+
+        roomba.flush().await.unwrap();
+        sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(virtual_roomba.mode(), OIMode::Passive);
+
+        roomba.close().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn close_stops_the_robot() {
+        let (mut roomba, virtual_roomba) = connect().await;
+        roomba.set_mode(OIMode::Safe).await.unwrap();
+
+        roomba.drive(DriveCommand::Straight(200)).await.unwrap();
+        roomba.close().await.unwrap();
+
+        // We can't sync through a query anymore, so give the virtual Roomba a moment.
+        sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(virtual_roomba.mode(), OIMode::Off);
+        assert_eq!(virtual_roomba.wheel_velocities(), (0, 0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn passive_restarts_the_robot() {
+        let (mut roomba, virtual_roomba) = connect().await;
+        roomba.set_mode(OIMode::Off).await.unwrap();
+
+        assert!(matches!(
+            roomba.set_mode(OIMode::Safe).await,
+            Err(Error::ModeTooLow {
+                required: OIMode::Passive,
+                current: OIMode::Off,
+            })
+        ));
+
+        roomba.set_mode(OIMode::Passive).await.unwrap();
+        roomba.set_mode(OIMode::Safe).await.unwrap();
+        sync(&mut roomba).await;
+        assert_eq!(virtual_roomba.mode(), OIMode::Safe);
+
+        roomba.close().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drive_commands() {
+        let (mut roomba, virtual_roomba) = connect().await;
+        roomba.set_mode(OIMode::Safe).await.unwrap();
+
+        roomba.drive(DriveCommand::Straight(-100)).await.unwrap();
+        sync(&mut roomba).await;
+        assert_eq!(virtual_roomba.mode(), OIMode::Safe);
+        assert_eq!(virtual_roomba.wheel_velocities(), (-100, -100));
+
+        roomba
+            .drive(DriveCommand::Turn(TurnDirection::Left(150)))
+            .await
+            .unwrap();
+        sync(&mut roomba).await;
+        assert_eq!(virtual_roomba.wheel_velocities(), (-150, 150));
+
+        roomba
+            .drive(DriveCommand::Turn(TurnDirection::Right(150)))
+            .await
+            .unwrap();
+        sync(&mut roomba).await;
+        assert_eq!(virtual_roomba.wheel_velocities(), (150, -150));
+
+        roomba
+            .drive(DriveCommand::Arc {
+                radius: TurnDirection::Left(500),
+                speed: 200,
+            })
+            .await
+            .unwrap();
+        sync(&mut roomba).await;
+        let (left, right) = virtual_roomba.wheel_velocities();
+        assert!(left < 200 && right > 200, "{left} {right}");
+
+        roomba.drive(DriveCommand::Stop).await.unwrap();
+        sync(&mut roomba).await;
+        assert_eq!(virtual_roomba.wheel_velocities(), (0, 0));
+
+        roomba.close().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drive_direct() {
+        let (mut roomba, virtual_roomba) = connect().await;
+        roomba.set_mode(OIMode::Safe).await.unwrap();
+
+        roomba.drive_direct(-50, 300).await.unwrap();
+        let requested = roomba
+            .query_sensors(&[
+                Sensor::RequestedLeftVelocity,
+                Sensor::RequestedRightVelocity,
+            ])
+            .await
+            .unwrap();
+
+        assert_eq!(virtual_roomba.wheel_velocities(), (-50, 300));
+        assert_eq!(
+            requested,
+            vec![
+                SensorData::RequestedLeftVelocity(-50),
+                SensorData::RequestedRightVelocity(300)
+            ]
+        );
+
+        roomba.close().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn motions_through_the_encoders() {
+        let (mut roomba, _virtual_roomba) = connect().await;
+        let mut sensor_stream = roomba.take_sensor_stream().unwrap();
+
+        roomba.set_mode(OIMode::Safe).await.unwrap();
+        roomba
+            .start_stream(&[Sensor::LeftEncoderCounts, Sensor::RightEncoderCounts])
+            .await
+            .unwrap();
+
+        let motions = [
+            Motion::Drive {
+                distance: 0.3,
+                speed: 0.2,
+            },
+            Motion::Rotate {
+                angle: -FRAC_PI_2,
+                speed: 1.0,
+            },
+        ];
+
+        for motion in motions {
+            let mut controller = MotionController::new(motion, WheelGeometry::default());
+            roomba.drive(controller.drive_command()).await.unwrap();
+            roomba.flush().await.unwrap();
+
+            while !controller.is_done() {
+                let frame = sensor_stream.recv().await.unwrap().unwrap();
+                for data in &frame.data {
+                    controller.update(data);
+                }
+            }
+
+            roomba.drive(DriveCommand::Stop).await.unwrap();
+            roomba.flush().await.unwrap();
+
+            // It can only stop on a frame, so it goes up to a frame further than it was asked to.
+            let overshoot = controller.progress() - controller.target();
+            assert!(overshoot.abs() < 0.02, "{motion:?} overshot by {overshoot}");
+        }
+
+        // The distance and angle add up the same movements, in millimeters and degrees.
+        let readings = roomba
+            .query_sensors(&[Sensor::Distance, Sensor::Angle])
+            .await
+            .unwrap();
+        let [SensorData::Distance(distance), SensorData::Angle(angle)] = readings[..] else {
+            panic!("Unexpected readings {readings:?}");
+        };
+        assert!((distance - 300).abs() <= 5, "Drove {distance} mm");
+        assert!((angle + 90).abs() <= 2, "Turned {angle} degrees");
+
+        roomba.close().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn behaviors() {
+        let (mut roomba, virtual_roomba) = connect().await;
+
+        roomba.clean().await.unwrap();
+        sync(&mut roomba).await;
+        assert_eq!(virtual_roomba.behavior(), Some(Behavior::Clean));
+
+        roomba.spot().await.unwrap();
+        sync(&mut roomba).await;
+        assert_eq!(virtual_roomba.behavior(), Some(Behavior::Spot));
+
+        // Behaviors drop the robot back to Passive mode.
+        roomba.set_mode(OIMode::Full).await.unwrap();
+        roomba.seek_dock().await.unwrap();
+        sync(&mut roomba).await;
+        assert_eq!(virtual_roomba.behavior(), Some(Behavior::SeekDock));
+        assert_eq!(virtual_roomba.mode(), OIMode::Passive);
+        assert_eq!(roomba.mode(), OIMode::Passive);
+
+        roomba.close().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn modes() {
+        let (mut roomba, virtual_roomba) = connect().await;
+
+        assert_eq!(roomba.mode(), OIMode::Passive);
+
+        roomba.set_mode(OIMode::Full).await.unwrap();
+        sync(&mut roomba).await;
+        assert_eq!(virtual_roomba.mode(), OIMode::Full);
+        assert_eq!(roomba.mode(), OIMode::Full);
+
+        roomba.set_mode(OIMode::Passive).await.unwrap();
+        sync(&mut roomba).await;
+        assert_eq!(virtual_roomba.mode(), OIMode::Passive);
+
+        roomba.set_mode(OIMode::Safe).await.unwrap();
+        sync(&mut roomba).await;
+        assert_eq!(virtual_roomba.mode(), OIMode::Safe);
+
+        roomba.close().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn mode_rules() {
+        let (mut roomba, virtual_roomba) = connect().await;
+
+        // Nothing that needs control works in Passive mode, and nothing gets sent.
+        assert!(matches!(
+            roomba.drive(DriveCommand::Straight(100)).await,
+            Err(Error::ModeTooLow {
+                required: OIMode::Safe,
+                current: OIMode::Passive,
+            })
+        ));
+        assert!(matches!(
+            roomba.set_leds(LedState::default()).await,
+            Err(Error::ModeTooLow { .. })
+        ));
+        assert!(matches!(
+            roomba.play_song(0).await,
+            Err(Error::ModeTooLow { .. })
+        ));
+
+        // But sensors do.
+        sync(&mut roomba).await;
+        assert_eq!(virtual_roomba.mode(), OIMode::Passive);
+
+        roomba.set_mode(OIMode::Safe).await.unwrap();
+        roomba.drive(DriveCommand::Straight(100)).await.unwrap();
+        sync(&mut roomba).await;
+        assert_eq!(virtual_roomba.wheel_velocities(), (100, 100));
+
+        // The robot drops out of Safe mode by itself when it's picked up. We find out through the
+        // OI mode sensor.
+        virtual_roomba.set_sensor(SensorData::BumpersAndWheelDrops {
+            wheel_drop_left: true,
+            wheel_drop_right: false,
+            bumper_left: false,
+            bumper_right: false,
+        });
+        assert_eq!(roomba.mode(), OIMode::Safe);
+        sync(&mut roomba).await;
+        assert_eq!(roomba.mode(), OIMode::Passive);
+        assert_eq!(virtual_roomba.wheel_velocities(), (0, 0));
+
+        assert!(matches!(
+            roomba.drive(DriveCommand::Straight(100)).await,
+            Err(Error::ModeTooLow { .. })
+        ));
+
+        roomba.close().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn lost_link() {
+        let (stream, virtual_stream) = tokio::io::duplex(4096);
+        let virtual_roomba = VirtualRoomba::new();
+        let server = tokio::spawn({
+            let virtual_roomba = virtual_roomba.clone();
+            async move { virtual_roomba.serve(virtual_stream).await }
+        });
+
+        let (read, write) = tokio::io::split(stream);
+        let mut roomba = Roomba::new(read, write).await.unwrap();
+        let mut sensor_stream = roomba.take_sensor_stream().unwrap();
+
+        // Pull the plug.
+        server.abort();
+
+        assert!(matches!(
+            sensor_stream.recv().await.unwrap(),
+            Err(Error::IO(_))
+        ));
+        assert!(matches!(
+            roomba.query_sensors(&[Sensor::OIMode]).await,
+            Err(Error::IO(_) | Error::QueryAbandoned)
+        ));
+
+        roomba.disconnect();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn lost_link_during_query() {
+        let (stream, robot) = tokio::io::duplex(4096);
+        let (read, write) = tokio::io::split(stream);
+        let mut roomba = Roomba::new(read, write).await.unwrap();
+        let mut sensor_stream = roomba.take_sensor_stream().unwrap();
+
+        // The robot goes away without answering.
+        let (query, frame) = tokio::join!(roomba.query_sensors(&[Sensor::OIMode]), async {
+            drop(robot);
+            sensor_stream.recv().await
+        });
+
+        assert!(matches!(query, Err(Error::QueryAbandoned)));
+        assert!(matches!(frame, Some(Err(Error::IO(_)))));
+
+        roomba.disconnect();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn silent_robot() {
+        let (stream, mut robot) = tokio::io::duplex(4096);
+        let (read, write) = tokio::io::split(stream);
+        let mut roomba = Roomba::new(read, write).await.unwrap();
+        let mut sensor_stream = roomba.take_sensor_stream().unwrap();
+
+        // Nothing is expected until a stream is asked for.
+        sleep(SILENCE_TIMEOUT * 2).await;
+        assert!(sensor_stream.try_recv().is_err());
+
+        roomba.start_stream(&[Sensor::Wall]).await.unwrap();
+        roomba.flush().await.unwrap();
+
+        let mut frame = vec![STREAM_HEADER, 2, 8, 1];
+        let sum = frame.iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+        frame.push(0u8.wrapping_sub(sum));
+        robot.write_all(&frame).await.unwrap();
+
+        assert_eq!(
+            sensor_stream.recv().await.unwrap().unwrap().data,
+            vec![SensorData::Wall(true)]
+        );
+
+        // A paused stream is allowed to be quiet.
+        roomba.pause_stream(true).await.unwrap();
+        sleep(SILENCE_TIMEOUT * 2).await;
+        assert!(sensor_stream.try_recv().is_err());
+
+        // Then the robot stops answering.
+        roomba.pause_stream(false).await.unwrap();
+        assert!(matches!(
+            sensor_stream.recv().await.unwrap(),
+            Err(Error::Silent(_))
+        ));
+
+        roomba.disconnect();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn query_with_frames_in_flight() {
+        let (stream, mut robot) = tokio::io::duplex(4096);
+        let (read, write) = tokio::io::split(stream);
+        let mut roomba = Roomba::new(read, write).await.unwrap();
+        let mut sensor_stream = roomba.take_sensor_stream().unwrap();
+
+        roomba.start_stream(&[Sensor::Wall]).await.unwrap();
+        roomba.flush().await.unwrap();
+
+        let mut commands = [0u8; 5];
+        robot.read_exact(&mut commands).await.unwrap();
+        assert_eq!(commands, [7, 128, 148, 1, 8]);
+
+        let mut frame = vec![STREAM_HEADER, 2, 8, 1];
+        let sum = frame.iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+        frame.push(0u8.wrapping_sub(sum));
+
+        let (response, ()) = tokio::join!(roomba.query_sensors(&[Sensor::Voltage]), async {
+            // The stream is paused around the query.
+            let mut commands = [0u8; 7];
+            robot.read_exact(&mut commands).await.unwrap();
+            assert_eq!(commands, [150, 0, 149, 1, 22, 150, 1]);
+
+            // A frame sent before the pause, then a voltage of 4866 mV, which looks like the
+            // start of a frame, then the stream picks up again.
+            robot.write_all(&frame).await.unwrap();
+            robot.write_all(&[STREAM_HEADER, 2]).await.unwrap();
+            robot.write_all(&frame).await.unwrap();
+        });
+
+        assert_eq!(response.unwrap(), vec![SensorData::Voltage(4866)]);
+
+        for _ in 0..2 {
+            assert_eq!(
+                sensor_stream.recv().await.unwrap().unwrap().data,
+                vec![SensorData::Wall(true)]
+            );
+        }
+
+        assert_eq!(roomba.stream_statistics(), StreamStatistics::default());
+
+        roomba.disconnect();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn query_timeout() {
+        let (stream, mut robot) = tokio::io::duplex(4096);
+        let (read, write) = tokio::io::split(stream);
+        let mut roomba = Roomba::new(read, write).await.unwrap();
+
+        // The robot never answers.
+        assert!(matches!(
+            roomba.query_sensors(&[Sensor::OIMode]).await,
+            Err(Error::QueryTimeout(_))
+        ));
+
+        // Later queries still work.
+        let (response, ()) = tokio::join!(roomba.query_sensors(&[Sensor::OIMode]), async {
+            robot.write_all(&[1]).await.unwrap();
+        });
+        assert_eq!(response.unwrap(), vec![SensorData::OIMode(OIMode::Passive)]);
+
+        roomba.disconnect();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stale_mode_reports() {
+        let (stream, mut robot) = tokio::io::duplex(4096);
+        let (read, write) = tokio::io::split(stream);
+        let mut roomba = Roomba::new(read, write).await.unwrap();
+        let mut sensor_stream = roomba.take_sensor_stream().unwrap();
+
+        roomba.start_stream(&[Sensor::OIMode]).await.unwrap();
+        roomba.set_mode(OIMode::Safe).await.unwrap();
+        roomba.flush().await.unwrap();
+
+        let report = |mode: OIMode| {
+            let mut frame = vec![STREAM_HEADER, 2, 35, mode.into()];
+            let sum = frame.iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+            frame.push(0u8.wrapping_sub(sum));
+            frame
+        };
+
+        robot.write_all(&report(OIMode::Safe)).await.unwrap();
+        sensor_stream.recv().await.unwrap().unwrap();
+        assert_eq!(roomba.mode(), OIMode::Safe);
+
+        // A frame from before the robot got the command.
+        roomba.seek_dock().await.unwrap();
+        roomba.flush().await.unwrap();
+        robot.write_all(&report(OIMode::Safe)).await.unwrap();
+        sensor_stream.recv().await.unwrap().unwrap();
+        assert_eq!(roomba.mode(), OIMode::Passive);
+
+        robot.write_all(&report(OIMode::Passive)).await.unwrap();
+        sensor_stream.recv().await.unwrap().unwrap();
+        assert_eq!(roomba.mode(), OIMode::Passive);
+
+        // Once the robot has had time to act on a command, it can still change mode on its own,
+        // like when it sees a cliff in Safe mode.
+        roomba.set_mode(OIMode::Safe).await.unwrap();
+        roomba.flush().await.unwrap();
+        sleep(STREAM_PERIOD * 2).await;
+        robot.write_all(&report(OIMode::Passive)).await.unwrap();
+        sensor_stream.recv().await.unwrap().unwrap();
+        assert_eq!(roomba.mode(), OIMode::Passive);
+
+        roomba.disconnect();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn mode_stream_through_seek_dock() {
+        let (mut roomba, virtual_roomba) = connect().await;
+        let mut sensor_stream = roomba.take_sensor_stream().unwrap();
+
+        roomba.set_mode(OIMode::Safe).await.unwrap();
+        roomba.start_stream(&[Sensor::OIMode]).await.unwrap();
+        sync(&mut roomba).await;
+
+        roomba.seek_dock().await.unwrap();
+        roomba.flush().await.unwrap();
+        assert_eq!(roomba.mode(), OIMode::Passive);
+
+        // Frames already on their way can still say Safe, which mustn't look like something took
+        // the robot back out of Passive.
+        for _ in 0..20 {
+            sensor_stream.recv().await.unwrap().unwrap();
+            assert_eq!(roomba.mode(), OIMode::Passive);
+        }
+
+        assert_eq!(virtual_roomba.mode(), OIMode::Passive);
+        assert_eq!(virtual_roomba.behavior(), Some(Behavior::SeekDock));
+
+        roomba.disconnect();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn leds_and_display() {
+        let (mut roomba, virtual_roomba) = connect().await;
+        roomba.set_mode(OIMode::Safe).await.unwrap();
+
+        let leds = LedState {
+            check_robot: true,
+            dock: false,
+            spot: true,
+            debris: false,
+            power_color: 128,
+            power_intensity: 255,
+        };
+        roomba.set_leds(leds).await.unwrap();
+        roomba.set_seven_segment("HELLO").await.unwrap();
+        sync(&mut roomba).await;
+
+        assert_eq!(virtual_roomba.leds(), leds);
+        assert_eq!(&virtual_roomba.display(), b"HELL");
+
+        roomba.set_seven_segment("hé").await.unwrap();
+        sync(&mut roomba).await;
+        assert_eq!(&virtual_roomba.display(), b"HE  ");
+
+        // The top and bottom of the first digit, and the whole last digit.
+        let digits = [SEGMENT_A | SEGMENT_D, 0, 0, ALL_SEGMENTS];
+        roomba.set_digit_segments(digits).await.unwrap();
+
+        let mut scheduling_leds = SchedulingLedState {
+            colon: true,
+            pm: true,
+            ..Default::default()
+        };
+        scheduling_leds.days[u8::from(Weekday::Monday) as usize] = true;
+        scheduling_leds.days[u8::from(Weekday::Saturday) as usize] = true;
+        roomba.set_scheduling_leds(scheduling_leds).await.unwrap();
+        sync(&mut roomba).await;
+
+        assert_eq!(virtual_roomba.digit_segments(), digits);
+        assert_eq!(virtual_roomba.scheduling_leds(), [0x42, 0x03]);
+
+        assert!(matches!(
+            roomba.set_digit_segments([0x80, 0, 0, 0]).await,
+            Err(Error::InvalidDigitSegments(0x80))
+        ));
+
+        roomba.close().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn motors() {
+        let (mut roomba, virtual_roomba) = connect().await;
+        roomba.set_mode(OIMode::Safe).await.unwrap();
+
+        roomba
+            .set_motors(MotorState {
+                main_brush: true,
+                main_brush_outward: true,
+                side_brush: true,
+                side_brush_clockwise: false,
+                vacuum: true,
+            })
+            .await
+            .unwrap();
+        sync(&mut roomba).await;
+        assert_eq!(virtual_roomba.motor_pwm(), (-127, 127, 127));
+
+        roomba.set_motors(MotorState::default()).await.unwrap();
+        sync(&mut roomba).await;
+        assert_eq!(virtual_roomba.motor_pwm(), (0, 0, 0));
+
+        roomba.set_motor_pwm(64, -32, 100).await.unwrap();
+        sync(&mut roomba).await;
+        assert_eq!(virtual_roomba.motor_pwm(), (64, -32, 100));
+
+        assert!(matches!(
+            roomba.set_motor_pwm(i8::MIN, 0, 0).await,
+            Err(Error::MotorPwmRange)
+        ));
+        assert!(matches!(
+            roomba.set_motor_pwm(0, 0, -1).await,
+            Err(Error::MotorPwmRange)
+        ));
+
+        roomba.close().await.unwrap();
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(virtual_roomba.motor_pwm(), (0, 0, 0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn songs() {
+        let (mut roomba, virtual_roomba) = connect().await;
+        roomba.set_mode(OIMode::Safe).await.unwrap();
+
+        let song = Song::new(vec![Note::new(60, 32).unwrap(), Note::rest(16)]).unwrap();
+        roomba.define_song(2, &song).await.unwrap();
+        roomba.play_song(2).await.unwrap();
+
+        assert_eq!(
+            roomba
+                .query_sensors(&[Sensor::SongNumber, Sensor::SongPlaying])
+                .await
+                .unwrap(),
+            vec![SensorData::SongNumber(2), SensorData::SongPlaying(true)]
+        );
+        assert_eq!(virtual_roomba.song(2), Some(vec![(60, 32), (0, 16)]));
+
+        sleep(Duration::from_secs(1)).await;
+        assert_eq!(
+            roomba.query_sensors(&[Sensor::SongPlaying]).await.unwrap(),
+            vec![SensorData::SongPlaying(false)]
+        );
+
+        roomba.close().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn song_validation() {
+        let (mut roomba, _virtual_roomba) = connect().await;
+
+        assert!(matches!(
+            Note::new(30, 10),
+            Err(Error::InvalidNotePitch(30))
+        ));
+        assert!(matches!(
+            Note::new(128, 10),
+            Err(Error::InvalidNotePitch(128))
+        ));
+        assert!(matches!(
+            Song::new(vec![]),
+            Err(Error::InvalidSongLength(0))
+        ));
+        assert!(matches!(
+            Song::new(vec![Note::rest(1); 17]),
+            Err(Error::InvalidSongLength(17))
+        ));
+
+        let song = Song::new(vec![Note::rest(1); 16]).unwrap();
+        assert!(matches!(
+            roomba.define_song(4, &song).await,
+            Err(Error::InvalidSongNumber(4))
+        ));
+        assert!(matches!(
+            roomba.play_song(4).await,
+            Err(Error::InvalidSongNumber(4))
+        ));
+
+        roomba.close().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn clock_and_schedule() {
+        let (mut roomba, virtual_roomba) = connect().await;
+
+        roomba
+            .set_day_time(Weekday::Wednesday, TimeOfDay::new(13, 37).unwrap())
+            .await
+            .unwrap();
+
+        let mut schedule = WeeklySchedule::new();
+        schedule.set(Weekday::Sunday, Some(TimeOfDay::new(9, 30).unwrap()));
+        schedule.set(Weekday::Friday, Some(TimeOfDay::new(18, 5).unwrap()));
+        roomba.set_schedule(&schedule).await.unwrap();
+
+        sync(&mut roomba).await;
+        assert_eq!(virtual_roomba.clock(), [3, 13, 37]);
+        assert_eq!(
+            virtual_roomba.schedule(),
+            [0b0010_0001, 9, 30, 0, 0, 0, 0, 0, 0, 0, 0, 18, 5, 0, 0]
+        );
+
+        assert!(matches!(
+            TimeOfDay::new(24, 0),
+            Err(Error::InvalidTime {
+                hour: 24,
+                minute: 0
+            })
+        ));
+        assert!(TimeOfDay::new(23, 60).is_err());
+
+        roomba.close().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn query() {
+        let (mut roomba, virtual_roomba) = connect().await;
+
+        virtual_roomba.set_sensor(SensorData::Voltage(14_800));
+
+        let response = roomba.query(Sensor::Voltage).await.unwrap();
+        roomba.flush().await.unwrap();
+
+        assert_eq!(response.await.unwrap(), vec![SensorData::Voltage(14_800)]);
+
+        roomba.close().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn query_list_keeps_order() {
+        let (mut roomba, virtual_roomba) = connect().await;
+
+        virtual_roomba.set_sensor(SensorData::BatteryCharge(1200));
+        virtual_roomba.set_sensor(SensorData::ChargingState(ChargingState::TrickleCharging));
+        virtual_roomba.set_sensor(SensorData::Angle(-15));
+
+        let readings = roomba
+            .query_sensors(&[Sensor::Angle, Sensor::BatteryCharge, Sensor::ChargingState])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            readings,
+            vec![
+                SensorData::Angle(-15),
+                SensorData::BatteryCharge(1200),
+                SensorData::ChargingState(ChargingState::TrickleCharging),
+            ]
+        );
+
+        roomba.close().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn groups() {
+        let (mut roomba, virtual_roomba) = connect().await;
+        let mut sensor_stream = roomba.take_sensor_stream().unwrap();
+        let force_field = InfraredCharacter::Roomba600Charger(DockBeams {
+            force_field: true,
+            ..Default::default()
+        });
+
+        virtual_roomba.set_sensor(SensorData::InfraredCharacterOmni(force_field));
+        virtual_roomba.set_sensor(SensorData::Angle(-15));
+        virtual_roomba.set_sensor(SensorData::LightBumpCenterLeftSignal(300));
+
+        let readings = roomba.query_sensors(&[Sensor::Group2]).await.unwrap();
+        assert_eq!(
+            readings,
+            vec![
+                SensorData::InfraredCharacterOmni(force_field),
+                SensorData::Buttons {
+                    clock: false,
+                    schedule: false,
+                    day: false,
+                    hour: false,
+                    minute: false,
+                    dock: false,
+                    spot: false,
+                    clean: false,
+                },
+                SensorData::Distance(0),
+                SensorData::Angle(-15),
+            ]
+        );
+
+        // Everything but the three unused packets.
+        let readings = roomba.query_sensors(&[Sensor::Group100]).await.unwrap();
+        assert_eq!(readings.len(), 49);
+        assert!(readings.contains(&SensorData::LightBumpCenterLeftSignal(300)));
+        assert!(readings.contains(&SensorData::OIMode(OIMode::Passive)));
+
+        roomba.start_stream(&[Sensor::Group106]).await.unwrap();
+        assert_eq!(
+            sensor_stream.recv().await.unwrap().unwrap().data,
+            vec![
+                SensorData::LightBumpLeftSignal(0),
+                SensorData::LightBumpFrontLeftSignal(0),
+                SensorData::LightBumpCenterLeftSignal(300),
+                SensorData::LightBumpCenterRightSignal(0),
+                SensorData::LightBumpFrontRightSignal(0),
+                SensorData::LightBumpRightSignal(0),
+            ]
+        );
+
+        roomba.close().await.unwrap();
+    }
+
+    #[test]
+    fn infrared_characters() {
+        assert_eq!(InfraredCharacter::from(0), InfraredCharacter::NoSignal);
+        assert_eq!(
+            InfraredCharacter::from(136),
+            InfraredCharacter::Remote(RemoteButton::Clean)
+        );
+        assert_eq!(InfraredCharacter::from(162), InfraredCharacter::VirtualWall);
+        assert_eq!(
+            InfraredCharacter::from(169),
+            InfraredCharacter::Roomba600Charger(DockBeams {
+                red_buoy: true,
+                green_buoy: false,
+                force_field: true,
+            })
+        );
+        assert_eq!(
+            InfraredCharacter::from(246),
+            InfraredCharacter::HomeBase(DockBeams {
+                red_buoy: false,
+                green_buoy: true,
+                force_field: true,
+            })
+        );
+        assert_eq!(
+            InfraredCharacter::from(163),
+            InfraredCharacter::Unknown(163)
+        );
+        assert_eq!(
+            InfraredCharacter::from(241),
+            InfraredCharacter::Unknown(241)
+        );
+        assert_eq!(InfraredCharacter::from(162).dock_beams(), None);
+
+        // Nothing gets lost going back to the code the robot sent.
+        for code in 0..=u8::MAX {
+            assert_eq!(u8::from(InfraredCharacter::from(code)), code);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stream() {
+        let (mut roomba, virtual_roomba) = connect().await;
+        let mut sensor_stream = roomba.take_sensor_stream().unwrap();
+        assert!(roomba.take_sensor_stream().is_none());
+
+        virtual_roomba.set_sensor(SensorData::Wall(true));
+        virtual_roomba.set_sensor(SensorData::Current(-1500));
+
+        roomba
+            .start_stream(&[Sensor::Wall, Sensor::Current])
+            .await
+            .unwrap();
+
+        let mut last_received = None;
+
+        for _ in 0..3 {
+            let frame = sensor_stream.recv().await.unwrap().unwrap();
+            assert_eq!(
+                frame.data,
+                vec![SensorData::Wall(true), SensorData::Current(-1500)]
+            );
+
+            // Frames come in at the stream's rate, and are stamped with when they did.
+            if let Some(last_received) = last_received.replace(frame.received) {
+                assert_eq!(frame.received - last_received, Duration::from_millis(15));
+            }
+        }
+
+        roomba.pause_stream(true).await.unwrap();
+        sync(&mut roomba).await;
+        assert!(virtual_roomba.stream_paused());
+
+        // Drain anything sent before the pause took effect.
+        while sensor_stream.try_recv().is_ok() {}
+
+        sleep(Duration::from_millis(100)).await;
+        assert!(sensor_stream.try_recv().is_err());
+
+        roomba.pause_stream(false).await.unwrap();
+        assert_eq!(
+            sensor_stream.recv().await.unwrap().unwrap().data,
+            vec![SensorData::Wall(true), SensorData::Current(-1500)]
+        );
+
+        roomba.close().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn oversized_requests() {
+        let (mut roomba, _virtual_roomba) = connect().await;
+
+        assert!(matches!(
+            roomba.query_list(&[Sensor::Wall; 256]).await,
+            Err(Error::TooManySensors(256))
+        ));
+        assert!(matches!(
+            roomba.start_stream(&[Sensor::Wall; 256]).await,
+            Err(Error::TooManySensors(256))
+        ));
+
+        // Each of these is 80 bytes of data.
+        assert!(matches!(
+            roomba.start_stream(&[Sensor::Group100; 4]).await,
+            Err(Error::FrameTooLong(324))
+        ));
+
+        roomba.start_stream(&[Sensor::Group100; 3]).await.unwrap();
+
+        roomba.close().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stream_resync() {
+        // We play the part of the robot ourselves, so we can send it garbage.
+        let (stream, mut robot) = tokio::io::duplex(4096);
+        let (read, write) = tokio::io::split(stream);
+        let mut roomba = Roomba::new(read, write).await.unwrap();
+        let mut sensor_stream = roomba.take_sensor_stream().unwrap();
+
+        roomba
+            .start_stream(&[Sensor::Wall, Sensor::Current])
+            .await
+            .unwrap();
+        roomba.flush().await.unwrap();
+
+        // The wall sensor, then a current of -1517 mA, which happens to contain the header byte.
+        let mut frame = vec![STREAM_HEADER, 5, 8, 1, 23, 0xFA, STREAM_HEADER];
+        let sum = frame.iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+        frame.push(0u8.wrapping_sub(sum));
+
+        // A header with a length that doesn't match the stream.
+        robot.write_all(&[STREAM_HEADER, 40]).await.unwrap();
+        // The start of a frame that got cut off, so the next one looks like part of it.
+        robot.write_all(&frame[..4]).await.unwrap();
+        robot.write_all(&frame).await.unwrap();
+        robot.write_all(&frame).await.unwrap();
+
+        for _ in 0..2 {
+            assert_eq!(
+                sensor_stream.recv().await.unwrap().unwrap().data,
+                vec![SensorData::Wall(true), SensorData::Current(-1517)]
+            );
+        }
+
+        assert_eq!(
+            roomba.stream_statistics(),
+            StreamStatistics {
+                checksum_failures: 1,
+                resyncs: 2,
+                dropped_packets: 2,
+            }
+        );
+
+        roomba.disconnect();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn query_while_streaming() {
+        let (mut roomba, virtual_roomba) = connect().await;
+        let mut sensor_stream = roomba.take_sensor_stream().unwrap();
+
+        virtual_roomba.set_sensor(SensorData::Voltage(15_000));
+        roomba.start_stream(&[Sensor::Distance]).await.unwrap();
+        sensor_stream.recv().await.unwrap().unwrap();
+
+        // Keep draining the stream so the reader never blocks on it.
+        let drain = tokio::spawn(async move {
+            while let Some(frame) = sensor_stream.recv().await {
+                assert_eq!(frame.unwrap().data, vec![SensorData::Distance(0)]);
+            }
+        });
+
+        for _ in 0..10 {
+            assert_eq!(
+                roomba.query_sensors(&[Sensor::Voltage]).await.unwrap(),
+                vec![SensorData::Voltage(15_000)]
+            );
+        }
+
+        roomba.close().await.unwrap();
+        drain.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn overlapping_queries_while_streaming() {
+        let (stream, mut robot) = tokio::io::duplex(4096);
+        let (read, write) = tokio::io::split(stream);
+        let mut roomba = Roomba::new(read, write).await.unwrap();
+        let mut sensor_stream = roomba.take_sensor_stream().unwrap();
+
+        roomba.start_stream(&[Sensor::Wall]).await.unwrap();
+
+        // Like the read service, several queries can be waiting at once while the stream is read.
+        let mut responses = Vec::new();
+        for _ in 0..3 {
+            responses.push(roomba.query_list(&[Sensor::Voltage]).await.unwrap());
+        }
+        roomba.flush().await.unwrap();
+
+        let mut commands = [0u8; 5 + 3 * 7];
+        robot.read_exact(&mut commands).await.unwrap();
+
+        let mut frame = vec![STREAM_HEADER, 2, 8, 1];
+        let sum = frame.iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+        frame.push(0u8.wrapping_sub(sum));
+
+        // Each response is a voltage of 4866 mV, which looks like the start of a frame. Frames
+        // come in between them, from before each pause and after each resume.
+        for _ in 0..3 {
+            robot.write_all(&frame).await.unwrap();
+            robot.write_all(&[STREAM_HEADER, 2]).await.unwrap();
+        }
+        robot.write_all(&frame).await.unwrap();
+
+        for response in responses {
+            assert_eq!(response.await.unwrap(), vec![SensorData::Voltage(4866)]);
+        }
+
+        for _ in 0..4 {
+            assert_eq!(
+                sensor_stream.recv().await.unwrap().unwrap().data,
+                vec![SensorData::Wall(true)]
+            );
+        }
+
+        assert_eq!(roomba.stream_statistics(), StreamStatistics::default());
+
+        roomba.disconnect();
+    }
+
+    #[test]
+    fn sensor_data_round_trip() {
+        let samples = [
+            SensorData::BumpersAndWheelDrops {
+                wheel_drop_left: true,
+                wheel_drop_right: false,
+                bumper_left: false,
+                bumper_right: true,
+            },
+            SensorData::Wall(true),
+            SensorData::CliffLeft(true),
+            SensorData::CliffFrontLeft(false),
+            SensorData::CliffFrontRight(true),
+            SensorData::CliffRight(false),
+            SensorData::VirtualWall(true),
+            SensorData::WheelOvercurrents {
+                left_wheel: true,
+                right_wheel: false,
+                main_brush: true,
+                side_brush: true,
+            },
+            SensorData::DirtDetect(42),
+            SensorData::InfraredCharacterOmni(InfraredCharacter::Roomba600Charger(DockBeams {
+                force_field: true,
+                ..Default::default()
+            })),
+            SensorData::InfraredCharacterLeft(InfraredCharacter::Roomba600Charger(DockBeams {
+                green_buoy: true,
+                ..Default::default()
+            })),
+            SensorData::InfraredCharacterRight(InfraredCharacter::Remote(RemoteButton::SeekDock)),
+            SensorData::Buttons {
+                clock: true,
+                schedule: false,
+                day: true,
+                hour: false,
+                minute: true,
+                dock: false,
+                spot: true,
+                clean: false,
+            },
+            SensorData::Distance(-300),
+            SensorData::Angle(90),
+            SensorData::ChargingState(ChargingState::ChargingFaultCondition),
+            SensorData::Voltage(16_000),
+            SensorData::Current(-200),
+            SensorData::BatteryTemperature(-5),
+            SensorData::BatteryCharge(2500),
+            SensorData::BatteryCapacity(2696),
+            SensorData::WallSignal(1023),
+            SensorData::CliffLeftSignal(1),
+            SensorData::CliffFrontLeftSignal(2),
+            SensorData::CliffFrontRightSignal(3),
+            SensorData::CliffRightSignal(4095),
+            SensorData::ChargingSourcesAvailable {
+                home_base: true,
+                internal_charger: false,
+            },
+            SensorData::OIMode(OIMode::Full),
+            SensorData::SongNumber(3),
+            SensorData::SongPlaying(true),
+            SensorData::NumberOfStreamPackets(43),
+            SensorData::RequestedVelocity(-500),
+            SensorData::RequestedRadius(2000),
+            SensorData::RequestedRightVelocity(500),
+            SensorData::RequestedLeftVelocity(-499),
+            SensorData::LeftEncoderCounts(65_535),
+            SensorData::RightEncoderCounts(12),
+            SensorData::LightBumper {
+                right: true,
+                front_right: false,
+                center_right: true,
+                center_left: false,
+                front_left: true,
+                left: false,
+            },
+            SensorData::LightBumpLeftSignal(10),
+            SensorData::LightBumpFrontLeftSignal(20),
+            SensorData::LightBumpCenterLeftSignal(30),
+            SensorData::LightBumpCenterRightSignal(40),
+            SensorData::LightBumpFrontRightSignal(50),
+            SensorData::LightBumpRightSignal(60),
+            SensorData::LeftMotorCurrent(-1),
+            SensorData::RightMotorCurrent(1),
+            SensorData::MainBrushMotorCurrent(300),
+            SensorData::SideBrushMotorCurrent(-300),
+            SensorData::IsMovingForward(true),
+        ];
+
+        for sample in samples {
+            let (sensor, bytes) = crate::virtual_roomba::encode_sensor_data(&sample);
+            assert_eq!(bytes.len(), sensor.data_length(), "{sample:?}");
+
+            let mut payload = bytes.into_iter();
+            assert_eq!(parse_packet(sensor, &mut payload).unwrap(), sample);
+            assert!(payload.next().is_none());
+        }
+    }
+
+    #[test]
+    fn parse_reports_short_payloads() {
+        let mut payload = [0x01u8].into_iter();
+
+        assert!(matches!(
+            parse_sensor_data(Sensor::Voltage, &mut payload).as_slice(),
+            [Err(Error::UnexpectedEnd)]
+        ));
+
+        // A group stops at the first sensor it runs out on.
+        let mut payload = [0x00u8; 5].into_iter();
+        let sensor_data = parse_sensor_data(Sensor::Group106, &mut payload);
+        assert_eq!(sensor_data.len(), 3);
+        assert!(matches!(sensor_data[2], Err(Error::UnexpectedEnd)));
+    }
+
+    #[test]
+    fn group_lengths() {
+        // As given by the Open Interface spec.
+        for (group, length) in [
+            (Sensor::Group0, 26),
+            (Sensor::Group1, 10),
+            (Sensor::Group2, 6),
+            (Sensor::Group3, 10),
+            (Sensor::Group4, 14),
+            (Sensor::Group5, 12),
+            (Sensor::Group6, 52),
+            (Sensor::Group100, 80),
+            (Sensor::Group101, 28),
+            (Sensor::Group106, 12),
+            (Sensor::Group107, 9),
+        ] {
+            assert_eq!(group.data_length(), length, "{group:?}");
+        }
+    }
+
+    #[test]
+    fn group_includes() {
+        assert!(Sensor::Wall.includes(Sensor::Wall));
+        assert!(!Sensor::Wall.includes(Sensor::Angle));
+        assert!(Sensor::Group2.includes(Sensor::Angle));
+        assert!(!Sensor::Group2.includes(Sensor::Voltage));
+        assert!(Sensor::Group100.includes(Sensor::RightEncoderCounts));
+        assert!(!Sensor::Group100.includes(Sensor::Group2));
     }
 }
@@ -0,0 +1,337 @@
+//! Keeping the robot out of trouble in Full mode, where it ignores its own cliff and wheel drop
+//! sensors.
+
+use crate::roomba_interface::{DriveCommand, SensorData, TurnDirection};
+
+/// Something that can make driving unsafe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hazard {
+    WheelDrop,
+    Cliff,
+    Bump,
+    WheelOvercurrent,
+}
+
+impl std::fmt::Display for Hazard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::WheelDrop => write!(f, "a wheel has dropped"),
+            Self::Cliff => write!(f, "there is a cliff"),
+            Self::Bump => write!(f, "the bumper is pressed"),
+            Self::WheelOvercurrent => write!(f, "a wheel motor is overloaded"),
+        }
+    }
+}
+
+/// What to do about a hazard while it's there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SafetyPolicy {
+    /// Leave it to whoever is driving.
+    Ignore,
+
+    /// Stop if driving forwards, and only allow backing off or turning on the spot.
+    BlockForward,
+
+    /// Stop, and don't allow driving at all.
+    Stop,
+}
+
+impl SafetyPolicy {
+    fn allows(self, movement: Movement) -> bool {
+        match self {
+            Self::Ignore => true,
+            Self::BlockForward => movement != Movement::Forward,
+            Self::Stop => movement == Movement::Stopped,
+        }
+    }
+}
+
+/// What to do about each hazard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SafetyPolicies {
+    pub wheel_drop: SafetyPolicy,
+    pub cliff: SafetyPolicy,
+    pub bump: SafetyPolicy,
+    pub wheel_overcurrent: SafetyPolicy,
+}
+
+impl Default for SafetyPolicies {
+    fn default() -> Self {
+        Self {
+            wheel_drop: SafetyPolicy::Stop,
+            cliff: SafetyPolicy::BlockForward,
+            bump: SafetyPolicy::BlockForward,
+            wheel_overcurrent: SafetyPolicy::Stop,
+        }
+    }
+}
+
+impl SafetyPolicies {
+    /// Lets everything through.
+    pub fn disabled() -> Self {
+        Self {
+            wheel_drop: SafetyPolicy::Ignore,
+            cliff: SafetyPolicy::Ignore,
+            bump: SafetyPolicy::Ignore,
+            wheel_overcurrent: SafetyPolicy::Ignore,
+        }
+    }
+}
+
+/// Which hazards the sensors are reporting right now.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Hazards {
+    pub wheel_drop: bool,
+    pub cliff: bool,
+    pub bump: bool,
+    pub wheel_overcurrent: bool,
+}
+
+/// How a drive command moves the robot, as far as safety is concerned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Movement {
+    Stopped,
+    Forward,
+    Backward,
+
+    /// On the spot.
+    Turning,
+}
+
+impl Movement {
+    pub fn of_drive(command: &DriveCommand) -> Self {
+        let speed = match command {
+            DriveCommand::Stop => 0,
+            DriveCommand::Straight(speed) | DriveCommand::Arc { speed, .. } => *speed,
+            DriveCommand::Turn(TurnDirection::Left(speed) | TurnDirection::Right(speed)) => {
+                return if *speed == 0 {
+                    Self::Stopped
+                } else {
+                    Self::Turning
+                };
+            }
+        };
+
+        Self::of_speed(speed as i32)
+    }
+
+    pub fn of_direct_drive(left_wheel_velocity: i16, right_wheel_velocity: i16) -> Self {
+        let (left, right) = (left_wheel_velocity as i32, right_wheel_velocity as i32);
+
+        if left == -right && left != 0 {
+            Self::Turning
+        } else {
+            Self::of_speed(left + right)
+        }
+    }
+
+    fn of_speed(speed: i32) -> Self {
+        match speed {
+            0 => Self::Stopped,
+            speed if speed > 0 => Self::Forward,
+            _ => Self::Backward,
+        }
+    }
+}
+
+/// Watches the cliff, wheel drop, bumper and overcurrent sensors, and decides which drive
+/// commands are safe to send. Those sensors need to be part of the sensor stream for this to do
+/// anything.
+#[derive(Debug)]
+pub struct SafetySupervisor {
+    policies: SafetyPolicies,
+    hazards: Hazards,
+    cliffs: [bool; 4],
+
+    /// What the last command we let through does.
+    movement: Movement,
+}
+
+impl SafetySupervisor {
+    pub fn new(policies: SafetyPolicies) -> Self {
+        Self {
+            policies,
+            hazards: Hazards::default(),
+            cliffs: [false; 4],
+            movement: Movement::Stopped,
+        }
+    }
+
+    pub fn policies(&self) -> SafetyPolicies {
+        self.policies
+    }
+
+    pub fn hazards(&self) -> Hazards {
+        self.hazards
+    }
+
+    /// Whether `movement` would be allowed right now. Gives the hazard in the way if not.
+    pub fn allows(&self, movement: Movement) -> Result<(), Hazard> {
+        [
+            (
+                Hazard::WheelDrop,
+                self.hazards.wheel_drop,
+                self.policies.wheel_drop,
+            ),
+            (Hazard::Cliff, self.hazards.cliff, self.policies.cliff),
+            (Hazard::Bump, self.hazards.bump, self.policies.bump),
+            (
+                Hazard::WheelOvercurrent,
+                self.hazards.wheel_overcurrent,
+                self.policies.wheel_overcurrent,
+            ),
+        ]
+        .into_iter()
+        .find(|(_, present, policy)| *present && !policy.allows(movement))
+        .map_or(Ok(()), |(hazard, _, _)| Err(hazard))
+    }
+
+    /// Call before sending every drive command. If it's allowed, it's remembered so we know to
+    /// stop the robot if a hazard shows up while it's going. If not, the robot should be stopped
+    /// instead, so it doesn't carry on with whatever it was doing before.
+    pub fn check(&mut self, movement: Movement) -> Result<(), Hazard> {
+        let allowed = self.allows(movement);
+        self.movement = match allowed {
+            Ok(()) => movement,
+            Err(_) => Movement::Stopped,
+        };
+
+        allowed
+    }
+
+    /// Call when the robot gets stopped without going through `check`.
+    pub fn stopped(&mut self) {
+        self.movement = Movement::Stopped;
+    }
+
+    /// Feed in sensor data as it arrives. Returns the hazard if the robot has to be stopped
+    /// because of it.
+    pub fn update(&mut self, data: &SensorData) -> Option<Hazard> {
+        match data {
+            SensorData::BumpersAndWheelDrops {
+                wheel_drop_left,
+                wheel_drop_right,
+                bumper_left,
+                bumper_right,
+            } => {
+                self.hazards.wheel_drop = *wheel_drop_left || *wheel_drop_right;
+                self.hazards.bump = *bumper_left || *bumper_right;
+            }
+            SensorData::CliffLeft(cliff) => self.set_cliff(0, *cliff),
+            SensorData::CliffFrontLeft(cliff) => self.set_cliff(1, *cliff),
+            SensorData::CliffFrontRight(cliff) => self.set_cliff(2, *cliff),
+            SensorData::CliffRight(cliff) => self.set_cliff(3, *cliff),
+            SensorData::WheelOvercurrents {
+                left_wheel,
+                right_wheel,
+                ..
+            } => self.hazards.wheel_overcurrent = *left_wheel || *right_wheel,
+            _ => return None,
+        }
+
+        let hazard = self.allows(self.movement).err()?;
+        self.movement = Movement::Stopped;
+
+        Some(hazard)
+    }
+
+    fn set_cliff(&mut self, index: usize, cliff: bool) {
+        self.cliffs[index] = cliff;
+        self.hazards.cliff = self.cliffs.contains(&true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bumpers(bumper: bool, wheel_drop: bool) -> SensorData {
+        SensorData::BumpersAndWheelDrops {
+            wheel_drop_left: wheel_drop,
+            wheel_drop_right: false,
+            bumper_left: false,
+            bumper_right: bumper,
+        }
+    }
+
+    #[test]
+    fn movements() {
+        assert_eq!(Movement::of_drive(&DriveCommand::Stop), Movement::Stopped);
+        assert_eq!(
+            Movement::of_drive(&DriveCommand::Straight(-100)),
+            Movement::Backward
+        );
+        assert_eq!(
+            Movement::of_drive(&DriveCommand::Turn(TurnDirection::Left(100))),
+            Movement::Turning
+        );
+        assert_eq!(
+            Movement::of_drive(&DriveCommand::Arc {
+                radius: TurnDirection::Right(500),
+                speed: 200
+            }),
+            Movement::Forward
+        );
+        assert_eq!(Movement::of_direct_drive(0, 0), Movement::Stopped);
+        assert_eq!(Movement::of_direct_drive(-100, 100), Movement::Turning);
+        assert_eq!(Movement::of_direct_drive(100, 0), Movement::Forward);
+        assert_eq!(Movement::of_direct_drive(-200, 100), Movement::Backward);
+    }
+
+    #[test]
+    fn pushing_into_a_bump() {
+        let mut supervisor = SafetySupervisor::new(SafetyPolicies::default());
+
+        assert_eq!(supervisor.check(Movement::Forward), Ok(()));
+        assert_eq!(supervisor.update(&bumpers(false, false)), None);
+
+        // Driving into it stops the robot, but backing off is fine.
+        assert_eq!(supervisor.update(&bumpers(true, false)), Some(Hazard::Bump));
+        assert_eq!(supervisor.check(Movement::Forward), Err(Hazard::Bump));
+        assert_eq!(supervisor.check(Movement::Backward), Ok(()));
+        assert_eq!(supervisor.update(&bumpers(true, false)), None);
+
+        supervisor.update(&bumpers(false, false));
+        assert_eq!(supervisor.check(Movement::Forward), Ok(()));
+    }
+
+    #[test]
+    fn cliffs() {
+        let mut supervisor = SafetySupervisor::new(SafetyPolicies::default());
+
+        supervisor.check(Movement::Turning).unwrap();
+        assert_eq!(supervisor.update(&SensorData::CliffFrontLeft(true)), None);
+        assert_eq!(supervisor.update(&SensorData::CliffRight(false)), None);
+        assert!(supervisor.hazards().cliff);
+        assert_eq!(supervisor.check(Movement::Forward), Err(Hazard::Cliff));
+
+        supervisor.update(&SensorData::CliffFrontLeft(false));
+        assert!(!supervisor.hazards().cliff);
+    }
+
+    #[test]
+    fn wheel_drop_stops_everything() {
+        let mut supervisor = SafetySupervisor::new(SafetyPolicies::default());
+
+        supervisor.check(Movement::Backward).unwrap();
+        assert_eq!(
+            supervisor.update(&bumpers(true, true)),
+            Some(Hazard::WheelDrop)
+        );
+        assert_eq!(supervisor.check(Movement::Turning), Err(Hazard::WheelDrop));
+        assert_eq!(supervisor.check(Movement::Stopped), Ok(()));
+
+        // Nothing to stop once it's been vetoed.
+        supervisor.check(Movement::Forward).unwrap_err();
+        assert_eq!(supervisor.update(&bumpers(false, true)), None);
+    }
+
+    #[test]
+    fn disabled() {
+        let mut supervisor = SafetySupervisor::new(SafetyPolicies::disabled());
+
+        supervisor.check(Movement::Forward).unwrap();
+        assert_eq!(supervisor.update(&bumpers(true, true)), None);
+        assert_eq!(supervisor.check(Movement::Forward), Ok(()));
+    }
+}
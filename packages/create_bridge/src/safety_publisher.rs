@@ -0,0 +1,94 @@
+use anyhow::{bail, Context, Result};
+use create_bridge::safety::{Hazards, Movement, SafetyPolicies, SafetyPolicy, SafetySupervisor};
+use r2r::{
+    create_bridge_interface::msg::SafetyStatus, std_msgs::msg::Header, Node, Publisher, QosProfile,
+};
+
+use crate::timestamp::Timestamp;
+
+/// Sets up the safety supervisor from the `safety.*` parameters. It's off unless
+/// `safety.enabled` is set, in which case each hazard can be given its own policy.
+pub fn safety_supervisor_from_parameters(node: &Node) -> Result<SafetySupervisor> {
+    let enabled: Option<bool> = node
+        .get_parameter("safety.enabled")
+        .context("Failed to get safety supervisor setting.")?;
+
+    if !enabled.unwrap_or(false) {
+        return Ok(SafetySupervisor::new(SafetyPolicies::disabled()));
+    }
+
+    let defaults = SafetyPolicies::default();
+    let policies = SafetyPolicies {
+        wheel_drop: policy_parameter(node, "wheel_drop")?.unwrap_or(defaults.wheel_drop),
+        cliff: policy_parameter(node, "cliff")?.unwrap_or(defaults.cliff),
+        bump: policy_parameter(node, "bump")?.unwrap_or(defaults.bump),
+        wheel_overcurrent: policy_parameter(node, "wheel_overcurrent")?
+            .unwrap_or(defaults.wheel_overcurrent),
+    };
+
+    Ok(SafetySupervisor::new(policies))
+}
+
+fn policy_parameter(node: &Node, hazard: &str) -> Result<Option<SafetyPolicy>> {
+    let policy: Option<String> = node
+        .get_parameter(&format!("safety.{hazard}.policy"))
+        .with_context(|| format!("Failed to get {hazard} safety policy."))?;
+
+    let policy = match policy.as_deref() {
+        None => return Ok(None),
+        Some("ignore") => SafetyPolicy::Ignore,
+        Some("block_forward") => SafetyPolicy::BlockForward,
+        Some("stop") => SafetyPolicy::Stop,
+        Some(policy) => bail!(
+            "Unknown safety policy {policy} for {hazard}. Expected ignore, block_forward, or stop."
+        ),
+    };
+
+    Ok(Some(policy))
+}
+
+/// Lets clients know what the safety supervisor is holding the robot back from. Latched, and only
+/// published when something changes.
+pub struct SafetyPublisher {
+    publisher: Publisher<SafetyStatus>,
+    enabled: bool,
+    last_published: Option<Hazards>,
+}
+
+impl SafetyPublisher {
+    pub fn new(node: &mut Node, supervisor: &SafetySupervisor) -> Result<Self> {
+        Ok(Self {
+            publisher: node.create_publisher::<SafetyStatus>(
+                "safety/status",
+                QosProfile::default().transient_local(),
+            )?,
+            enabled: supervisor.policies() != SafetyPolicies::disabled(),
+            last_published: None,
+        })
+    }
+
+    pub fn publish(&mut self, supervisor: &SafetySupervisor, timestamp: &Timestamp) -> Result<()> {
+        let hazards = supervisor.hazards();
+        if self.last_published == Some(hazards) {
+            return Ok(());
+        }
+        self.last_published = Some(hazards);
+
+        self.publisher.publish(&SafetyStatus {
+            header: Header {
+                stamp: timestamp.ros.clone(),
+                frame_id: String::new(),
+            },
+            enabled: self.enabled,
+            wheel_drop: hazards.wheel_drop,
+            cliff: hazards.cliff,
+            bump: hazards.bump,
+            wheel_overcurrent: hazards.wheel_overcurrent,
+            forward_allowed: supervisor.allows(Movement::Forward).is_ok(),
+            backward_allowed: supervisor.allows(Movement::Backward).is_ok(),
+            turning_allowed: supervisor.allows(Movement::Turning).is_ok(),
+        })?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,251 @@
+//! Where the Roomba's obstacle sensors sit, so their readings can be turned into positions.
+
+use crate::roomba_interface::SensorData;
+
+/// Where a sensor sits on the robot, relative to the robot's base frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SensorPlacement {
+    /// Forward, in meters.
+    pub x: f64,
+
+    /// To the left, in meters.
+    pub y: f64,
+
+    /// Up, in meters.
+    pub z: f64,
+
+    /// Which way the sensor faces. Counter clockwise from straight ahead, in radians.
+    pub yaw: f64,
+}
+
+impl SensorPlacement {
+    /// On the edge of a round robot, at `angle` counter clockwise from straight ahead, facing
+    /// outwards.
+    pub fn on_edge(radius: f64, angle: f64, z: f64) -> Self {
+        Self {
+            x: radius * angle.cos(),
+            y: radius * angle.sin(),
+            z,
+            yaw: angle,
+        }
+    }
+
+    /// The point `distance` meters in front of the sensor, in the robot's base frame.
+    pub fn point_ahead(&self, distance: f64) -> [f64; 3] {
+        [
+            self.x + distance * self.yaw.cos(),
+            self.y + distance * self.yaw.sin(),
+            self.z,
+        ]
+    }
+}
+
+/// Where each of the obstacle sensors sits.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SensorGeometry {
+    pub bumper_left: SensorPlacement,
+    pub bumper_right: SensorPlacement,
+    pub cliff_left: SensorPlacement,
+    pub cliff_front_left: SensorPlacement,
+    pub cliff_front_right: SensorPlacement,
+    pub cliff_right: SensorPlacement,
+    pub light_bump_left: SensorPlacement,
+    pub light_bump_front_left: SensorPlacement,
+    pub light_bump_center_left: SensorPlacement,
+    pub light_bump_center_right: SensorPlacement,
+    pub light_bump_front_right: SensorPlacement,
+    pub light_bump_right: SensorPlacement,
+}
+
+impl Default for SensorGeometry {
+    /// Roughly where the sensors are on a Create 2. The spec doesn't say, so these are estimates
+    /// that are worth checking against your own robot.
+    fn default() -> Self {
+        const BUMPER_RADIUS: f64 = 0.17;
+        const CLIFF_RADIUS: f64 = 0.15;
+
+        Self {
+            bumper_left: SensorPlacement::on_edge(BUMPER_RADIUS, 0.6, 0.03),
+            bumper_right: SensorPlacement::on_edge(BUMPER_RADIUS, -0.6, 0.03),
+            cliff_left: SensorPlacement::on_edge(CLIFF_RADIUS, 1.05, 0.0),
+            cliff_front_left: SensorPlacement::on_edge(CLIFF_RADIUS, 0.3, 0.0),
+            cliff_front_right: SensorPlacement::on_edge(CLIFF_RADIUS, -0.3, 0.0),
+            cliff_right: SensorPlacement::on_edge(CLIFF_RADIUS, -1.05, 0.0),
+            light_bump_left: SensorPlacement::on_edge(BUMPER_RADIUS, 1.13, 0.05),
+            light_bump_front_left: SensorPlacement::on_edge(BUMPER_RADIUS, 0.6, 0.05),
+            light_bump_center_left: SensorPlacement::on_edge(BUMPER_RADIUS, 0.2, 0.05),
+            light_bump_center_right: SensorPlacement::on_edge(BUMPER_RADIUS, -0.2, 0.05),
+            light_bump_front_right: SensorPlacement::on_edge(BUMPER_RADIUS, -0.6, 0.05),
+            light_bump_right: SensorPlacement::on_edge(BUMPER_RADIUS, -1.13, 0.05),
+        }
+    }
+}
+
+impl SensorGeometry {
+    /// Every sensor, along with its name.
+    pub fn placements(&self) -> [(&'static str, SensorPlacement); 12] {
+        [
+            ("bumper_left", self.bumper_left),
+            ("bumper_right", self.bumper_right),
+            ("cliff_left", self.cliff_left),
+            ("cliff_front_left", self.cliff_front_left),
+            ("cliff_front_right", self.cliff_front_right),
+            ("cliff_right", self.cliff_right),
+            ("light_bump_left", self.light_bump_left),
+            ("light_bump_front_left", self.light_bump_front_left),
+            ("light_bump_center_left", self.light_bump_center_left),
+            ("light_bump_center_right", self.light_bump_center_right),
+            ("light_bump_front_right", self.light_bump_front_right),
+            ("light_bump_right", self.light_bump_right),
+        ]
+    }
+
+    /// Where the bumpers and cliff sensors in `data` have found something, in the robot's base
+    /// frame. Gives `None` if `data` has none of those sensors in it, so there's nothing to say
+    /// either way.
+    pub fn obstacle_points(&self, data: &[SensorData]) -> Option<Vec<[f64; 3]>> {
+        let mut seen = false;
+        let mut points = Vec::new();
+
+        for data in data {
+            let (triggered, placement) = match data {
+                SensorData::BumpersAndWheelDrops {
+                    bumper_left,
+                    bumper_right,
+                    ..
+                } => {
+                    seen = true;
+                    if *bumper_left {
+                        points.push(self.bumper_left.point_ahead(0.0));
+                    }
+                    if *bumper_right {
+                        points.push(self.bumper_right.point_ahead(0.0));
+                    }
+                    continue;
+                }
+                SensorData::CliffLeft(cliff) => (*cliff, &self.cliff_left),
+                SensorData::CliffFrontLeft(cliff) => (*cliff, &self.cliff_front_left),
+                SensorData::CliffFrontRight(cliff) => (*cliff, &self.cliff_front_right),
+                SensorData::CliffRight(cliff) => (*cliff, &self.cliff_right),
+                _ => continue,
+            };
+
+            seen = true;
+            if triggered {
+                points.push(placement.point_ahead(0.0));
+            }
+        }
+
+        seen.then_some(points)
+    }
+}
+
+/// How to turn a light bump signal into a distance. The signal is how much infrared light bounces
+/// back, which falls off with the square of the distance, but also depends on what it bounces off.
+/// Dark surfaces look further away than they are.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightBumpCalibration {
+    /// The closest distance that can be told apart, in meters.
+    pub min_range: f64,
+
+    /// The distance at which the signal drops to `detection_signal`, in meters.
+    pub max_range: f64,
+
+    /// How wide the sensor's beam is, in radians.
+    pub field_of_view: f64,
+
+    /// Signals weaker than this are taken to be nothing at all.
+    pub detection_signal: u16,
+}
+
+impl Default for LightBumpCalibration {
+    fn default() -> Self {
+        Self {
+            min_range: 0.01,
+            max_range: 0.15,
+            field_of_view: 0.35,
+            detection_signal: 100,
+        }
+    }
+}
+
+impl LightBumpCalibration {
+    /// How far away whatever the sensor sees is, in meters. Nothing at all is infinitely far away.
+    pub fn range(&self, signal: u16) -> f64 {
+        if signal == 0 || signal < self.detection_signal {
+            return f64::INFINITY;
+        }
+
+        let range = self.max_range * (self.detection_signal as f64 / signal as f64).sqrt();
+        range.clamp(self.min_range, self.max_range)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: [f64; 3], b: [f64; 3]) {
+        for (a, b) in a.iter().zip(b) {
+            assert!((a - b).abs() < 1e-9, "{a} != {b}");
+        }
+    }
+
+    #[test]
+    fn placement_on_edge() {
+        let placement = SensorPlacement::on_edge(0.2, std::f64::consts::FRAC_PI_2, 0.05);
+
+        assert_close([placement.x, placement.y, placement.z], [0.0, 0.2, 0.05]);
+        assert_close(placement.point_ahead(0.1), [0.0, 0.3, 0.05]);
+    }
+
+    #[test]
+    fn obstacle_points() {
+        let geometry = SensorGeometry::default();
+
+        assert_eq!(geometry.obstacle_points(&[SensorData::Wall(true)]), None);
+        assert_eq!(
+            geometry.obstacle_points(&[SensorData::CliffLeft(false)]),
+            Some(vec![])
+        );
+
+        let points = geometry
+            .obstacle_points(&[
+                SensorData::BumpersAndWheelDrops {
+                    wheel_drop_left: true,
+                    wheel_drop_right: false,
+                    bumper_left: false,
+                    bumper_right: true,
+                },
+                SensorData::CliffFrontLeft(true),
+                SensorData::CliffRight(false),
+            ])
+            .unwrap();
+
+        assert_eq!(points.len(), 2);
+        assert_close(points[0], geometry.bumper_right.point_ahead(0.0));
+        assert_close(points[1], geometry.cliff_front_left.point_ahead(0.0));
+    }
+
+    #[test]
+    fn light_bump_range() {
+        let calibration = LightBumpCalibration::default();
+
+        assert_eq!(calibration.range(0), f64::INFINITY);
+        assert_eq!(calibration.range(99), f64::INFINITY);
+        assert_eq!(calibration.range(100), 0.15);
+
+        // Four times the light means half the distance.
+        assert!((calibration.range(400) - 0.075).abs() < 1e-9);
+
+        assert_eq!(calibration.range(4095), 0.15 * (100.0f64 / 4095.0).sqrt());
+        assert_eq!(
+            LightBumpCalibration {
+                min_range: 0.05,
+                ..calibration
+            }
+            .range(4095),
+            0.05
+        );
+    }
+}
@@ -1,13 +1,22 @@
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
 use r2r::{
     create_bridge_interface::msg::{
         BumpersAndWheelDrops, Buttons, ChargingSourcesAvailable, ChargingState, LightBumper,
-        OIMode, SensorQuery, WheelOvercurrents,
+        OIMode, RobotState, SensorQuery, SensorReadings, WheelOvercurrents,
     },
-    std_msgs::msg::{Bool, Int16, Int8, UInt16, UInt8},
-    Node, Publisher, QosProfile, Result,
+    std_msgs::msg::{Bool, Header, Int16, Int8, UInt16, UInt8},
+    Node, Publisher, QosProfile, WrappedTypesupport,
+};
+use tokio::time::Instant;
+
+use create_bridge::{
+    publish_policy::{PublishFilter, PublishPolicy},
+    roomba_interface::{Sensor, SensorData},
 };
 
-use crate::roomba_interface::{Sensor, SensorData};
+use crate::timestamp::Timestamp;
 
 pub fn query_list_from_ros_message(message: &SensorQuery) -> Vec<Sensor> {
     let mut sensor_list = Vec::new();
@@ -159,241 +168,582 @@ pub fn query_list_from_ros_message(message: &SensorQuery) -> Vec<Sensor> {
     if message.is_moving_forward {
         sensor_list.push(Sensor::IsMovingForward);
     }
+    if message.group_0 {
+        sensor_list.push(Sensor::Group0);
+    }
+    if message.group_1 {
+        sensor_list.push(Sensor::Group1);
+    }
+    if message.group_2 {
+        sensor_list.push(Sensor::Group2);
+    }
+    if message.group_3 {
+        sensor_list.push(Sensor::Group3);
+    }
+    if message.group_4 {
+        sensor_list.push(Sensor::Group4);
+    }
+    if message.group_5 {
+        sensor_list.push(Sensor::Group5);
+    }
+    if message.group_6 {
+        sensor_list.push(Sensor::Group6);
+    }
+    if message.group_100 {
+        sensor_list.push(Sensor::Group100);
+    }
+    if message.group_101 {
+        sensor_list.push(Sensor::Group101);
+    }
+    if message.group_106 {
+        sensor_list.push(Sensor::Group106);
+    }
+    if message.group_107 {
+        sensor_list.push(Sensor::Group107);
+    }
 
     sensor_list
 }
 
+/// Collect a set of sensor values into a single message.
+/// Only the sensors that show up in `data` are marked as present.
+pub fn readings_from_sensor_data(data: impl IntoIterator<Item = SensorData>) -> SensorReadings {
+    let mut readings = SensorReadings::default();
+
+    for data in data {
+        match data {
+            SensorData::BumpersAndWheelDrops {
+                wheel_drop_left,
+                wheel_drop_right,
+                bumper_left,
+                bumper_right,
+            } => {
+                readings.present.bumpers_and_wheel_drops = true;
+                readings.bumpers_and_wheel_drops = BumpersAndWheelDrops {
+                    wheel_drop_left,
+                    wheel_drop_right,
+                    bumper_left,
+                    bumper_right,
+                };
+            }
+            SensorData::Wall(data) => {
+                readings.present.wall = true;
+                readings.wall = data;
+            }
+            SensorData::CliffLeft(data) => {
+                readings.present.cliff_left = true;
+                readings.cliff_left = data;
+            }
+            SensorData::CliffFrontLeft(data) => {
+                readings.present.cliff_front_left = true;
+                readings.cliff_front_left = data;
+            }
+            SensorData::CliffFrontRight(data) => {
+                readings.present.cliff_front_right = true;
+                readings.cliff_front_right = data;
+            }
+            SensorData::CliffRight(data) => {
+                readings.present.cliff_right = true;
+                readings.cliff_right = data;
+            }
+            SensorData::VirtualWall(data) => {
+                readings.present.virtual_wall = true;
+                readings.virtual_wall = data;
+            }
+            SensorData::WheelOvercurrents {
+                left_wheel,
+                right_wheel,
+                main_brush,
+                side_brush,
+            } => {
+                readings.present.wheel_overcurrents = true;
+                readings.wheel_overcurrents = WheelOvercurrents {
+                    left_wheel,
+                    right_wheel,
+                    main_brush,
+                    side_brush,
+                };
+            }
+            SensorData::DirtDetect(data) => {
+                readings.present.dirt_detect = true;
+                readings.dirt_detect = data;
+            }
+            SensorData::InfraredCharacterOmni(data) => {
+                readings.present.infrared_character_omni = true;
+                readings.infrared_character_omni = data.into();
+            }
+            SensorData::InfraredCharacterLeft(data) => {
+                readings.present.infrared_character_left = true;
+                readings.infrared_character_left = data.into();
+            }
+            SensorData::InfraredCharacterRight(data) => {
+                readings.present.infrared_character_right = true;
+                readings.infrared_character_right = data.into();
+            }
+            SensorData::Buttons {
+                clock,
+                schedule,
+                day,
+                hour,
+                minute,
+                dock,
+                spot,
+                clean,
+            } => {
+                readings.present.buttons = true;
+                readings.buttons = Buttons {
+                    clock,
+                    schedule,
+                    day,
+                    hour,
+                    minute,
+                    dock,
+                    spot,
+                    clean,
+                };
+            }
+            SensorData::Distance(data) => {
+                readings.present.distance = true;
+                readings.distance = data;
+            }
+            SensorData::Angle(data) => {
+                readings.present.angle = true;
+                readings.angle = data;
+            }
+            SensorData::ChargingState(state) => {
+                readings.present.charging_state = true;
+                readings.charging_state = ChargingState {
+                    state: state.into(),
+                };
+            }
+            SensorData::Voltage(data) => {
+                readings.present.voltage = true;
+                readings.voltage = data;
+            }
+            SensorData::Current(data) => {
+                readings.present.current = true;
+                readings.current = data;
+            }
+            SensorData::BatteryTemperature(data) => {
+                readings.present.battery_temperature = true;
+                readings.battery_temperature = data;
+            }
+            SensorData::BatteryCharge(data) => {
+                readings.present.battery_charge = true;
+                readings.battery_charge = data;
+            }
+            SensorData::BatteryCapacity(data) => {
+                readings.present.battery_capacity = true;
+                readings.battery_capacity = data;
+            }
+            SensorData::WallSignal(data) => {
+                readings.present.wall_signal = true;
+                readings.wall_signal = data;
+            }
+            SensorData::CliffLeftSignal(data) => {
+                readings.present.cliff_left_signal = true;
+                readings.cliff_left_signal = data;
+            }
+            SensorData::CliffFrontLeftSignal(data) => {
+                readings.present.cliff_front_left_signal = true;
+                readings.cliff_front_left_signal = data;
+            }
+            SensorData::CliffFrontRightSignal(data) => {
+                readings.present.cliff_front_right_signal = true;
+                readings.cliff_front_right_signal = data;
+            }
+            SensorData::CliffRightSignal(data) => {
+                readings.present.cliff_right_signal = true;
+                readings.cliff_right_signal = data;
+            }
+            SensorData::ChargingSourcesAvailable {
+                home_base,
+                internal_charger,
+            } => {
+                readings.present.charging_sources_available = true;
+                readings.charging_sources_available = ChargingSourcesAvailable {
+                    home_base,
+                    internal_charger,
+                };
+            }
+            SensorData::OIMode(mode) => {
+                readings.present.oi_mode = true;
+                readings.oi_mode = OIMode { mode: mode.into() };
+            }
+            SensorData::SongNumber(data) => {
+                readings.present.song_number = true;
+                readings.song_number = data;
+            }
+            SensorData::SongPlaying(data) => {
+                readings.present.song_playing = true;
+                readings.song_playing = data;
+            }
+            SensorData::NumberOfStreamPackets(data) => {
+                readings.present.number_of_stream_packets = true;
+                readings.number_of_stream_packets = data;
+            }
+            SensorData::RequestedVelocity(data) => {
+                readings.present.requested_velocity = true;
+                readings.requested_velocity = data;
+            }
+            SensorData::RequestedRadius(data) => {
+                readings.present.requested_radius = true;
+                readings.requested_radius = data;
+            }
+            SensorData::RequestedRightVelocity(data) => {
+                readings.present.requested_right_velocity = true;
+                readings.requested_right_velocity = data;
+            }
+            SensorData::RequestedLeftVelocity(data) => {
+                readings.present.requested_left_velocity = true;
+                readings.requested_left_velocity = data;
+            }
+            SensorData::LeftEncoderCounts(data) => {
+                readings.present.left_encoder_counts = true;
+                readings.left_encoder_counts = data;
+            }
+            SensorData::RightEncoderCounts(data) => {
+                readings.present.right_encoder_counts = true;
+                readings.right_encoder_counts = data;
+            }
+            SensorData::LightBumper {
+                right,
+                front_right,
+                center_right,
+                center_left,
+                front_left,
+                left,
+            } => {
+                readings.present.light_bumper = true;
+                readings.light_bumper = LightBumper {
+                    right,
+                    front_right,
+                    center_right,
+                    center_left,
+                    front_left,
+                    left,
+                };
+            }
+            SensorData::LightBumpLeftSignal(data) => {
+                readings.present.light_bump_left_signal = true;
+                readings.light_bump_left_signal = data;
+            }
+            SensorData::LightBumpFrontLeftSignal(data) => {
+                readings.present.light_bump_front_left_signal = true;
+                readings.light_bump_front_left_signal = data;
+            }
+            SensorData::LightBumpCenterLeftSignal(data) => {
+                readings.present.light_bump_center_left_signal = true;
+                readings.light_bump_center_left_signal = data;
+            }
+            SensorData::LightBumpCenterRightSignal(data) => {
+                readings.present.light_bump_center_right_signal = true;
+                readings.light_bump_center_right_signal = data;
+            }
+            SensorData::LightBumpFrontRightSignal(data) => {
+                readings.present.light_bump_front_right_signal = true;
+                readings.light_bump_front_right_signal = data;
+            }
+            SensorData::LightBumpRightSignal(data) => {
+                readings.present.light_bump_right_signal = true;
+                readings.light_bump_right_signal = data;
+            }
+            SensorData::LeftMotorCurrent(data) => {
+                readings.present.left_motor_current = true;
+                readings.left_motor_current = data;
+            }
+            SensorData::RightMotorCurrent(data) => {
+                readings.present.right_motor_current = true;
+                readings.right_motor_current = data;
+            }
+            SensorData::MainBrushMotorCurrent(data) => {
+                readings.present.main_brush_motor_current = true;
+                readings.main_brush_motor_current = data;
+            }
+            SensorData::SideBrushMotorCurrent(data) => {
+                readings.present.side_brush_motor_current = true;
+                readings.side_brush_motor_current = data;
+            }
+            SensorData::IsMovingForward(data) => {
+                readings.present.is_moving_forward = true;
+                readings.is_moving_forward = data;
+            }
+        }
+    }
+
+    readings
+}
+
+/// Publishes sensor data that was read together as one `RobotState` message.
+pub struct SensorPublisher {
+    robot_state: Publisher<RobotState>,
+
+    /// Older nodes expect every sensor on its own topic. That's a lot of traffic, so it's optional.
+    sensor_set: Option<SensorSet>,
+}
+
+impl SensorPublisher {
+    pub fn new(node: &mut Node, per_sensor_topics: bool) -> Result<Self> {
+        Ok(Self {
+            robot_state: node
+                .create_publisher::<RobotState>("robot_state", QosProfile::default())?,
+            sensor_set: per_sensor_topics
+                .then(|| SensorSet::new(node))
+                .transpose()?,
+        })
+    }
+
+    pub fn publish(&mut self, data: Vec<SensorData>, timestamp: &Timestamp) -> Result<()> {
+        self.robot_state.publish(&RobotState {
+            header: Header {
+                stamp: timestamp.ros.clone(),
+                frame_id: String::new(),
+            },
+            readings: readings_from_sensor_data(data.iter().cloned()),
+        })?;
+
+        if let Some(sensor_set) = &mut self.sensor_set {
+            for data in data {
+                sensor_set.publish(data, timestamp.monotonic)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Publishes every sensor on its own topic. Each sensor has its own publish policy, read from the
+/// `sensors.<name>.policy` parameter, or `sensors.default.policy` for the ones that don't.
 pub struct SensorSet {
-    bumpers_and_wheel_drops: Publisher<BumpersAndWheelDrops>,
-    wall: Publisher<Bool>,
-    cliff_left: Publisher<Bool>,
-    cliff_front_left: Publisher<Bool>,
-    cliff_front_right: Publisher<Bool>,
-    cliff_right: Publisher<Bool>,
-    virtual_wall: Publisher<Bool>,
-    wheel_overcurrents: Publisher<WheelOvercurrents>,
-    dirt_detect: Publisher<UInt8>,
-    infrared_character_omni: Publisher<UInt8>,
-    infrared_character_left: Publisher<UInt8>,
-    infrared_character_right: Publisher<UInt8>,
-    buttons: Publisher<Buttons>,
-    distance: Publisher<Int16>,
-    angle: Publisher<Int16>,
-    charging_state: Publisher<ChargingState>,
-    voltage: Publisher<UInt16>,
-    current: Publisher<Int16>,
-    battery_temperature: Publisher<Int8>,
-    battery_charge: Publisher<UInt16>,
-    battery_capacity: Publisher<UInt16>,
-    wall_signal: Publisher<UInt16>,
-    cliff_left_signal: Publisher<UInt16>,
-    cliff_right_signal: Publisher<UInt16>,
-    cliff_front_right_signal: Publisher<UInt16>,
-    cliff_front_left_signal: Publisher<UInt16>,
-    charging_sources_available: Publisher<ChargingSourcesAvailable>,
-    oi_mode: Publisher<OIMode>,
-    song_number: Publisher<UInt8>,
-    song_playing: Publisher<Bool>,
-    number_of_stream_packets: Publisher<UInt8>,
-    requested_velocity: Publisher<Int16>,
-    requested_radius: Publisher<Int16>,
-    requested_right_velocity: Publisher<Int16>,
-    requested_left_velocity: Publisher<Int16>,
-    left_encoder_counts: Publisher<UInt16>,
-    right_encoder_counts: Publisher<UInt16>,
-    light_bumper: Publisher<LightBumper>,
-    light_bump_left_signal: Publisher<UInt16>,
-    light_bump_front_left_signal: Publisher<UInt16>,
-    light_bump_center_left_signal: Publisher<UInt16>,
-    light_bump_center_right_signal: Publisher<UInt16>,
-    light_bump_front_right_signal: Publisher<UInt16>,
-    light_bump_right_signal: Publisher<UInt16>,
-    left_motor_current: Publisher<Int16>,
-    right_motor_current: Publisher<Int16>,
-    main_brush_motor_current: Publisher<Int16>,
-    side_brush_motor_current: Publisher<Int16>,
-    is_moving_forward: Publisher<Bool>,
+    bumpers_and_wheel_drops: SensorTopic<BumpersAndWheelDrops>,
+    wall: SensorTopic<Bool>,
+    cliff_left: SensorTopic<Bool>,
+    cliff_front_left: SensorTopic<Bool>,
+    cliff_front_right: SensorTopic<Bool>,
+    cliff_right: SensorTopic<Bool>,
+    virtual_wall: SensorTopic<Bool>,
+    wheel_overcurrents: SensorTopic<WheelOvercurrents>,
+    dirt_detect: SensorTopic<UInt8>,
+    infrared_character_omni: SensorTopic<UInt8>,
+    infrared_character_left: SensorTopic<UInt8>,
+    infrared_character_right: SensorTopic<UInt8>,
+    buttons: SensorTopic<Buttons>,
+    distance: SensorTopic<Int16>,
+    angle: SensorTopic<Int16>,
+    charging_state: SensorTopic<ChargingState>,
+    voltage: SensorTopic<UInt16>,
+    current: SensorTopic<Int16>,
+    battery_temperature: SensorTopic<Int8>,
+    battery_charge: SensorTopic<UInt16>,
+    battery_capacity: SensorTopic<UInt16>,
+    wall_signal: SensorTopic<UInt16>,
+    cliff_left_signal: SensorTopic<UInt16>,
+    cliff_right_signal: SensorTopic<UInt16>,
+    cliff_front_right_signal: SensorTopic<UInt16>,
+    cliff_front_left_signal: SensorTopic<UInt16>,
+    charging_sources_available: SensorTopic<ChargingSourcesAvailable>,
+    oi_mode: SensorTopic<OIMode>,
+    song_number: SensorTopic<UInt8>,
+    song_playing: SensorTopic<Bool>,
+    number_of_stream_packets: SensorTopic<UInt8>,
+    requested_velocity: SensorTopic<Int16>,
+    requested_radius: SensorTopic<Int16>,
+    requested_right_velocity: SensorTopic<Int16>,
+    requested_left_velocity: SensorTopic<Int16>,
+    left_encoder_counts: SensorTopic<UInt16>,
+    right_encoder_counts: SensorTopic<UInt16>,
+    light_bumper: SensorTopic<LightBumper>,
+    light_bump_left_signal: SensorTopic<UInt16>,
+    light_bump_front_left_signal: SensorTopic<UInt16>,
+    light_bump_center_left_signal: SensorTopic<UInt16>,
+    light_bump_center_right_signal: SensorTopic<UInt16>,
+    light_bump_front_right_signal: SensorTopic<UInt16>,
+    light_bump_right_signal: SensorTopic<UInt16>,
+    left_motor_current: SensorTopic<Int16>,
+    right_motor_current: SensorTopic<Int16>,
+    main_brush_motor_current: SensorTopic<Int16>,
+    side_brush_motor_current: SensorTopic<Int16>,
+    is_moving_forward: SensorTopic<Bool>,
 }
 
 impl SensorSet {
     pub fn new(node: &mut Node) -> Result<Self> {
+        let default_policy =
+            policy_parameter(node, "sensors.default")?.unwrap_or(PublishPolicy::Always);
+
         Ok(Self {
-            bumpers_and_wheel_drops: node.create_publisher::<BumpersAndWheelDrops>(
-                "sensors/bumpers_and_wheel_drops",
-                QosProfile::default(),
-            )?,
-            wall: node.create_publisher::<Bool>("sensors/wall", QosProfile::default())?,
-            cliff_left: node
-                .create_publisher::<Bool>("sensors/cliff_left", QosProfile::default())?,
-            cliff_front_left: node
-                .create_publisher::<Bool>("sensors/cliff_front_left", QosProfile::default())?,
-            cliff_front_right: node
-                .create_publisher::<Bool>("sensors/cliff_front_right", QosProfile::default())?,
-            cliff_right: node
-                .create_publisher::<Bool>("sensors/cliff_right", QosProfile::default())?,
-            virtual_wall: node
-                .create_publisher::<Bool>("sensors/virtual_wall", QosProfile::default())?,
-            wheel_overcurrents: node.create_publisher::<WheelOvercurrents>(
-                "sensors/wheel_overcurrents",
-                QosProfile::default(),
-            )?,
-            dirt_detect: node
-                .create_publisher::<UInt8>("sensors/dirt_detect", QosProfile::default())?,
-            infrared_character_omni: node.create_publisher::<UInt8>(
-                "sensors/infrared_character_omni",
-                QosProfile::default(),
+            bumpers_and_wheel_drops: SensorTopic::new(
+                node,
+                "bumpers_and_wheel_drops",
+                default_policy,
             )?,
-            infrared_character_left: node.create_publisher::<UInt8>(
-                "sensors/infrared_character_left",
-                QosProfile::default(),
+            wall: SensorTopic::new(node, "wall", default_policy)?,
+            cliff_left: SensorTopic::new(node, "cliff_left", default_policy)?,
+            cliff_front_left: SensorTopic::new(node, "cliff_front_left", default_policy)?,
+            cliff_front_right: SensorTopic::new(node, "cliff_front_right", default_policy)?,
+            cliff_right: SensorTopic::new(node, "cliff_right", default_policy)?,
+            virtual_wall: SensorTopic::new(node, "virtual_wall", default_policy)?,
+            wheel_overcurrents: SensorTopic::new(node, "wheel_overcurrents", default_policy)?,
+            dirt_detect: SensorTopic::new(node, "dirt_detect", default_policy)?,
+            infrared_character_omni: SensorTopic::new(
+                node,
+                "infrared_character_omni",
+                default_policy,
             )?,
-            infrared_character_right: node.create_publisher::<UInt8>(
-                "sensors/infrared_character_right",
-                QosProfile::default(),
+            infrared_character_left: SensorTopic::new(
+                node,
+                "infrared_character_left",
+                default_policy,
             )?,
-            buttons: node.create_publisher::<Buttons>("sensors/buttons", QosProfile::default())?,
-            distance: node.create_publisher::<Int16>("sensors/distance", QosProfile::default())?,
-            angle: node.create_publisher::<Int16>("sensors/angle", QosProfile::default())?,
-            charging_state: node.create_publisher::<ChargingState>(
-                "sensors/charging_state",
-                QosProfile::default(),
+            infrared_character_right: SensorTopic::new(
+                node,
+                "infrared_character_right",
+                default_policy,
             )?,
-            voltage: node.create_publisher::<UInt16>("sensors/voltage", QosProfile::default())?,
-            current: node.create_publisher::<Int16>("sensors/current", QosProfile::default())?,
-            battery_temperature: node
-                .create_publisher::<Int8>("sensors/battery_temperature", QosProfile::default())?,
-            battery_charge: node
-                .create_publisher::<UInt16>("sensors/battery_charge", QosProfile::default())?,
-            battery_capacity: node
-                .create_publisher::<UInt16>("sensors/battery_capacity", QosProfile::default())?,
-            wall_signal: node
-                .create_publisher::<UInt16>("sensors/wall_signal", QosProfile::default())?,
-            cliff_left_signal: node
-                .create_publisher::<UInt16>("sensors/cliff_left_signal", QosProfile::default())?,
-            cliff_right_signal: node
-                .create_publisher::<UInt16>("sensors/cliff_right_signal", QosProfile::default())?,
-            cliff_front_right_signal: node.create_publisher::<UInt16>(
-                "sensors/cliff_front_right_signal",
-                QosProfile::default(),
+            buttons: SensorTopic::new(node, "buttons", default_policy)?,
+            distance: SensorTopic::new(node, "distance", default_policy)?,
+            angle: SensorTopic::new(node, "angle", default_policy)?,
+            charging_state: SensorTopic::new(node, "charging_state", default_policy)?,
+            voltage: SensorTopic::new(node, "voltage", default_policy)?,
+            current: SensorTopic::new(node, "current", default_policy)?,
+            battery_temperature: SensorTopic::new(node, "battery_temperature", default_policy)?,
+            battery_charge: SensorTopic::new(node, "battery_charge", default_policy)?,
+            battery_capacity: SensorTopic::new(node, "battery_capacity", default_policy)?,
+            wall_signal: SensorTopic::new(node, "wall_signal", default_policy)?,
+            cliff_left_signal: SensorTopic::new(node, "cliff_left_signal", default_policy)?,
+            cliff_right_signal: SensorTopic::new(node, "cliff_right_signal", default_policy)?,
+            cliff_front_right_signal: SensorTopic::new(
+                node,
+                "cliff_front_right_signal",
+                default_policy,
             )?,
-            cliff_front_left_signal: node.create_publisher::<UInt16>(
-                "sensors/cliff_front_left_signal",
-                QosProfile::default(),
+            cliff_front_left_signal: SensorTopic::new(
+                node,
+                "cliff_front_left_signal",
+                default_policy,
             )?,
-            charging_sources_available: node.create_publisher::<ChargingSourcesAvailable>(
-                "sensors/charging_sources_available",
-                QosProfile::default(),
+            charging_sources_available: SensorTopic::new(
+                node,
+                "charging_sources_available",
+                default_policy,
             )?,
-            oi_mode: node.create_publisher::<OIMode>("sensors/oi_mode", QosProfile::default())?,
-            song_number: node
-                .create_publisher::<UInt8>("sensors/song_number", QosProfile::default())?,
-            song_playing: node
-                .create_publisher::<Bool>("sensors/song_playing", QosProfile::default())?,
-            number_of_stream_packets: node.create_publisher::<UInt8>(
-                "sensors/number_of_stream_packets",
-                QosProfile::default(),
+            oi_mode: SensorTopic::new(node, "oi_mode", default_policy)?,
+            song_number: SensorTopic::new(node, "song_number", default_policy)?,
+            song_playing: SensorTopic::new(node, "song_playing", default_policy)?,
+            number_of_stream_packets: SensorTopic::new(
+                node,
+                "number_of_stream_packets",
+                default_policy,
             )?,
-            requested_velocity: node
-                .create_publisher::<Int16>("sensors/requested_velocity", QosProfile::default())?,
-            requested_radius: node
-                .create_publisher::<Int16>("sensors/requested_radius", QosProfile::default())?,
-            requested_right_velocity: node.create_publisher::<Int16>(
-                "sensors/requested_right_velocity",
-                QosProfile::default(),
+            requested_velocity: SensorTopic::new(node, "requested_velocity", default_policy)?,
+            requested_radius: SensorTopic::new(node, "requested_radius", default_policy)?,
+            requested_right_velocity: SensorTopic::new(
+                node,
+                "requested_right_velocity",
+                default_policy,
             )?,
-            requested_left_velocity: node.create_publisher::<Int16>(
-                "sensors/requested_left_velocity",
-                QosProfile::default(),
+            requested_left_velocity: SensorTopic::new(
+                node,
+                "requested_left_velocity",
+                default_policy,
             )?,
-            left_encoder_counts: node
-                .create_publisher::<UInt16>("sensors/left_encoder_counts", QosProfile::default())?,
-            right_encoder_counts: node.create_publisher::<UInt16>(
-                "sensors/right_encoder_counts",
-                QosProfile::default(),
+            left_encoder_counts: SensorTopic::new(node, "left_encoder_counts", default_policy)?,
+            right_encoder_counts: SensorTopic::new(node, "right_encoder_counts", default_policy)?,
+            light_bumper: SensorTopic::new(node, "light_bumper", default_policy)?,
+            light_bump_left_signal: SensorTopic::new(
+                node,
+                "light_bump_left_signal",
+                default_policy,
             )?,
-            light_bumper: node
-                .create_publisher::<LightBumper>("sensors/light_bumper", QosProfile::default())?,
-            light_bump_left_signal: node.create_publisher::<UInt16>(
-                "sensors/light_bump_left_signal",
-                QosProfile::default(),
+            light_bump_front_left_signal: SensorTopic::new(
+                node,
+                "light_bump_front_left_signal",
+                default_policy,
             )?,
-            light_bump_front_left_signal: node.create_publisher::<UInt16>(
-                "sensors/light_bump_front_left_signal",
-                QosProfile::default(),
+            light_bump_center_left_signal: SensorTopic::new(
+                node,
+                "light_bump_center_left_signal",
+                default_policy,
             )?,
-            light_bump_center_left_signal: node.create_publisher::<UInt16>(
-                "sensors/light_bump_center_left_signal",
-                QosProfile::default(),
+            light_bump_center_right_signal: SensorTopic::new(
+                node,
+                "light_bump_center_right_signal",
+                default_policy,
             )?,
-            light_bump_center_right_signal: node.create_publisher::<UInt16>(
-                "sensors/light_bump_center_right_signal",
-                QosProfile::default(),
+            light_bump_front_right_signal: SensorTopic::new(
+                node,
+                "light_bump_front_right_signal",
+                default_policy,
             )?,
-            light_bump_front_right_signal: node.create_publisher::<UInt16>(
-                "sensors/light_bump_front_right_signal",
-                QosProfile::default(),
+            light_bump_right_signal: SensorTopic::new(
+                node,
+                "light_bump_right_signal",
+                default_policy,
             )?,
-            light_bump_right_signal: node.create_publisher::<UInt16>(
-                "sensors/light_bump_right_signal",
-                QosProfile::default(),
+            left_motor_current: SensorTopic::new(node, "left_motor_current", default_policy)?,
+            right_motor_current: SensorTopic::new(node, "right_motor_current", default_policy)?,
+            main_brush_motor_current: SensorTopic::new(
+                node,
+                "main_brush_motor_current",
+                default_policy,
             )?,
-            left_motor_current: node
-                .create_publisher::<Int16>("sensors/left_motor_current", QosProfile::default())?,
-            right_motor_current: node
-                .create_publisher::<Int16>("sensors/right_motor_current", QosProfile::default())?,
-            main_brush_motor_current: node.create_publisher::<Int16>(
-                "sensors/main_brush_motor_current",
-                QosProfile::default(),
+            side_brush_motor_current: SensorTopic::new(
+                node,
+                "side_brush_motor_current",
+                default_policy,
             )?,
-            side_brush_motor_current: node.create_publisher::<Int16>(
-                "sensors/side_brush_motor_current",
-                QosProfile::default(),
-            )?,
-            is_moving_forward: node
-                .create_publisher::<Bool>("sensors/is_moving_forward", QosProfile::default())?,
+            is_moving_forward: SensorTopic::new(node, "is_moving_forward", default_policy)?,
         })
     }
 
-    pub fn publish(&self, data: SensorData) -> Result<()> {
+    pub fn publish(&mut self, data: SensorData, now: Instant) -> Result<()> {
         match data {
             SensorData::BumpersAndWheelDrops {
                 wheel_drop_left,
                 wheel_drop_right,
                 bumper_left,
                 bumper_right,
-            } => self.bumpers_and_wheel_drops.publish(&BumpersAndWheelDrops {
-                wheel_drop_left,
-                wheel_drop_right,
-                bumper_left,
-                bumper_right,
-            }),
-            SensorData::Wall(data) => self.wall.publish(&Bool { data }),
-            SensorData::CliffLeft(data) => self.cliff_left.publish(&Bool { data }),
-            SensorData::CliffFrontLeft(data) => self.cliff_front_left.publish(&Bool { data }),
-            SensorData::CliffFrontRight(data) => self.cliff_front_right.publish(&Bool { data }),
-            SensorData::CliffRight(data) => self.cliff_right.publish(&Bool { data }),
-            SensorData::VirtualWall(data) => self.virtual_wall.publish(&Bool { data }),
+            } => self.bumpers_and_wheel_drops.publish(
+                now,
+                &BumpersAndWheelDrops {
+                    wheel_drop_left,
+                    wheel_drop_right,
+                    bumper_left,
+                    bumper_right,
+                },
+            ),
+            SensorData::Wall(data) => self.wall.publish(now, &Bool { data }),
+            SensorData::CliffLeft(data) => self.cliff_left.publish(now, &Bool { data }),
+            SensorData::CliffFrontLeft(data) => self.cliff_front_left.publish(now, &Bool { data }),
+            SensorData::CliffFrontRight(data) => {
+                self.cliff_front_right.publish(now, &Bool { data })
+            }
+            SensorData::CliffRight(data) => self.cliff_right.publish(now, &Bool { data }),
+            SensorData::VirtualWall(data) => self.virtual_wall.publish(now, &Bool { data }),
             SensorData::WheelOvercurrents {
                 left_wheel,
                 right_wheel,
                 main_brush,
                 side_brush,
-            } => self.wheel_overcurrents.publish(&WheelOvercurrents {
-                left_wheel,
-                right_wheel,
-                main_brush,
-                side_brush,
-            }),
-            SensorData::DirtDetect(data) => self.dirt_detect.publish(&UInt8 { data }),
-            SensorData::InfraredCharacterOmni(data) => {
-                self.infrared_character_omni.publish(&UInt8 { data })
-            }
-            SensorData::InfraredCharacterLeft(data) => {
-                self.infrared_character_left.publish(&UInt8 { data })
-            }
-            SensorData::InfraredCharacterRight(data) => {
-                self.infrared_character_right.publish(&UInt8 { data })
-            }
+            } => self.wheel_overcurrents.publish(
+                now,
+                &WheelOvercurrents {
+                    left_wheel,
+                    right_wheel,
+                    main_brush,
+                    side_brush,
+                },
+            ),
+            SensorData::DirtDetect(data) => self.dirt_detect.publish(now, &UInt8 { data }),
+            SensorData::InfraredCharacterOmni(data) => self
+                .infrared_character_omni
+                .publish(now, &UInt8 { data: data.into() }),
+            SensorData::InfraredCharacterLeft(data) => self
+                .infrared_character_left
+                .publish(now, &UInt8 { data: data.into() }),
+            SensorData::InfraredCharacterRight(data) => self
+                .infrared_character_right
+                .publish(now, &UInt8 { data: data.into() }),
             SensorData::Buttons {
                 clock,
                 schedule,
@@ -403,65 +753,82 @@ impl SensorSet {
                 dock,
                 spot,
                 clean,
-            } => self.buttons.publish(&Buttons {
-                clock,
-                schedule,
-                day,
-                hour,
-                minute,
-                dock,
-                spot,
-                clean,
-            }),
-            SensorData::Distance(data) => self.distance.publish(&Int16 { data }),
-            SensorData::Angle(data) => self.angle.publish(&Int16 { data }),
-            SensorData::ChargingState(state) => self.charging_state.publish(&ChargingState {
-                state: state.into(),
-            }),
-            SensorData::Voltage(data) => self.voltage.publish(&UInt16 { data }),
-            SensorData::Current(data) => self.current.publish(&Int16 { data }),
+            } => self.buttons.publish(
+                now,
+                &Buttons {
+                    clock,
+                    schedule,
+                    day,
+                    hour,
+                    minute,
+                    dock,
+                    spot,
+                    clean,
+                },
+            ),
+            SensorData::Distance(data) => self.distance.publish(now, &Int16 { data }),
+            SensorData::Angle(data) => self.angle.publish(now, &Int16 { data }),
+            SensorData::ChargingState(state) => self.charging_state.publish(
+                now,
+                &ChargingState {
+                    state: state.into(),
+                },
+            ),
+            SensorData::Voltage(data) => self.voltage.publish(now, &UInt16 { data }),
+            SensorData::Current(data) => self.current.publish(now, &Int16 { data }),
             SensorData::BatteryTemperature(data) => {
-                self.battery_temperature.publish(&Int8 { data })
+                self.battery_temperature.publish(now, &Int8 { data })
+            }
+            SensorData::BatteryCharge(data) => self.battery_charge.publish(now, &UInt16 { data }),
+            SensorData::BatteryCapacity(data) => {
+                self.battery_capacity.publish(now, &UInt16 { data })
+            }
+            SensorData::WallSignal(data) => self.wall_signal.publish(now, &UInt16 { data }),
+            SensorData::CliffLeftSignal(data) => {
+                self.cliff_left_signal.publish(now, &UInt16 { data })
             }
-            SensorData::BatteryCharge(data) => self.battery_charge.publish(&UInt16 { data }),
-            SensorData::BatteryCapacity(data) => self.battery_capacity.publish(&UInt16 { data }),
-            SensorData::WallSignal(data) => self.wall_signal.publish(&UInt16 { data }),
-            SensorData::CliffLeftSignal(data) => self.cliff_left_signal.publish(&UInt16 { data }),
             SensorData::CliffFrontLeftSignal(data) => {
-                self.cliff_front_left_signal.publish(&UInt16 { data })
+                self.cliff_front_left_signal.publish(now, &UInt16 { data })
             }
             SensorData::CliffFrontRightSignal(data) => {
-                self.cliff_front_right_signal.publish(&UInt16 { data })
+                self.cliff_front_right_signal.publish(now, &UInt16 { data })
+            }
+            SensorData::CliffRightSignal(data) => {
+                self.cliff_right_signal.publish(now, &UInt16 { data })
             }
-            SensorData::CliffRightSignal(data) => self.cliff_right_signal.publish(&UInt16 { data }),
             SensorData::ChargingSourcesAvailable {
                 home_base,
                 internal_charger,
-            } => self
-                .charging_sources_available
-                .publish(&ChargingSourcesAvailable {
+            } => self.charging_sources_available.publish(
+                now,
+                &ChargingSourcesAvailable {
                     home_base,
                     internal_charger,
-                }),
-            SensorData::OIMode(mode) => self.oi_mode.publish(&OIMode { mode: mode.into() }),
-            SensorData::SongNumber(data) => self.song_number.publish(&UInt8 { data }),
-            SensorData::SongPlaying(data) => self.song_playing.publish(&Bool { data }),
+                },
+            ),
+            SensorData::OIMode(mode) => self.oi_mode.publish(now, &OIMode { mode: mode.into() }),
+            SensorData::SongNumber(data) => self.song_number.publish(now, &UInt8 { data }),
+            SensorData::SongPlaying(data) => self.song_playing.publish(now, &Bool { data }),
             SensorData::NumberOfStreamPackets(data) => {
-                self.number_of_stream_packets.publish(&UInt8 { data })
+                self.number_of_stream_packets.publish(now, &UInt8 { data })
+            }
+            SensorData::RequestedVelocity(data) => {
+                self.requested_velocity.publish(now, &Int16 { data })
+            }
+            SensorData::RequestedRadius(data) => {
+                self.requested_radius.publish(now, &Int16 { data })
             }
-            SensorData::RequestedVelocity(data) => self.requested_velocity.publish(&Int16 { data }),
-            SensorData::RequestedRadius(data) => self.requested_radius.publish(&Int16 { data }),
             SensorData::RequestedRightVelocity(data) => {
-                self.requested_right_velocity.publish(&Int16 { data })
+                self.requested_right_velocity.publish(now, &Int16 { data })
             }
             SensorData::RequestedLeftVelocity(data) => {
-                self.requested_left_velocity.publish(&Int16 { data })
+                self.requested_left_velocity.publish(now, &Int16 { data })
             }
             SensorData::LeftEncoderCounts(data) => {
-                self.left_encoder_counts.publish(&UInt16 { data })
+                self.left_encoder_counts.publish(now, &UInt16 { data })
             }
             SensorData::RightEncoderCounts(data) => {
-                self.right_encoder_counts.publish(&UInt16 { data })
+                self.right_encoder_counts.publish(now, &UInt16 { data })
             }
             SensorData::LightBumper {
                 right,
@@ -470,43 +837,118 @@ impl SensorSet {
                 center_left,
                 front_left,
                 left,
-            } => self.light_bumper.publish(&LightBumper {
-                right,
-                front_right,
-                center_right,
-                center_left,
-                front_left,
-                left,
-            }),
+            } => self.light_bumper.publish(
+                now,
+                &LightBumper {
+                    right,
+                    front_right,
+                    center_right,
+                    center_left,
+                    front_left,
+                    left,
+                },
+            ),
             SensorData::LightBumpLeftSignal(data) => {
-                self.light_bump_left_signal.publish(&UInt16 { data })
-            }
-            SensorData::LightBumpFrontLeftSignal(data) => {
-                self.light_bump_front_left_signal.publish(&UInt16 { data })
-            }
-            SensorData::LightBumpCenterLeftSignal(data) => {
-                self.light_bump_center_left_signal.publish(&UInt16 { data })
+                self.light_bump_left_signal.publish(now, &UInt16 { data })
             }
+            SensorData::LightBumpFrontLeftSignal(data) => self
+                .light_bump_front_left_signal
+                .publish(now, &UInt16 { data }),
+            SensorData::LightBumpCenterLeftSignal(data) => self
+                .light_bump_center_left_signal
+                .publish(now, &UInt16 { data }),
             SensorData::LightBumpCenterRightSignal(data) => self
                 .light_bump_center_right_signal
-                .publish(&UInt16 { data }),
-            SensorData::LightBumpFrontRightSignal(data) => {
-                self.light_bump_front_right_signal.publish(&UInt16 { data })
-            }
+                .publish(now, &UInt16 { data }),
+            SensorData::LightBumpFrontRightSignal(data) => self
+                .light_bump_front_right_signal
+                .publish(now, &UInt16 { data }),
             SensorData::LightBumpRightSignal(data) => {
-                self.light_bump_right_signal.publish(&UInt16 { data })
+                self.light_bump_right_signal.publish(now, &UInt16 { data })
+            }
+            SensorData::LeftMotorCurrent(data) => {
+                self.left_motor_current.publish(now, &Int16 { data })
             }
-            SensorData::LeftMotorCurrent(data) => self.left_motor_current.publish(&Int16 { data }),
             SensorData::RightMotorCurrent(data) => {
-                self.right_motor_current.publish(&Int16 { data })
+                self.right_motor_current.publish(now, &Int16 { data })
             }
             SensorData::MainBrushMotorCurrent(data) => {
-                self.main_brush_motor_current.publish(&Int16 { data })
+                self.main_brush_motor_current.publish(now, &Int16 { data })
             }
             SensorData::SideBrushMotorCurrent(data) => {
-                self.side_brush_motor_current.publish(&Int16 { data })
+                self.side_brush_motor_current.publish(now, &Int16 { data })
+            }
+            SensorData::IsMovingForward(data) => {
+                self.is_moving_forward.publish(now, &Bool { data })
             }
-            SensorData::IsMovingForward(data) => self.is_moving_forward.publish(&Bool { data }),
         }
     }
 }
+
+/// One sensor's topic, and what has been published on it.
+struct SensorTopic<T: WrappedTypesupport> {
+    publisher: Publisher<T>,
+    filter: PublishFilter<T>,
+}
+
+impl<T: WrappedTypesupport + PartialEq + Clone + 'static> SensorTopic<T> {
+    fn new(node: &mut Node, name: &str, default_policy: PublishPolicy) -> Result<Self> {
+        let policy = policy_parameter(node, &format!("sensors.{name}"))?.unwrap_or(default_policy);
+
+        Ok(Self {
+            publisher: node
+                .create_publisher::<T>(&format!("sensors/{name}"), QosProfile::default())?,
+            filter: PublishFilter::new(policy),
+        })
+    }
+
+    fn publish(&mut self, now: Instant, message: &T) -> Result<()> {
+        if self.filter.filter(message, now) {
+            self.publisher.publish(message)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads a publish policy from the `<prefix>.policy` parameter, which is one of `always`,
+/// `on_change`, `on_change_with_heartbeat`, or `decimate`. The heartbeat comes from
+/// `<prefix>.heartbeat_ms`, and the rate to decimate to from `<prefix>.rate_hz`.
+fn policy_parameter(node: &Node, prefix: &str) -> Result<Option<PublishPolicy>> {
+    let policy: Option<String> = node
+        .get_parameter(&format!("{prefix}.policy"))
+        .with_context(|| format!("Failed to get {prefix} publish policy."))?;
+
+    let policy = match policy.as_deref() {
+        None => return Ok(None),
+        Some("always") => PublishPolicy::Always,
+        Some("on_change") => PublishPolicy::OnChange,
+        Some("on_change_with_heartbeat") => {
+            let heartbeat: Option<i64> = node
+                .get_parameter(&format!("{prefix}.heartbeat_ms"))
+                .with_context(|| format!("Failed to get {prefix} heartbeat."))?;
+            let heartbeat = heartbeat.unwrap_or(1000);
+            if heartbeat <= 0 {
+                bail!("Invalid heartbeat {heartbeat}ms for {prefix}. It must be greater than zero.");
+            }
+
+            PublishPolicy::OnChangeWithHeartbeat(Duration::from_millis(heartbeat as u64))
+        }
+        Some("decimate") => {
+            let rate: Option<f64> = node
+                .get_parameter(&format!("{prefix}.rate_hz"))
+                .with_context(|| format!("Failed to get {prefix} rate."))?;
+            let rate = rate.with_context(|| format!("{prefix} is decimated, but has no rate_hz."))?;
+
+            match PublishPolicy::decimate(rate) {
+                Some(policy) => policy,
+                None => bail!("Invalid rate {rate}Hz for {prefix}. It must be greater than zero."),
+            }
+        }
+        Some(policy) => bail!(
+            "Unknown publish policy {policy} for {prefix}. Expected always, on_change, on_change_with_heartbeat, or decimate."
+        ),
+    };
+
+    Ok(Some(policy))
+}
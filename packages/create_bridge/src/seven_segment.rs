@@ -0,0 +1,133 @@
+//! Fitting text onto the Roomba's four digit seven segment display.
+
+/// How many characters the display can show at once.
+pub const DISPLAY_WIDTH: usize = 4;
+
+/// The blank space left between the end of scrolling text and its start coming back around.
+const SCROLL_GAP: usize = DISPLAY_WIDTH;
+
+/// Which segments of a digit to light up. The segments are lettered clockwise from the top, with
+/// G in the middle.
+pub const SEGMENT_A: u8 = 0x01;
+pub const SEGMENT_B: u8 = 0x02;
+pub const SEGMENT_C: u8 = 0x04;
+pub const SEGMENT_D: u8 = 0x08;
+pub const SEGMENT_E: u8 = 0x10;
+pub const SEGMENT_F: u8 = 0x20;
+pub const SEGMENT_G: u8 = 0x40;
+
+/// Every segment of a digit.
+pub const ALL_SEGMENTS: u8 = 0x7F;
+
+/// Turn a character into the ASCII code the display will accept for it.
+/// The display only knows printable ASCII, so anything else is swapped for the closest thing
+/// it can show, or a `?` if there's nothing close.
+pub fn display_character(character: char) -> u8 {
+    let character = match character {
+        ' '..='~' => character,
+        '\t' | '\n' | '\r' => ' ',
+        '‘' | '’' | '´' => '\'',
+        '“' | '”' | '«' | '»' => '"',
+        '‐' | '‑' | '‒' | '–' | '—' | '−' => '-',
+        '°' => 'o',
+        '×' => 'x',
+        'À'..='Å' | 'à'..='å' => 'A',
+        'Ç' | 'ç' => 'C',
+        'È'..='Ë' | 'è'..='ë' => 'E',
+        'Ì'..='Ï' | 'ì'..='ï' => 'I',
+        'Ñ' | 'ñ' => 'N',
+        'Ò'..='Ö' | 'Ø' | 'ò'..='ö' | 'ø' => 'O',
+        'Ù'..='Ü' | 'ù'..='ü' => 'U',
+        'Ý' | 'ý' | 'ÿ' => 'Y',
+        'ß' => 'S',
+        _ => '?',
+    };
+
+    // The display only has capital letters.
+    character.to_ascii_uppercase() as u8
+}
+
+/// Text prepared for the display, which scrolls across it if it's too long to fit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScrollingText {
+    characters: Vec<u8>,
+    position: usize,
+}
+
+impl ScrollingText {
+    pub fn new(text: &str) -> Self {
+        let mut characters: Vec<u8> = text.chars().map(display_character).collect();
+
+        if characters.len() > DISPLAY_WIDTH {
+            characters.extend([b' '; SCROLL_GAP]);
+        }
+
+        Self {
+            characters,
+            position: 0,
+        }
+    }
+
+    /// Short text fits on the display as it is, so there's only ever one frame.
+    pub fn scrolls(&self) -> bool {
+        self.characters.len() > DISPLAY_WIDTH
+    }
+
+    /// What should be on the display now. Each call moves the text along by one character,
+    /// wrapping around once it has all gone by.
+    pub fn next_frame(&mut self) -> String {
+        if !self.scrolls() {
+            return format!(
+                "{:<DISPLAY_WIDTH$}",
+                String::from_utf8_lossy(&self.characters)
+            );
+        }
+
+        let frame = self
+            .characters
+            .iter()
+            .cycle()
+            .skip(self.position)
+            .take(DISPLAY_WIDTH)
+            .map(|character| *character as char)
+            .collect();
+
+        self.position = (self.position + 1) % self.characters.len();
+
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsupported_characters() {
+        let text: Vec<u8> = "Café 25°C\t¿ok?".chars().map(display_character).collect();
+
+        assert_eq!(text, b"CAFE 25OC ?OK?");
+    }
+
+    #[test]
+    fn short_text() {
+        let mut text = ScrollingText::new("hi");
+
+        assert!(!text.scrolls());
+        assert_eq!(text.next_frame(), "HI  ");
+        assert_eq!(text.next_frame(), "HI  ");
+    }
+
+    #[test]
+    fn long_text_scrolls() {
+        let mut text = ScrollingText::new("Hello");
+
+        assert!(text.scrolls());
+
+        let frames: Vec<String> = (0..10).map(|_| text.next_frame()).collect();
+        assert_eq!(
+            frames,
+            ["HELL", "ELLO", "LLO ", "LO  ", "O   ", "    ", "   H", "  HE", " HEL", "HELL"]
+        );
+    }
+}
@@ -0,0 +1,38 @@
+use anyhow::Result;
+use r2r::{builtin_interfaces::msg::Time, Clock, ClockType};
+use tokio::time::Instant;
+
+/// When some sensor data was received, by both the monotonic clock and the ROS clock.
+#[derive(Debug, Clone)]
+pub struct Timestamp {
+    /// For measuring how much time passed between samples.
+    pub monotonic: Instant,
+
+    /// For stamping messages.
+    pub ros: Time,
+}
+
+/// Works out the ROS time of things that happened a little while ago.
+pub struct Timestamper {
+    clock: Clock,
+}
+
+impl Timestamper {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            clock: Clock::create(ClockType::RosTime)?,
+        })
+    }
+
+    /// The ROS clock can jump around, so we can't compare it to when something was received.
+    /// Instead we take how long ago it was received, and go back that far from the current ROS time.
+    pub fn stamp(&mut self, received: Instant) -> Result<Timestamp> {
+        let now = self.clock.get_now()?;
+        let then = now.saturating_sub(received.elapsed());
+
+        Ok(Timestamp {
+            monotonic: received,
+            ros: Clock::to_builtin_time(&then),
+        })
+    }
+}
@@ -0,0 +1,829 @@
+//! A simulated Roomba that speaks the Open Interface, so `Roomba` can be used without a robot.
+
+use std::{
+    collections::HashMap,
+    f64::consts::PI,
+    io,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, DuplexStream},
+    time::{interval, Instant, MissedTickBehavior},
+};
+
+use crate::roomba_interface::{GroupMember, LedState, OIMode, Sensor, SensorData};
+
+/// The duty cycle the motors run at when they are turned on without a PWM value.
+const FULL_SPEED: i8 = 127;
+
+/// How often a real Roomba sends a frame of a sensor stream.
+const STREAM_PERIOD: Duration = Duration::from_millis(15);
+
+/// The distance between the wheels, in millimeters. Used to turn drive commands into wheel speeds.
+const WHEEL_BASE: i32 = 235;
+
+/// How far a wheel travels for each encoder tick, in millimeters. A Create 2 has 72 mm wheels, and
+/// 508.8 ticks per revolution.
+const TICK_DISTANCE: f64 = PI * 72.0 / 508.8;
+
+/// A real Roomba prints some text when it resets. We do the same, since the sensor reader has to
+/// cope with it.
+const BOOT_MESSAGE: &[u8] = b"bl-start\r\nvirtual roomba\r\n";
+
+/// Something the Roomba can be asked to do on its own, taking it out of the user's control.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Behavior {
+    Clean,
+    Spot,
+    SeekDock,
+}
+
+struct State {
+    mode: OIMode,
+    behavior: Option<Behavior>,
+
+    /// In mm/s.
+    requested_velocity: i16,
+    /// In millimeters.
+    requested_radius: i16,
+    /// In mm/s.
+    left_velocity: i16,
+    /// In mm/s.
+    right_velocity: i16,
+
+    /// In encoder ticks. Kept as fractions so that slow wheels still add up to whole ticks.
+    left_encoder: f64,
+    right_encoder: f64,
+    /// In millimeters, since it was last read.
+    distance: f64,
+    /// Counter clockwise, in degrees, since it was last read.
+    angle: f64,
+    /// When the wheels were last moved along at their current speeds.
+    wheels_advanced: Instant,
+
+    /// Duty cycles of the main brush, side brush and vacuum.
+    motor_pwm: (i8, i8, i8),
+
+    leds: LedState,
+    display: [u8; 4],
+    digit_segments: [u8; 4],
+    /// Weekday LEDs, then the other scheduling LEDs.
+    scheduling_leds: [u8; 2],
+
+    /// Day, hour and minute.
+    clock: [u8; 3],
+    /// The raw arguments of the last schedule command.
+    schedule: [u8; 15],
+
+    /// Pitch and duration pairs. An empty song has not been defined.
+    songs: [Vec<(u8, u8)>; 4],
+    song_number: u8,
+    song_ends: Instant,
+
+    /// Sensor values set through `VirtualRoomba::set_sensor`, already encoded.
+    sensors: HashMap<Sensor, Vec<u8>>,
+
+    stream: Vec<Sensor>,
+    stream_paused: bool,
+}
+
+impl State {
+    fn new() -> Self {
+        Self {
+            mode: OIMode::Off,
+            behavior: None,
+            requested_velocity: 0,
+            requested_radius: 0,
+            left_velocity: 0,
+            right_velocity: 0,
+            left_encoder: 0.0,
+            right_encoder: 0.0,
+            distance: 0.0,
+            angle: 0.0,
+            wheels_advanced: Instant::now(),
+            motor_pwm: (0, 0, 0),
+            leds: LedState::default(),
+            display: [b' '; 4],
+            digit_segments: [0; 4],
+            scheduling_leds: [0; 2],
+            clock: [0; 3],
+            schedule: [0; 15],
+            songs: Default::default(),
+            song_number: 0,
+            song_ends: Instant::now(),
+            sensors: HashMap::new(),
+            stream: Vec::new(),
+            stream_paused: false,
+        }
+    }
+
+    /// Commands that move the robot or change what it shows need control of it.
+    fn in_control(&self) -> bool {
+        matches!(self.mode, OIMode::Safe | OIMode::Full)
+    }
+
+    /// Drops back to passive mode and stops moving, which is what the Roomba does when it's told
+    /// to do something on its own.
+    fn start_behavior(&mut self, behavior: Behavior) {
+        self.mode = OIMode::Passive;
+        self.behavior = Some(behavior);
+        self.set_drive(0, 0);
+    }
+
+    /// Turn the wheels for however long they've been going at their current speeds. This has to
+    /// happen before the speeds change, and before anything reads the encoders.
+    fn advance_wheels(&mut self) {
+        let now = Instant::now();
+        let elapsed = (now - self.wheels_advanced).as_secs_f64();
+        self.wheels_advanced = now;
+
+        let left = self.left_velocity as f64 * elapsed;
+        let right = self.right_velocity as f64 * elapsed;
+
+        self.left_encoder += left / TICK_DISTANCE;
+        self.right_encoder += right / TICK_DISTANCE;
+        self.distance += (left + right) / 2.0;
+        self.angle += ((right - left) / WHEEL_BASE as f64).to_degrees();
+    }
+
+    fn set_drive(&mut self, velocity: i16, radius: i16) {
+        self.requested_velocity = velocity;
+        self.requested_radius = radius;
+
+        let velocity = velocity as i32;
+        let (left, right) = match radius {
+            // Special cases for driving straight. We also drive straight with a radius of zero,
+            // which is what `DriveCommand::Stop` sends.
+            0 | 0x7FFF | i16::MIN => (velocity, velocity),
+            // Turn in place counter clockwise.
+            1 => (-velocity, velocity),
+            // Turn in place clockwise.
+            -1 => (velocity, -velocity),
+            radius => {
+                let radius = radius as i32;
+
+                (
+                    velocity * (radius - WHEEL_BASE / 2) / radius,
+                    velocity * (radius + WHEEL_BASE / 2) / radius,
+                )
+            }
+        };
+
+        self.left_velocity = left as i16;
+        self.right_velocity = right as i16;
+    }
+
+    /// Like on a real Roomba, reading the distance or angle starts it counting again from zero.
+    fn sensor_bytes(&mut self, sensor: Sensor) -> Vec<u8> {
+        if let Some(members) = sensor.group_members() {
+            return members
+                .flat_map(|member| match member {
+                    GroupMember::Sensor(sensor) => self.sensor_bytes(sensor),
+                    GroupMember::Unused(length) => vec![0; length],
+                })
+                .collect();
+        }
+
+        match sensor {
+            Sensor::OIMode => vec![self.mode.into()],
+            Sensor::SongNumber => vec![self.song_number],
+            Sensor::SongPlaying => vec![(Instant::now() < self.song_ends) as u8],
+            Sensor::NumberOfStreamPackets => vec![self.stream.len() as u8],
+            Sensor::RequestedVelocity => self.requested_velocity.to_be_bytes().to_vec(),
+            Sensor::RequestedRadius => self.requested_radius.to_be_bytes().to_vec(),
+            Sensor::RequestedRightVelocity => self.right_velocity.to_be_bytes().to_vec(),
+            Sensor::RequestedLeftVelocity => self.left_velocity.to_be_bytes().to_vec(),
+            // The counts roll over.
+            Sensor::LeftEncoderCounts => (self.left_encoder.floor() as i64 as u16)
+                .to_be_bytes()
+                .to_vec(),
+            Sensor::RightEncoderCounts => (self.right_encoder.floor() as i64 as u16)
+                .to_be_bytes()
+                .to_vec(),
+            // Whatever is left over from rounding carries on into the next reading.
+            Sensor::Distance => {
+                let distance = self.distance.trunc();
+                self.distance -= distance;
+
+                (distance as i16).to_be_bytes().to_vec()
+            }
+            Sensor::Angle => {
+                let angle = self.angle.trunc();
+                self.angle -= angle;
+
+                (angle as i16).to_be_bytes().to_vec()
+            }
+            sensor => self
+                .sensors
+                .get(&sensor)
+                .cloned()
+                .unwrap_or_else(|| vec![0; sensor.data_length()]),
+        }
+    }
+
+    /// The raw bytes of the sensors, one after the other, with no IDs between them.
+    /// This is how query responses look.
+    fn query_response(&mut self, sensors: &[Sensor]) -> Vec<u8> {
+        sensors
+            .iter()
+            .flat_map(|sensor| self.sensor_bytes(*sensor))
+            .collect()
+    }
+
+    fn stream_frame(&mut self) -> Option<Vec<u8>> {
+        if self.stream.is_empty() || self.stream_paused {
+            return None;
+        }
+
+        let mut payload = Vec::new();
+        for sensor in self.stream.clone() {
+            payload.push(sensor.into());
+            payload.extend(self.sensor_bytes(sensor));
+        }
+
+        let mut frame = vec![19, payload.len() as u8];
+        frame.extend(payload);
+
+        let sum = frame.iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+        frame.push(0u8.wrapping_sub(sum));
+
+        Some(frame)
+    }
+}
+
+/// An emulation of a Roomba's Open Interface.
+/// Cloning it gives another view of the same robot, which is useful for checking on it while it's
+/// being driven.
+#[derive(Clone)]
+pub struct VirtualRoomba {
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for VirtualRoomba {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VirtualRoomba {
+    /// Create a virtual Roomba. Like a real one, it starts in the off mode and needs a start command.
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State::new())),
+        }
+    }
+
+    /// Create a virtual Roomba and start serving it in the background.
+    /// The returned stream acts as the serial port to the robot.
+    pub fn spawn() -> (Self, DuplexStream) {
+        let roomba = Self::new();
+        let (local, remote) = tokio::io::duplex(4096);
+
+        let server = roomba.clone();
+        tokio::spawn(async move { server.serve(remote).await });
+
+        (roomba, local)
+    }
+
+    /// Act as the robot on the other end of `stream` until it closes.
+    pub async fn serve<S: AsyncRead + AsyncWrite>(&self, stream: S) -> io::Result<()> {
+        let (read_stream, mut write_stream) = tokio::io::split(stream);
+        let mut read_stream = BufReader::new(read_stream);
+
+        let mut stream_timer = interval(STREAM_PERIOD);
+        stream_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                opcode = read_stream.read_u8() => {
+                    let opcode = match opcode {
+                        Ok(opcode) => opcode,
+                        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                        Err(error) => return Err(error),
+                    };
+
+                    let response = self.execute(opcode, &mut read_stream).await?;
+                    write_stream.write_all(&response).await?;
+                    write_stream.flush().await?;
+                }
+                _ = stream_timer.tick() => {
+                    let frame = {
+                        let mut state = self.state();
+                        state.advance_wheels();
+                        state.stream_frame()
+                    };
+
+                    if let Some(frame) = frame {
+                        write_stream.write_all(&frame).await?;
+                        write_stream.flush().await?;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Run a single command, returning anything that should be sent back.
+    async fn execute<R: AsyncRead + std::marker::Unpin>(
+        &self,
+        opcode: u8,
+        read_stream: &mut R,
+    ) -> io::Result<Vec<u8>> {
+        // Arguments must be read even if the command is going to be ignored, so that they aren't
+        // mistaken for opcodes.
+        let arguments = read_arguments(opcode, read_stream).await?;
+
+        let mut state = self.state();
+        state.advance_wheels();
+
+        // The only things the Roomba listens to while off are start and reset.
+        if state.mode == OIMode::Off && !matches!(opcode, 7 | 128) {
+            return Ok(Vec::new());
+        }
+
+        let mut response = Vec::new();
+
+        match opcode {
+            // Reset.
+            7 => {
+                *state = State {
+                    sensors: std::mem::take(&mut state.sensors),
+                    ..State::new()
+                };
+                response.extend_from_slice(BOOT_MESSAGE);
+            }
+            // Start.
+            128 => state.mode = OIMode::Passive,
+            // Safe.
+            131 => {
+                state.mode = OIMode::Safe;
+                state.behavior = None;
+            }
+            // Full.
+            132 => {
+                state.mode = OIMode::Full;
+                state.behavior = None;
+            }
+            // Stop.
+            173 => {
+                state.mode = OIMode::Off;
+                state.stream.clear();
+                state.set_drive(0, 0);
+                state.motor_pwm = (0, 0, 0);
+            }
+            134 => state.start_behavior(Behavior::Spot),
+            135 => state.start_behavior(Behavior::Clean),
+            143 => state.start_behavior(Behavior::SeekDock),
+            // Drive.
+            137 if state.in_control() => {
+                let velocity = i16::from_be_bytes([arguments[0], arguments[1]]);
+                let radius = i16::from_be_bytes([arguments[2], arguments[3]]);
+
+                state.set_drive(velocity, radius);
+            }
+            // Drive direct.
+            145 if state.in_control() => {
+                state.right_velocity = i16::from_be_bytes([arguments[0], arguments[1]]);
+                state.left_velocity = i16::from_be_bytes([arguments[2], arguments[3]]);
+            }
+            // Motors.
+            138 if state.in_control() => {
+                let motors = arguments[0];
+                let speed = |on_bit: u8, reverse_bit: u8| match (
+                    motors & on_bit != 0,
+                    motors & reverse_bit != 0,
+                ) {
+                    (false, _) => 0,
+                    (true, false) => FULL_SPEED,
+                    (true, true) => -FULL_SPEED,
+                };
+
+                state.motor_pwm = (
+                    speed(0x04, 0x10),
+                    speed(0x01, 0x08),
+                    if motors & 0x02 != 0 { FULL_SPEED } else { 0 },
+                );
+            }
+            // PWM motors.
+            144 if state.in_control() => {
+                state.motor_pwm = (arguments[0] as i8, arguments[1] as i8, arguments[2] as i8);
+            }
+            // LEDs.
+            139 if state.in_control() => {
+                state.leds = LedState {
+                    check_robot: arguments[0] & 0x08 != 0,
+                    dock: arguments[0] & 0x04 != 0,
+                    spot: arguments[0] & 0x02 != 0,
+                    debris: arguments[0] & 0x01 != 0,
+                    power_color: arguments[1],
+                    power_intensity: arguments[2],
+                };
+            }
+            // Digit LEDs ASCII.
+            164 if state.in_control() => {
+                state.display.copy_from_slice(&arguments);
+            }
+            // Digit LEDs raw.
+            163 if state.in_control() => {
+                state.digit_segments.copy_from_slice(&arguments);
+            }
+            // Scheduling LEDs.
+            162 if state.in_control() => {
+                state.scheduling_leds.copy_from_slice(&arguments);
+            }
+            // Set day and time.
+            168 => state.clock.copy_from_slice(&arguments),
+            // Schedule.
+            167 => state.schedule.copy_from_slice(&arguments),
+            // Song.
+            140 => {
+                let song_number = arguments[0] as usize;
+
+                if let Some(song) = state.songs.get_mut(song_number) {
+                    *song = arguments[2..]
+                        .chunks_exact(2)
+                        .map(|note| (note[0], note[1]))
+                        .collect();
+                }
+            }
+            // Play.
+            141 if state.in_control() => {
+                let song_number = arguments[0];
+
+                if let Some(song) = state.songs.get(song_number as usize) {
+                    let length: u64 = song.iter().map(|(_pitch, duration)| *duration as u64).sum();
+
+                    state.song_number = song_number;
+                    state.song_ends = Instant::now() + Duration::from_millis(length * 1000 / 64);
+                }
+            }
+            // Query.
+            142 => {
+                if let Ok(sensor) = Sensor::try_from(arguments[0]) {
+                    response = state.query_response(&[sensor]);
+                }
+            }
+            // Query list.
+            149 => {
+                let sensors = parse_sensor_list(&arguments[1..]);
+                response = state.query_response(&sensors);
+            }
+            // Stream.
+            148 => {
+                state.stream = parse_sensor_list(&arguments[1..]);
+                state.stream_paused = false;
+            }
+            // Pause/resume stream.
+            150 => state.stream_paused = arguments[0] == 0,
+            _ => {}
+        }
+
+        Ok(response)
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .expect("Virtual Roomba state was poisoned")
+    }
+
+    pub fn mode(&self) -> OIMode {
+        self.state().mode
+    }
+
+    /// The last thing the Roomba was told to do on its own, if it hasn't been taken over since.
+    pub fn behavior(&self) -> Option<Behavior> {
+        self.state().behavior
+    }
+
+    /// The speed of the left and right wheels, in mm/s.
+    pub fn wheel_velocities(&self) -> (i16, i16) {
+        let state = self.state();
+
+        (state.left_velocity, state.right_velocity)
+    }
+
+    /// The duty cycles of the main brush, side brush and vacuum, from -127 to 127.
+    /// Negative values run in reverse.
+    pub fn motor_pwm(&self) -> (i8, i8, i8) {
+        self.state().motor_pwm
+    }
+
+    pub fn leds(&self) -> LedState {
+        self.state().leds
+    }
+
+    /// The raw bytes sent to the seven segment display.
+    pub fn display(&self) -> [u8; 4] {
+        self.state().display
+    }
+
+    /// The raw segments of each digit, from left to right.
+    pub fn digit_segments(&self) -> [u8; 4] {
+        self.state().digit_segments
+    }
+
+    /// The raw scheduling LEDs: a bit for each day from Sunday, then a bit for each of the colon,
+    /// PM, AM, clock and schedule LEDs.
+    pub fn scheduling_leds(&self) -> [u8; 2] {
+        self.state().scheduling_leds
+    }
+
+    /// The day of the week (from Sunday), hour and minute the clock was last set to.
+    pub fn clock(&self) -> [u8; 3] {
+        self.state().clock
+    }
+
+    /// The raw schedule: a bit for each day that has a time, then the hour and minute for each
+    /// day from Sunday.
+    pub fn schedule(&self) -> [u8; 15] {
+        self.state().schedule
+    }
+
+    /// The notes of a song as pitch and duration pairs, or `None` if it was never defined.
+    pub fn song(&self, song_number: u8) -> Option<Vec<(u8, u8)>> {
+        self.state()
+            .songs
+            .get(song_number as usize)
+            .filter(|song| !song.is_empty())
+            .cloned()
+    }
+
+    /// The sensors being streamed, or nothing if there is no stream.
+    pub fn streamed_sensors(&self) -> Vec<Sensor> {
+        self.state().stream.clone()
+    }
+
+    pub fn stream_paused(&self) -> bool {
+        self.state().stream_paused
+    }
+
+    /// Set what the robot reports for a sensor.
+    /// The OI mode, song and requested drive sensors are ignored, since those follow the robot's
+    /// actual state. The encoder counts, distance and angle are set, and then follow the wheels as
+    /// they turn.
+    /// Safe mode protects the robot, so wheel drops and cliffs set here will stop it and drop it
+    /// back to Passive mode, just like the real thing.
+    pub fn set_sensor(&self, data: SensorData) {
+        let (sensor, bytes) = encode_sensor_data(&data);
+        let mut state = self.state();
+        state.advance_wheels();
+
+        match data {
+            SensorData::LeftEncoderCounts(counts) => state.left_encoder = counts as f64,
+            SensorData::RightEncoderCounts(counts) => state.right_encoder = counts as f64,
+            SensorData::Distance(distance) => state.distance = distance as f64,
+            SensorData::Angle(angle) => state.angle = angle as f64,
+            _ => {}
+        }
+
+        let unsafe_condition = match data {
+            SensorData::BumpersAndWheelDrops {
+                wheel_drop_left,
+                wheel_drop_right,
+                ..
+            } => wheel_drop_left || wheel_drop_right,
+            SensorData::CliffLeft(cliff)
+            | SensorData::CliffFrontLeft(cliff)
+            | SensorData::CliffFrontRight(cliff)
+            | SensorData::CliffRight(cliff) => cliff,
+            _ => false,
+        };
+
+        if unsafe_condition && state.mode == OIMode::Safe {
+            state.mode = OIMode::Passive;
+            state.set_drive(0, 0);
+        }
+
+        state.sensors.insert(sensor, bytes);
+    }
+}
+
+/// Read the arguments that follow an opcode. For commands with a variable length, this includes
+/// the bytes that give the length.
+async fn read_arguments<R: AsyncRead + std::marker::Unpin>(
+    opcode: u8,
+    read_stream: &mut R,
+) -> io::Result<Vec<u8>> {
+    let (header_length, item_size) = match opcode {
+        137 | 145 | 163 | 164 => return read_bytes(read_stream, 4).await,
+        139 | 144 | 168 => return read_bytes(read_stream, 3).await,
+        162 => return read_bytes(read_stream, 2).await,
+        167 => return read_bytes(read_stream, 15).await,
+        138 | 141 | 142 | 150 => return read_bytes(read_stream, 1).await,
+        // Song number and note count, then a pitch and duration for each note.
+        140 => (2, 2),
+        // Sensor count, then the sensor IDs.
+        148 | 149 => (1, 1),
+        _ => return Ok(Vec::new()),
+    };
+
+    let mut arguments = read_bytes(read_stream, header_length).await?;
+    let count = arguments[header_length - 1] as usize;
+    arguments.extend(read_bytes(read_stream, count * item_size).await?);
+
+    Ok(arguments)
+}
+
+async fn read_bytes<R: AsyncRead + std::marker::Unpin>(
+    read_stream: &mut R,
+    length: usize,
+) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![0u8; length];
+    read_stream.read_exact(&mut bytes).await?;
+
+    Ok(bytes)
+}
+
+/// Unknown sensor IDs are skipped.
+fn parse_sensor_list(sensor_ids: &[u8]) -> Vec<Sensor> {
+    sensor_ids
+        .iter()
+        .filter_map(|sensor_id| Sensor::try_from(*sensor_id).ok())
+        .collect()
+}
+
+/// Turn sensor data back into the ID and bytes the Roomba would send for it.
+pub(crate) fn encode_sensor_data(data: &SensorData) -> (Sensor, Vec<u8>) {
+    fn flag(value: bool, bit: u8) -> u8 {
+        if value {
+            bit
+        } else {
+            0
+        }
+    }
+
+    match data {
+        SensorData::BumpersAndWheelDrops {
+            wheel_drop_left,
+            wheel_drop_right,
+            bumper_left,
+            bumper_right,
+        } => (
+            Sensor::BumpersAndWheelDrops,
+            vec![
+                flag(*wheel_drop_left, 0x08)
+                    | flag(*wheel_drop_right, 0x04)
+                    | flag(*bumper_left, 0x02)
+                    | flag(*bumper_right, 0x01),
+            ],
+        ),
+        SensorData::Wall(value) => (Sensor::Wall, vec![*value as u8]),
+        SensorData::CliffLeft(value) => (Sensor::CliffLeft, vec![*value as u8]),
+        SensorData::CliffFrontLeft(value) => (Sensor::CliffFrontLeft, vec![*value as u8]),
+        SensorData::CliffFrontRight(value) => (Sensor::CliffFrontRight, vec![*value as u8]),
+        SensorData::CliffRight(value) => (Sensor::CliffRight, vec![*value as u8]),
+        SensorData::VirtualWall(value) => (Sensor::VirtualWall, vec![*value as u8]),
+        SensorData::WheelOvercurrents {
+            left_wheel,
+            right_wheel,
+            main_brush,
+            side_brush,
+        } => (
+            Sensor::WheelOvercurrents,
+            vec![
+                flag(*left_wheel, 0x10)
+                    | flag(*right_wheel, 0x08)
+                    | flag(*main_brush, 0x04)
+                    | flag(*side_brush, 0x01),
+            ],
+        ),
+        SensorData::DirtDetect(value) => (Sensor::DirtDetect, vec![*value]),
+        SensorData::InfraredCharacterOmni(value) => {
+            (Sensor::InfraredCharacterOmni, vec![(*value).into()])
+        }
+        SensorData::InfraredCharacterLeft(value) => {
+            (Sensor::InfraredCharacterLeft, vec![(*value).into()])
+        }
+        SensorData::InfraredCharacterRight(value) => {
+            (Sensor::InfraredCharacterRight, vec![(*value).into()])
+        }
+        SensorData::Buttons {
+            clock,
+            schedule,
+            day,
+            hour,
+            minute,
+            dock,
+            spot,
+            clean,
+        } => (
+            Sensor::Buttons,
+            vec![
+                flag(*clock, 0x80)
+                    | flag(*schedule, 0x40)
+                    | flag(*day, 0x20)
+                    | flag(*hour, 0x10)
+                    | flag(*minute, 0x08)
+                    | flag(*dock, 0x04)
+                    | flag(*spot, 0x02)
+                    | flag(*clean, 0x01),
+            ],
+        ),
+        SensorData::Distance(value) => (Sensor::Distance, value.to_be_bytes().to_vec()),
+        SensorData::Angle(value) => (Sensor::Angle, value.to_be_bytes().to_vec()),
+        SensorData::ChargingState(value) => (Sensor::ChargingState, vec![(*value).into()]),
+        SensorData::Voltage(value) => (Sensor::Voltage, value.to_be_bytes().to_vec()),
+        SensorData::Current(value) => (Sensor::Current, value.to_be_bytes().to_vec()),
+        SensorData::BatteryTemperature(value) => (Sensor::BatteryTemperature, vec![*value as u8]),
+        SensorData::BatteryCharge(value) => (Sensor::BatteryCharge, value.to_be_bytes().to_vec()),
+        SensorData::BatteryCapacity(value) => {
+            (Sensor::BatteryCapacity, value.to_be_bytes().to_vec())
+        }
+        SensorData::WallSignal(value) => (Sensor::WallSignal, value.to_be_bytes().to_vec()),
+        SensorData::CliffLeftSignal(value) => {
+            (Sensor::CliffLeftSignal, value.to_be_bytes().to_vec())
+        }
+        SensorData::CliffFrontLeftSignal(value) => {
+            (Sensor::CliffFrontLeftSignal, value.to_be_bytes().to_vec())
+        }
+        SensorData::CliffFrontRightSignal(value) => {
+            (Sensor::CliffFrontRightSignal, value.to_be_bytes().to_vec())
+        }
+        SensorData::CliffRightSignal(value) => {
+            (Sensor::CliffRightSignal, value.to_be_bytes().to_vec())
+        }
+        SensorData::ChargingSourcesAvailable {
+            home_base,
+            internal_charger,
+        } => (
+            Sensor::ChargingSourcesAvailable,
+            vec![flag(*home_base, 0x02) | flag(*internal_charger, 0x01)],
+        ),
+        SensorData::OIMode(value) => (Sensor::OIMode, vec![(*value).into()]),
+        SensorData::SongNumber(value) => (Sensor::SongNumber, vec![*value]),
+        SensorData::SongPlaying(value) => (Sensor::SongPlaying, vec![*value as u8]),
+        SensorData::NumberOfStreamPackets(value) => (Sensor::NumberOfStreamPackets, vec![*value]),
+        SensorData::RequestedVelocity(value) => {
+            (Sensor::RequestedVelocity, value.to_be_bytes().to_vec())
+        }
+        SensorData::RequestedRadius(value) => {
+            (Sensor::RequestedRadius, value.to_be_bytes().to_vec())
+        }
+        SensorData::RequestedRightVelocity(value) => {
+            (Sensor::RequestedRightVelocity, value.to_be_bytes().to_vec())
+        }
+        SensorData::RequestedLeftVelocity(value) => {
+            (Sensor::RequestedLeftVelocity, value.to_be_bytes().to_vec())
+        }
+        SensorData::LeftEncoderCounts(value) => {
+            (Sensor::LeftEncoderCounts, value.to_be_bytes().to_vec())
+        }
+        SensorData::RightEncoderCounts(value) => {
+            (Sensor::RightEncoderCounts, value.to_be_bytes().to_vec())
+        }
+        SensorData::LightBumper {
+            right,
+            front_right,
+            center_right,
+            center_left,
+            front_left,
+            left,
+        } => (
+            Sensor::LightBumper,
+            vec![
+                flag(*right, 0x20)
+                    | flag(*front_right, 0x10)
+                    | flag(*center_right, 0x08)
+                    | flag(*center_left, 0x04)
+                    | flag(*front_left, 0x02)
+                    | flag(*left, 0x01),
+            ],
+        ),
+        SensorData::LightBumpLeftSignal(value) => {
+            (Sensor::LightBumpLeftSignal, value.to_be_bytes().to_vec())
+        }
+        SensorData::LightBumpFrontLeftSignal(value) => (
+            Sensor::LightBumpFrontLeftSignal,
+            value.to_be_bytes().to_vec(),
+        ),
+        SensorData::LightBumpCenterLeftSignal(value) => (
+            Sensor::LightBumpCenterLeftSignal,
+            value.to_be_bytes().to_vec(),
+        ),
+        SensorData::LightBumpCenterRightSignal(value) => (
+            Sensor::LightBumpCenterRightSignal,
+            value.to_be_bytes().to_vec(),
+        ),
+        SensorData::LightBumpFrontRightSignal(value) => (
+            Sensor::LightBumpFrontRightSignal,
+            value.to_be_bytes().to_vec(),
+        ),
+        SensorData::LightBumpRightSignal(value) => {
+            (Sensor::LightBumpRightSignal, value.to_be_bytes().to_vec())
+        }
+        SensorData::LeftMotorCurrent(value) => {
+            (Sensor::LeftMotorCurrent, value.to_be_bytes().to_vec())
+        }
+        SensorData::RightMotorCurrent(value) => {
+            (Sensor::RightMotorCurrent, value.to_be_bytes().to_vec())
+        }
+        SensorData::MainBrushMotorCurrent(value) => {
+            (Sensor::MainBrushMotorCurrent, value.to_be_bytes().to_vec())
+        }
+        SensorData::SideBrushMotorCurrent(value) => {
+            (Sensor::SideBrushMotorCurrent, value.to_be_bytes().to_vec())
+        }
+        SensorData::IsMovingForward(value) => (Sensor::IsMovingForward, vec![*value as u8]),
+    }
+}
@@ -6,7 +6,7 @@ pkgs.rosPackages.humble.buildRosPackage {
 
   buildType = "ament_cmake";
   buildInputs = [ pkgs.rosPackages.humble.ament-cmake pkgs.rosPackages.humble.rosidl-default-generators ];
-  propagatedBuildInputs = [ ];
+  propagatedBuildInputs = [ pkgs.rosPackages.humble.std-msgs pkgs.rosPackages.humble.action-msgs ];
   nativeBuildInputs = [ pkgs.rosPackages.humble.ament-cmake pkgs.rosPackages.humble.rosidl-default-generators ];
 
   meta = {
@@ -155,6 +155,8 @@
                 rosArgs = [ ];
                 params = {
                   serial_device = "\"/dev/serial/by-id/usb-FTDI_FT231X_USB_UART_DA01NM8I-if00-port0\"";
+                  # Teleop needs control of the robot to drive it.
+                  initial_mode = "\"safe\"";
                 };
               };
               # Provides joystick messages from a locally connected joystick.